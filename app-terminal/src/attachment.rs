@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use openai::types::*;
+
+static MAX_ATTACHMENT_SIZE: u64 = 20 * 1024 * 1024;
+
+/// Rough token cost of one image at the default detail level.
+static IMAGE_TOKENS: u64 = 765;
+
+fn image_mime_of(path: &Path) -> Option<&'static str> {
+	let extension = path.extension()?.to_str()?.to_lowercase();
+	match extension.as_str() {
+		"png" => Some("image/png"),
+		"jpg" | "jpeg" => Some("image/jpeg"),
+		"gif" => Some("image/gif"),
+		"webp" => Some("image/webp"),
+		_ => None
+	}
+}
+
+/// Reads an image or a text file so that it can be sent along with the next prompt.
+pub fn load_attachment(path: &Path) -> Result<Attachment, String> {
+	let metadata = std::fs::metadata(path).map_err(|err| err.to_string())?;
+	if !metadata.is_file() {
+		return Err("not a file".into());
+	}
+	if metadata.len() > MAX_ATTACHMENT_SIZE {
+		return Err(format!("larger than {} MB", MAX_ATTACHMENT_SIZE / 1024 / 1024));
+	}
+
+	let data = std::fs::read(path).map_err(|err| err.to_string())?;
+	let mime = match image_mime_of(path) {
+		Some(mime) => mime,
+		None if std::str::from_utf8(&data).is_ok() => "text/plain",
+		None => return Err("only images and text files can be attached".into())
+	};
+	let name = path.file_name().map_or(path.to_string_lossy(), |name| name.to_string_lossy()).to_string();
+
+	Ok(Attachment { name, mime: mime.into(), data })
+}
+
+pub fn is_image(attachment: &Attachment) -> bool {
+	attachment.mime.starts_with("image/")
+}
+
+/// Builds the content of a message: plain text without attachments, a list of parts otherwise.
+pub fn build_content(text: &str, attachments: &[Attachment]) -> MessageContent {
+	if attachments.is_empty() {
+		return MessageContent::Text(text.into());
+	}
+
+	let mut parts = vec![ContentPart::Text { text: text.into() }];
+	for attachment in attachments {
+		if is_image(attachment) {
+			parts.push(ContentPart::ImageUrl {
+				image_url: ImageUrl {
+					url: format!("data:{};base64,{}", attachment.mime, BASE64.encode(&attachment.data)),
+					detail: None
+				}
+			});
+		}
+		else {
+			parts.push(ContentPart::Text {
+				text: format!("File `{}`:\n```\n{}\n```", attachment.name, String::from_utf8_lossy(&attachment.data))
+			});
+		}
+	}
+	MessageContent::Parts(parts)
+}
+
+/// Estimates the token cost of a message the same way the context window is measured.
+pub fn estimate_tokens(text: &str, attachments: &[Attachment]) -> u64 {
+	let mut tokens = text.len() as u64 / 2;
+	for attachment in attachments {
+		tokens += if is_image(attachment) { IMAGE_TOKENS } else { attachment.data.len() as u64 / 2 };
+	}
+	tokens
+}
+
+/// Estimates the prompt tokens of content that is about to be sent.
+pub fn estimate_content_tokens(content: &MessageContent) -> u64 {
+	match content {
+		MessageContent::Text(text) => estimate_tokens(text, &[]),
+		MessageContent::Parts(parts) => parts
+			.iter()
+			.map(|part| match part {
+				ContentPart::Text { text } => estimate_tokens(text, &[]),
+				ContentPart::ImageUrl { .. } => IMAGE_TOKENS,
+			})
+			.sum(),
+	}
+}
+
+pub fn describe_attachment(attachment: &Attachment) -> String {
+	format!("{} ({}, {:.1} KB)", attachment.name, attachment.mime, attachment.data.len() as f64 / 1024.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+
+	use super::*;
+
+	fn test_file(name: &str, data: &[u8]) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("ai-attachment-test-{}-{}", std::process::id(), name));
+		std::fs::write(&path, data).unwrap();
+		path
+	}
+
+	#[test]
+	fn detects_images_and_text() {
+		let image = load_attachment(&test_file("image.PNG", b"\x89PNG")).unwrap();
+		assert_eq!((image.mime.as_str(), image.name.ends_with("image.PNG")), ("image/png", true));
+		assert_eq!(load_attachment(&test_file("notes.md", "héllo".as_bytes())).unwrap().mime, "text/plain");
+		assert!(load_attachment(&test_file("program.bin", b"\xff\xfe\x00")).is_err());
+	}
+
+	#[test]
+	fn refuses_files_over_the_size_limit() {
+		let path = test_file("large.txt", b"");
+		std::fs::File::options().write(true).open(&path).unwrap().set_len(MAX_ATTACHMENT_SIZE + 1).unwrap();
+		assert_eq!(load_attachment(&path).unwrap_err(), "larger than 20 MB");
+		assert!(load_attachment(&std::env::temp_dir()).is_err());
+	}
+}
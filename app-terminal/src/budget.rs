@@ -0,0 +1,244 @@
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Utc};
+use clap::{Subcommand, ValueEnum};
+
+use openai::types::*;
+use database::*;
+
+use crate::attachment::estimate_content_tokens;
+use crate::error::*;
+use crate::pricing::{cost_of, price_of};
+use crate::stats::mask_key;
+use crate::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum BudgetPeriod {
+	Day,
+	Month,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum BudgetUnit {
+	Tokens,
+	Usd,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BudgetCommand {
+	/// List budgets with the usage of their current period
+	List,
+
+	/// Set a budget for the current profile, or for one conversation
+	Set {
+		/// Tokens or US dollars allowed per period
+		limit: f64,
+
+		#[arg(long, value_enum, default_value = "day")]
+		period: BudgetPeriod,
+
+		#[arg(long, value_enum, default_value = "usd")]
+		unit: BudgetUnit,
+
+		/// Limit this conversation instead of the whole profile
+		#[arg(long, value_name = "ID")]
+		conversation: Option<u32>,
+	},
+
+	/// Remove a budget
+	Remove {
+		id: u32,
+	},
+}
+
+fn name_of<T: ValueEnum>(value: T) -> String {
+	value.to_possible_value().unwrap().get_name().to_string()
+}
+
+/// Start of the current day or month in local time.
+fn period_start(period: &str) -> DateTime<Utc> {
+	let today = Local::now().date_naive();
+	let start = match period {
+		"month" => today.with_day(1).unwrap(),
+		_ => today,
+	};
+	Local
+		.from_local_datetime(&start.and_time(NaiveTime::MIN))
+		.earliest()
+		.map_or(Utc::now(), |start| start.with_timezone(&Utc))
+}
+
+/// Tokens or US dollars spent under `budget` in its current period.
+fn spent(records: &[UsageRecord], budget: &Budget) -> f64 {
+	let start = period_start(&budget.period);
+	records
+		.iter()
+		.filter(|record| record.updateat >= start)
+		.filter(|record| match (&budget.key, budget.conversation_id) {
+			(_, Some(conversation_id)) => record.conversation_id == Some(conversation_id),
+			(Some(key), None) => &record.key == key,
+			(None, None) => false,
+		})
+		.map(|record| match &budget.unit[..] {
+			"tokens" => (record.prompt_tokens + record.completion_tokens) as f64,
+			_ => cost_of(record.model.as_deref(), record.prompt_tokens, record.completion_tokens).unwrap_or(0.0),
+		})
+		.fold(0.0, |total, amount| total + amount)
+}
+
+fn format_amount(unit: &str, amount: f64) -> String {
+	match unit {
+		"tokens" => format!("{:.0} tokens", amount),
+		_ => format!("${:.4}", amount),
+	}
+}
+
+fn describe_budget(budget: &Budget) -> String {
+	let target = match (&budget.key, budget.conversation_id) {
+		(_, Some(conversation_id)) => format!("conversation {}", conversation_id),
+		(Some(key), None) => format!("profile {}", mask_key(key)),
+		(None, None) => "nothing".into(),
+	};
+	format!("{} per {} for {}", format_amount(&budget.unit, budget.amount), budget.period, target)
+}
+
+/// Replies are expected to use this many tokens when `max_tokens` does not limit them.
+static EXPECTED_REPLY_TOKENS: u64 = 500;
+
+/// Tokens a request is expected to use, priced as `model`.
+pub struct RequestEstimate {
+	pub model: String,
+	pub prompt_tokens: u64,
+	pub completion_tokens: u64
+}
+
+impl RequestEstimate {
+	/// A chat request sending `context`, with a reply as long as `max_tokens` allows or of a usual length.
+	pub fn chat(model: &str, context: &[Message], max_tokens: Option<u32>) -> Self {
+		Self {
+			model: model.into(),
+			prompt_tokens: context.iter().map(|msg| estimate_content_tokens(&msg.content)).sum(),
+			completion_tokens: max_tokens.map_or(EXPECTED_REPLY_TOKENS, u64::from)
+		}
+	}
+
+	fn amount(&self, unit: &str) -> f64 {
+		match unit {
+			"tokens" => (self.prompt_tokens + self.completion_tokens) as f64,
+			_ => cost_of(Some(&self.model), self.prompt_tokens, self.completion_tokens).unwrap_or(0.0),
+		}
+	}
+}
+
+/// Outcome of checking a request against the budgets that apply to it.
+pub struct BudgetCheck {
+	pub warnings: Vec<String>,
+	pub refusal: Option<String>
+}
+
+/// Checks whether making `requests` stays within the budgets of profile `key` and the conversation, if any.
+/// Usage is what has been recorded this period plus the estimate of every request.
+/// Models without a known price cost nothing in USD budgets, which is warned about.
+pub fn check_budgets(mgr: &ChatManager, key: &str, conversation_id: Option<u32>, requests: &[RequestEstimate]) -> Result<BudgetCheck, MainError> {
+	let mut check = BudgetCheck { warnings: vec![], refusal: None };
+	let budgets = Database::get_applicable_budgets(&mgr.connection, key, conversation_id)?;
+	if budgets.is_empty() {
+		return Ok(check);
+	}
+	if budgets.iter().any(|budget| budget.unit == "usd") {
+		let mut unpriced: Vec<&str> = vec![];
+		for request in requests.iter().filter(|request| price_of(&request.model).is_none()) {
+			if !unpriced.contains(&request.model.as_str()) {
+				unpriced.push(&request.model);
+				check.warnings.push(format!("Budget warning: no price is known for {}, so USD budgets do not count it.", request.model));
+			}
+		}
+	}
+
+	let earliest = budgets.iter().map(|budget| period_start(&budget.period)).min();
+	let records = Database::get_usage_records_since(&mgr.connection, earliest)?;
+
+	for budget in budgets.iter() {
+		let used = spent(&records, budget);
+		let estimate: f64 = requests.iter().map(|request| request.amount(&budget.unit)).sum();
+		let projected = used + estimate;
+		let usage = format!("{} of {} used, about {} more with this message",
+			format_amount(&budget.unit, used), describe_budget(budget), format_amount(&budget.unit, estimate));
+
+		if projected > budget.amount {
+			check.refusal = Some(format!("Budget exceeded: {}.", usage));
+			break;
+		}
+		if projected >= budget.amount * mgr.budget_warn_percent as f64 / 100.0 {
+			check.warnings.push(format!("Budget warning: {}.", usage));
+		}
+	}
+	Ok(check)
+}
+
+pub fn run_budget_command(mgr: &ChatManager, command: BudgetCommand) -> Result<(), MainError> {
+	match command {
+		BudgetCommand::List => {
+			let budgets = Database::get_budgets(&mgr.connection)?;
+			if budgets.is_empty() {
+				println!("No budgets set.");
+				return Ok(());
+			}
+			let earliest = budgets.iter().map(|budget| period_start(&budget.period)).min();
+			let records = Database::get_usage_records_since(&mgr.connection, earliest)?;
+			for budget in budgets.iter() {
+				let used = spent(&records, budget);
+				println!("{}: {} ({} used, {:.0}%)",
+					budget.id, describe_budget(budget), format_amount(&budget.unit, used), used / budget.amount * 100.0);
+			}
+		},
+		BudgetCommand::Set { limit, period, unit, conversation } => {
+			if let Some(id) = conversation {
+				if !Database::conversation_exists(&mgr.connection, id)? {
+					println!("No such conversation.");
+					return Ok(());
+				}
+			}
+			let key = if conversation.is_some() { None } else { Some(mgr.api_key.as_str()) };
+			let id = Database::set_budget(&mgr.connection, key, conversation, &name_of(period), &name_of(unit), limit)?;
+			let budget = Budget { id, key: key.map(String::from), conversation_id: conversation, period: name_of(period), unit: name_of(unit), amount: limit };
+			println!("Budget {} set: {}.", id, describe_budget(&budget));
+		},
+		BudgetCommand::Remove { id } => {
+			match Database::remove_budget(&mgr.connection, id)? {
+				0 => println!("No such budget."),
+				_ => println!("Budget {} removed.", id),
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_support::*;
+
+	#[test]
+	fn estimates_include_the_reply() {
+		let context = [Message { role: MessageRole::User, content: "x".repeat(200).into() }];
+		let expected = RequestEstimate::chat("gpt-4", &context, None);
+		assert_eq!((expected.prompt_tokens, expected.completion_tokens), (100, EXPECTED_REPLY_TOKENS));
+
+		let limited = RequestEstimate::chat("gpt-4", &context, Some(1000));
+		assert_eq!(limited.amount("tokens"), 1100.0);
+		assert!((limited.amount("usd") - (0.1 * 0.03 + 1.0 * 0.06)).abs() < 1e-9);
+	}
+
+	#[test]
+	fn warns_about_models_without_a_price_under_usd_budgets() {
+		let mgr = test_manager("http://localhost/v1");
+		let estimate = |model: &str| RequestEstimate { model: model.into(), prompt_tokens: 10, completion_tokens: 10 };
+		let requests = [estimate("local-llama"), estimate("gpt-4"), estimate("local-llama"), estimate("text-embedding-3-small")];
+
+		Database::set_budget(&mgr.connection, Some(&mgr.api_key), None, "day", "tokens", 1000.0).unwrap();
+		assert!(check_budgets(&mgr, &mgr.api_key, None, &requests).unwrap().warnings.is_empty());
+		Database::set_budget(&mgr.connection, Some(&mgr.api_key), None, "day", "usd", 10.0).unwrap();
+		assert_eq!(check_budgets(&mgr, &mgr.api_key, None, &requests).unwrap().warnings, [
+			"Budget warning: no price is known for local-llama, so USD budgets do not count it."
+		]);
+	}
+}
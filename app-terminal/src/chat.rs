@@ -0,0 +1,195 @@
+use serde_json::json;
+
+use openai::prelude::*;
+use database::*;
+
+use crate::attachment::*;
+use crate::budget::check_budgets;
+use crate::context::{build_context, estimate_requests};
+use crate::error::*;
+use crate::include::{include_files, Inclusion};
+use crate::shell::command_block;
+use crate::settings::restore_settings;
+use crate::types::*;
+
+/// Starts a session on conversation `conversation_id` with its messages and stored settings.
+/// Returns the session and notes about its settings for the user.
+pub fn open_session(mgr: &mut ChatManager, conversation_id: u32) -> Result<(ChatSession, Vec<String>), MainError> {
+	let history = Database::get_all_messages_in_conversation(&mgr.connection, conversation_id)?;
+	let restored = restore_settings(mgr, conversation_id)?;
+	let session = ChatSession {
+		conversation_id,
+		history,
+		system_prompt: restored.system_prompt,
+		prompt: String::new(),
+		pending_attachments: vec![],
+		budget_override: false,
+		refused_prompt: None,
+		included_files: vec![],
+		pending_commands: vec![],
+		code_message: None
+	};
+	Ok((session, restored.notes))
+}
+
+/// A request that is ready to be sent: the context ending with the new prompt, with notes about the files
+/// included in the prompt and the budget warnings to show with the reply.
+pub struct PreparedChat {
+	pub context: Vec<Message>,
+	pub notes: Vec<String>,
+	pub budget_warnings: Vec<String>
+}
+
+pub enum Preparation {
+	Ready(PreparedChat),
+	/// The prompt would exceed a budget. It is kept so that it can be sent with /override.
+	Refused(String),
+}
+
+/// What became of a request, once it has been recorded. Replies are streamed to the caller as they arrive.
+pub enum ChatOutcome {
+	Reply,
+	Error(String),
+}
+
+/// Expands the file references in the prompt of the current session and adds the output of pending commands,
+/// checks the requests it takes against the budgets and builds the context for it. File references are left as
+/// they are without `expand_includes`, for prompts from clients that may not read local files. Budgets are
+/// checked first, as building the context may already make requests. The prompt is kept as the draft of the conversation until
+/// a reply to it is stored.
+pub async fn prepare_chat(mgr: &mut ChatManager, expand_includes: bool) -> Result<Preparation, MainError> {
+	let session = mgr.current_session.as_mut().unwrap();
+	Database::save_draft(&mgr.connection, session.conversation_id, &session.prompt)?;
+	let typed = session.prompt.clone();
+	let inclusion = match expand_includes {
+		true => include_files(&typed, mgr.include_max_size, mgr.include_max_tokens.unwrap_or(mgr.max_token / 2)),
+		false => Inclusion { text: typed.clone(), files: vec![], notes: vec![] },
+	};
+	session.prompt = inclusion.text;
+	for pending in session.pending_commands.iter() {
+		session.prompt = format!("{}\n\n{}", session.prompt.trim_end(), command_block(pending));
+	}
+	session.included_files = inclusion.files;
+	let (conversation_id, budget_override) = (session.conversation_id, session.budget_override);
+
+	let mut budget_warnings = vec![];
+	if !mgr.ignore_budget && !budget_override {
+		let check = check_budgets(mgr, &mgr.api_key, Some(conversation_id), &estimate_requests(mgr)?)?;
+		if let Some(refusal) = check.refusal {
+			let session = mgr.current_session.as_mut().unwrap();
+			session.refused_prompt = Some(typed);
+			return Ok(Preparation::Refused(refusal));
+		}
+		budget_warnings = check.warnings;
+	}
+
+	let mut context = build_context(mgr).await?;
+	let session = mgr.current_session.as_mut().unwrap();
+	context.push(Message { role: MessageRole::User, content: build_content(&session.prompt, &session.pending_attachments) });
+	session.budget_override = false;
+	session.refused_prompt = None;
+	Ok(Preparation::Ready(PreparedChat { context, notes: inclusion.notes, budget_warnings }))
+}
+
+/// Stores the prompt of the current session and the reply to it, or logs the error.
+/// Usage that the API did not report, as with some streaming APIs, is estimated.
+pub fn record_chat(mgr: &mut ChatManager, context: &[Message], response: Result<OpenAIResponse, String>, latency_ms: u64) -> Result<ChatOutcome, MainError> {
+	let session = mgr.current_session.as_mut().unwrap();
+	match response {
+		Ok(OpenAIResponse::Success(mut completion_response)) => {
+			if completion_response.usage.total_tokens == 0 {
+				let prompt_tokens: u64 = context.iter().map(|msg| estimate_content_tokens(&msg.content)).sum();
+				let completion_tokens = estimate_tokens(&completion_response.msg(), &[]);
+				completion_response.usage = TokenUsage { prompt_tokens, completion_tokens, total_tokens: prompt_tokens + completion_tokens };
+			}
+			let message_id = Database::add_client_message(&mgr.connection, session.conversation_id, &session.prompt)?;
+			Database::add_message_files(&mgr.connection, message_id, &std::mem::take(&mut session.included_files))?;
+			let runs: Vec<CommandRun> = session.pending_commands.drain(..).map(|pending| pending.run).collect();
+			Database::add_message_commands(&mgr.connection, message_id, &runs)?;
+			for (position, attachment) in session.pending_attachments.drain(..).enumerate() {
+				Database::add_attachment(&mgr.connection, message_id, position as u32, &attachment)?;
+			}
+			Database::add_server_message(&mgr.connection, session.conversation_id, &completion_response, latency_ms)?;
+			Database::delete_draft(&mgr.connection, session.conversation_id)?;
+			session.history = Database::get_all_messages_in_conversation(&mgr.connection, session.conversation_id)?;
+			session.code_message = None;
+			Ok(ChatOutcome::Reply)
+		},
+		Ok(OpenAIResponse::Failure(openai_error)) => {
+			let error_id = Database::add_error_log(&mgr.connection, &mgr.api_key, &mgr.model, Some(session.conversation_id), context, &json!(openai_error).to_string(), Some(&openai_error))?;
+			Database::add_error_attachments(&mgr.connection, error_id, &session.pending_attachments)?;
+			Database::add_error_files(&mgr.connection, error_id, &session.included_files)?;
+			Ok(ChatOutcome::Error(openai_error.error.message))
+		},
+		Err(err) => {
+			let error_id = Database::add_error_log(&mgr.connection, &mgr.api_key, &mgr.model, Some(session.conversation_id), context, &err, None)?;
+			Database::add_error_attachments(&mgr.connection, error_id, &session.pending_attachments)?;
+			Database::add_error_files(&mgr.connection, error_id, &session.included_files)?;
+			Ok(ChatOutcome::Error(err))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+	use axum::{extract::State, http::StatusCode, routing::post, Router};
+
+	use super::*;
+	use crate::context::ContextStrategy;
+	use crate::test_support::*;
+
+	async fn count_request(State(requests): State<Arc<AtomicUsize>>) -> StatusCode {
+		requests.fetch_add(1, Ordering::SeqCst);
+		StatusCode::INTERNAL_SERVER_ERROR
+	}
+
+	#[tokio::test]
+	async fn keeps_the_prompt_as_a_draft_until_it_is_answered() {
+		let mut mgr = test_manager("http://localhost/v1");
+		let id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		let (mut session, _) = open_session(&mut mgr, id).unwrap();
+		session.prompt = "unanswered question".into();
+		mgr.current_session = Some(session);
+		let Preparation::Ready(prepared) = prepare_chat(&mut mgr, true).await.unwrap() else {
+			panic!("no budget is set");
+		};
+		record_chat(&mut mgr, &prepared.context, Err("timed out".into()), 0).unwrap();
+
+		// A later session of the conversation finds the draft
+		let (session, _) = open_session(&mut mgr, id).unwrap();
+		assert_eq!(Database::get_draft(&mgr.connection, id).unwrap().as_deref(), Some("unanswered question"));
+		mgr.current_session = Some(session);
+		mgr.current_session.as_mut().unwrap().prompt = "unanswered question".into();
+		record_chat(&mut mgr, &prepared.context, Ok(OpenAIResponse::Success(reply("answer", 10, 5))), 0).unwrap();
+		assert_eq!(Database::get_draft(&mgr.connection, id).unwrap(), None);
+	}
+
+	#[tokio::test]
+	async fn refuses_before_summarizing_or_embedding() {
+		let requests = Arc::new(AtomicUsize::new(0));
+		let router = Router::new().route("/v1/*path", post(count_request)).with_state(requests.clone());
+		let mut mgr = test_manager(&serve_stub(router).await);
+		mgr.overrides.max_dialog = Some(2);
+		mgr.overrides.context_strategy = Some("summarize".into());
+		mgr.recall_k = 3;
+
+		let id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		for index in 0..6 {
+			Database::add_client_message(&mgr.connection, id, &format!("message {}", index)).unwrap();
+		}
+		Database::set_budget(&mgr.connection, Some(&mgr.api_key), None, "day", "tokens", 100.0).unwrap();
+		let (mut session, _) = open_session(&mut mgr, id).unwrap();
+		session.prompt = "hello".into();
+		mgr.current_session = Some(session);
+		assert_eq!(mgr.context_strategy, ContextStrategy::Summarize);
+
+		assert!(matches!(prepare_chat(&mut mgr, true).await.unwrap(), Preparation::Refused(_)));
+		assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+		mgr.current_session.as_mut().unwrap().budget_override = true;
+		assert!(matches!(prepare_chat(&mut mgr, true).await.unwrap(), Preparation::Ready(_)));
+		assert!(requests.load(Ordering::SeqCst) > 0);
+	}
+}
@@ -0,0 +1,312 @@
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use openai::types::*;
+
+use crate::context::role_of;
+use crate::error::*;
+use crate::types::*;
+
+/// A fenced code block of a message, with the file name suggested for it, if any.
+pub struct CodeBlock {
+	pub language: String,
+	pub content: String,
+	pub hint: Option<String>,
+	/// Whether the file name comes from the block itself, its info string or first-line comment, so that the block
+	/// can be taken as the whole file. A name in the text before the block may only say where a part of it goes.
+	pub names_file: bool
+}
+
+fn extension_of(language: &str) -> &str {
+	match language.to_lowercase().as_str() {
+		"rust" | "rs" => "rs",
+		"python" | "py" => "py",
+		"javascript" | "js" => "js",
+		"typescript" | "ts" => "ts",
+		"tsx" => "tsx",
+		"jsx" => "jsx",
+		"go" | "golang" => "go",
+		"c" => "c",
+		"cpp" | "c++" => "cpp",
+		"csharp" | "cs" | "c#" => "cs",
+		"java" => "java",
+		"kotlin" | "kt" => "kt",
+		"ruby" | "rb" => "rb",
+		"php" => "php",
+		"swift" => "swift",
+		"bash" | "sh" | "shell" | "zsh" | "console" => "sh",
+		"powershell" | "ps1" => "ps1",
+		"sql" => "sql",
+		"html" => "html",
+		"css" => "css",
+		"json" => "json",
+		"toml" => "toml",
+		"yaml" | "yml" => "yaml",
+		"xml" => "xml",
+		"markdown" | "md" => "md",
+		"dockerfile" => "Dockerfile",
+		"diff" | "patch" => "diff",
+		_ => "txt",
+	}
+}
+
+/// A word that reads like a relative file name, such as `src/main.rs` or **app.py**, without the quoting
+/// around it. Absolute paths and paths leaving the current directory are not used.
+fn file_name_in(word: &str) -> Option<String> {
+	let quoting = |c: char| "`*_'\"()[]<>,;:".contains(c);
+	let word = word.trim_matches(quoting).trim_end_matches('.').trim_end_matches(quoting);
+	let (stem, extension) = word.rsplit_once('.')?;
+	let valid_extension = (1..=5).contains(&extension.len())
+		&& extension.chars().all(|c| c.is_ascii_alphanumeric())
+		&& extension.chars().any(|c| c.is_ascii_alphabetic());
+	// Single letters around the dot are abbreviations such as "e.g." rather than file names
+	let name = stem.rsplit('/').next().unwrap_or(stem);
+	if name.is_empty() || (name.len() == 1 && extension.len() == 1) || !valid_extension || word.contains("://") {
+		return None;
+	}
+	let path = Path::new(word);
+	path.components()
+		.all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+		.then(|| word.to_string())
+}
+
+/// The file name suggested for a block: a path in its info string as in "rust src/main.rs" or
+/// "rust title=main.rs", a comment naming the file on its first line, or a file name on the line before it.
+/// Also returns whether the name was found in the block itself rather than on the line before it.
+fn hint_of(info: &str, content: &str, before: Option<&str>) -> Option<(String, bool)> {
+	let from_info = info
+		.split_whitespace()
+		.skip(1)
+		.map(|word| word.split_once('=').map_or(word, |(_, value)| value))
+		.find_map(file_name_in);
+	let from_comment = || {
+		let first = content.lines().next()?.trim();
+		let comment = ["//", "#", "--", "<!--", "/*", ";"].iter().find_map(|marker| first.strip_prefix(marker))?;
+		let comment = comment.trim().trim_end_matches("-->").trim_end_matches("*/").trim();
+		let comment = comment.strip_prefix("file:").or(comment.strip_prefix("filename:")).unwrap_or(comment).trim();
+		if comment.split_whitespace().count() != 1 {
+			return None;
+		}
+		file_name_in(comment)
+	};
+	let from_text = || before?.split_whitespace().rev().find_map(file_name_in);
+	from_info.or_else(from_comment).map(|name| (name, true)).or_else(|| from_text().map(|name| (name, false)))
+}
+
+/// Finds the fenced code blocks of a Markdown text, in order.
+pub fn extract_code_blocks(text: &str) -> Vec<CodeBlock> {
+	let mut blocks = vec![];
+	let mut previous_line: Option<&str> = None;
+	let mut lines = text.lines();
+	while let Some(line) = lines.next() {
+		let trimmed = line.trim_start();
+		let Some(marker) = trimmed.chars().next().filter(|c| *c == '`' || *c == '~') else {
+			if !trimmed.is_empty() {
+				previous_line = Some(trimmed);
+			}
+			continue
+		};
+		let fence_length = trimmed.len() - trimmed.trim_start_matches(marker).len();
+		if fence_length < 3 {
+			previous_line = Some(trimmed);
+			continue
+		}
+
+		let info = trimmed[fence_length..].trim();
+		let mut content = vec![];
+		for line in lines.by_ref() {
+			let closing = line.trim();
+			if closing.len() >= fence_length && closing.chars().all(|c| c == marker) {
+				break
+			}
+			content.push(line);
+		}
+		let content = content.join("\n");
+		let hint = hint_of(info, &content, previous_line);
+		blocks.push(CodeBlock {
+			language: info.split_whitespace().next().unwrap_or_default().to_string(),
+			names_file: hint.as_ref().is_some_and(|(_, in_block)| *in_block),
+			hint: hint.map(|(name, _)| name),
+			content
+		});
+		previous_line = None;
+	}
+	blocks
+}
+
+/// The message whose code blocks the commands refer to: the one chosen with /code, or else the latest reply.
+pub fn source_message(session: &ChatSession) -> Option<&SavedMessage> {
+	match session.code_message {
+		Some(id) => session.history.iter().find(|msg| msg.id == id),
+		None => session.history.iter().rev().find(|msg| matches!(role_of(msg), MessageRole::Assistant)),
+	}
+}
+
+fn describe_block(index: usize, block: &CodeBlock) -> String {
+	let language = if block.language.is_empty() { "plain" } else { block.language.as_str() };
+	let hint = block.hint.as_ref().map_or(String::new(), |hint| format!(", {}", hint));
+	let first_line = block.content.lines().find(|line| !line.trim().is_empty()).unwrap_or_default().trim();
+	format!("[{}] {}, {} line(s){}: {}", index + 1, language, block.content.lines().count(), hint, first_line)
+}
+
+/// Lists the code blocks of message `argument`, or of the latest reply, and makes it the message that /save and
+/// /copy refer to.
+pub fn list_code_blocks(mgr: &mut ChatManager, argument: &str) -> Result<(), MainError> {
+	let session = mgr.current_session.as_mut().unwrap();
+	if !argument.is_empty() {
+		let id = argument.trim_start_matches('#').parse::<u32>().ok();
+		let Some(msg) = session.history.iter().find(|msg| Some(msg.id) == id) else {
+			println!("No such message in this conversation. Message IDs are shown when a conversation is resumed.");
+			return Ok(());
+		};
+		session.code_message = Some(msg.id);
+	}
+	let Some(msg) = source_message(session) else {
+		println!("There is no reply in this conversation yet.");
+		return Ok(());
+	};
+
+	let blocks = extract_code_blocks(&msg.content);
+	if blocks.is_empty() {
+		println!("Message {} has no code blocks.", msg.id);
+	}
+	for (index, block) in blocks.iter().enumerate() {
+		println!("{}", describe_block(index, block));
+	}
+	Ok(())
+}
+
+/// The code block numbered `number`, counting from 1, of the message the commands refer to.
+fn find_block(session: &ChatSession, number: &str) -> Option<CodeBlock> {
+	let Some(msg) = source_message(session) else {
+		println!("There is no reply in this conversation yet.");
+		return None;
+	};
+	let mut blocks = extract_code_blocks(&msg.content);
+	match number.parse::<usize>() {
+		Ok(number) if (1..=blocks.len()).contains(&number) => Some(blocks.swap_remove(number - 1)),
+		_ => {
+			println!("Message {} has {} code block(s). Type /code to list them.", msg.id, blocks.len());
+			None
+		}
+	}
+}
+
+fn write_block(block: &CodeBlock, path: &Path, force: bool) -> Result<bool, MainError> {
+	if path.exists() && !force {
+		println!("{} already exists. Add --force to overwrite it.", path.display());
+		return Ok(false);
+	}
+	if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+		std::fs::create_dir_all(dir)?;
+	}
+	std::fs::write(path, format!("{}\n", block.content))?;
+	Ok(true)
+}
+
+/// Saves one code block to a file, or with `all`, every block to a directory under the file names suggested
+/// in the reply, or else `block-<n>` with an extension for its language. Existing files are kept unless
+/// `--force` is given.
+pub fn save_code_blocks(mgr: &ChatManager, argument: &str) -> Result<(), MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let mut arguments: Vec<&str> = argument.split_whitespace().collect();
+	let force = arguments.contains(&"--force");
+	arguments.retain(|argument| *argument != "--force");
+	let [number, path] = arguments[..] else {
+		println!("Usage: /save <n> <path> [--force] or /save all <directory> [--force]");
+		return Ok(());
+	};
+
+	if number != "all" {
+		if let Some(block) = find_block(session, number) {
+			if write_block(&block, Path::new(path), force)? {
+				println!("Saved code block {} to {}.", number, path);
+			}
+		}
+		return Ok(());
+	}
+
+	let Some(msg) = source_message(session) else {
+		println!("There is no reply in this conversation yet.");
+		return Ok(());
+	};
+	let blocks = extract_code_blocks(&msg.content);
+	if blocks.is_empty() {
+		println!("Message {} has no code blocks.", msg.id);
+	}
+	let mut used: Vec<PathBuf> = vec![];
+	for (index, block) in blocks.iter().enumerate() {
+		let name = block.hint.clone().unwrap_or_else(|| format!("block-{}.{}", index + 1, extension_of(&block.language)));
+		let mut target = Path::new(path).join(&name);
+		let mut copy = 1;
+		while used.contains(&target) {
+			copy += 1;
+			let stem = Path::new(&name).file_stem().unwrap_or_default().to_string_lossy().to_string();
+			let file_name = match Path::new(&name).extension() {
+				Some(extension) => format!("{}-{}.{}", stem, copy, extension.to_string_lossy()),
+				None => format!("{}-{}", stem, copy),
+			};
+			target = Path::new(path).join(&name).with_file_name(file_name);
+		}
+		if write_block(block, &target, force)? {
+			println!("Saved code block {} to {}.", index + 1, target.display());
+		}
+		used.push(target);
+	}
+	Ok(())
+}
+
+/// Copies a code block to the clipboard with the OSC 52 escape sequence, which the terminal handles, so that it
+/// also works over SSH. Inside tmux the sequence is passed through to the outer terminal.
+pub fn copy_code_block(mgr: &ChatManager, argument: &str) -> Result<(), MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	if argument.is_empty() {
+		println!("Usage: /copy <n>");
+		return Ok(());
+	}
+	let Some(block) = find_block(session, argument) else {
+		return Ok(());
+	};
+
+	let sequence = format!("\x1b]52;c;{}\x07", BASE64.encode(&block.content));
+	let sequence = match std::env::var_os("TMUX") {
+		Some(_) => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+		None => sequence,
+	};
+	let mut stdout = std::io::stdout();
+	stdout.write_all(sequence.as_bytes())?;
+	stdout.flush()?;
+	println!("Copied code block {} ({} bytes) to the clipboard, if your terminal allows it.", argument, block.content.len());
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn finds_blocks_and_their_file_names() {
+		let text = "Update src/lib.rs:\n\n```rust\nfn a() {}\n```\n\n```rust src/main.rs\nfn main() {}\n```\n\n````python\n# tools/run.py\n```\n````\n\n~~~\nplain\n~~~\n";
+		let blocks = extract_code_blocks(text);
+		let found: Vec<(&str, &str, Option<&str>, bool)> = blocks.iter()
+			.map(|block| (block.language.as_str(), block.content.as_str(), block.hint.as_deref(), block.names_file))
+			.collect();
+		assert_eq!(found, [
+			("rust", "fn a() {}", Some("src/lib.rs"), false),
+			("rust", "fn main() {}", Some("src/main.rs"), true),
+			("python", "# tools/run.py\n```", Some("tools/run.py"), true),
+			("", "plain", None, false),
+		]);
+	}
+
+	#[test]
+	fn ignores_names_that_are_not_relative_files() {
+		assert_eq!(hint_of("rust title=main.rs", "", None), Some(("main.rs".into(), true)));
+		assert_eq!(hint_of("rust", "// a comment about lib.rs", None), None);
+		assert_eq!(hint_of("rust", "", Some("As noted, e.g. in /etc/passwd or ../up.rs:")), None);
+		assert_eq!(hint_of("rust", "", Some("See https://example.com/index.html")), None);
+		assert_eq!(hint_of("rust", "", Some("Put this in **app.py**.")), Some(("app.py".into(), false)));
+	}
+}
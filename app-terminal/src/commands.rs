@@ -0,0 +1,201 @@
+use std::path::PathBuf;
+
+use database::*;
+
+use crate::attachment::*;
+use crate::codeblock::*;
+use crate::compose::compose;
+use crate::context::speaker_of;
+use crate::error::*;
+use crate::include::print_included_files;
+use crate::input::LineReader;
+use crate::patch::apply_patches;
+use crate::persona::*;
+use crate::revision::*;
+use crate::types::*;
+
+pub static COMMANDS: &[(&str, &str)] = &[
+	("/help", "Show this list"),
+	("/attach [path]", "Attach an image or text file to the next message, or list pending attachments"),
+	("/detach", "Discard pending attachments and command outputs"),
+	("/t <name> [key=value...]", "Send a prompt template with its placeholders filled in"),
+	("/system [prompt|clear]", "Show, replace or remove the system prompt of this conversation"),
+	("/pin [id]", "Always include a message in the context, by default the latest reply"),
+	("/unpin <id>", "Stop always including a message"),
+	("/pins", "List pinned messages"),
+	("/edit [id]", "Write the next message in $EDITOR, or edit a message keeping the previous content as a revision"),
+	("/quote <id>", "Write the next message in $EDITOR, starting with a quote of a message"),
+	("/revisions <id>", "Show the changes made to an edited message"),
+	("/code [id]", "List the code blocks of the latest reply or of a message"),
+	("/save <n|all> <path>", "Save a code block to a file, or all of them to a directory, keeping existing files unless --force is given"),
+	("/copy <n>", "Copy a code block to the clipboard through the terminal"),
+	("/apply [id]", "Preview the diffs and files of the latest reply or of a message and apply them to the working tree"),
+	("/files", "List the files included in this conversation with @path and whether they have changed"),
+	("/override", "Send the message refused for exceeding a budget, or the next one, anyway"),
+];
+
+fn print_help() {
+	for (usage, description) in COMMANDS {
+		println!("{:<24} {}", usage, description);
+	}
+}
+
+fn attach(session: &mut ChatSession, argument: &str) {
+	if argument.is_empty() {
+		if session.pending_attachments.is_empty() {
+			println!("No pending attachments.");
+		}
+		for attachment in session.pending_attachments.iter() {
+			println!("Pending: {}", describe_attachment(attachment));
+		}
+		return;
+	}
+
+	let path = PathBuf::from(argument.trim_matches(|c| c == '"' || c == '\''));
+	match load_attachment(&path) {
+		Ok(attachment) => {
+			println!("Attached {}. It will be sent with your next message.", describe_attachment(&attachment));
+			session.pending_attachments.push(attachment);
+		},
+		Err(err) => println!("Cannot attach {}: {}", path.display(), err),
+	}
+}
+
+fn expand_template(mgr: &ChatManager, argument: &str) -> Option<String> {
+	let arguments = split_arguments(argument);
+	let Some((name, pairs)) = arguments.split_first() else {
+		println!("Usage: /t <name> [key=value...]");
+		return None;
+	};
+	let persona = match load_persona(&mgr.persona_dir, name) {
+		Ok(persona) => persona,
+		Err(err) => {
+			println!("{}", err);
+			return None;
+		}
+	};
+	match parse_vars(pairs).and_then(|vars| render(&persona.prompt, &vars)) {
+		Ok(expanded) => Some(expanded),
+		Err(err) => {
+			println!("Cannot expand template {}: {}", name, err);
+			None
+		}
+	}
+}
+
+fn system(mgr: &mut ChatManager, argument: &str) -> Result<(), MainError> {
+	let session = mgr.current_session.as_mut().unwrap();
+	if argument.is_empty() {
+		match &session.system_prompt {
+			Some(system_prompt) => println!("System: {}", system_prompt.trim()),
+			None => println!("This conversation has no system prompt."),
+		}
+		return Ok(());
+	}
+
+	session.system_prompt = if argument == "clear" { None } else { Some(argument.into()) };
+	Database::set_system_prompt(&mgr.connection, session.conversation_id, session.system_prompt.as_deref())?;
+	match &session.system_prompt {
+		Some(_) => println!("System prompt updated."),
+		None => println!("System prompt removed."),
+	}
+	Ok(())
+}
+
+fn snippet_of(content: &str) -> String {
+	let flattened = content.split_whitespace().collect::<Vec<&str>>().join(" ");
+	if flattened.chars().count() > 60 {
+		format!("{}...", flattened.chars().take(57).collect::<String>())
+	}
+	else {
+		flattened
+	}
+}
+
+fn pin(mgr: &ChatManager, argument: &str, pinned: bool) -> Result<(), MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let message_id = if argument.is_empty() && pinned {
+		session.history.last().map(|msg| msg.id)
+	}
+	else {
+		argument.trim_start_matches('#').parse::<u32>().ok()
+	};
+	let Some(msg) = message_id.and_then(|id| session.history.iter().find(|msg| msg.id == id)) else {
+		println!("No such message in this conversation. Message IDs are shown when a conversation is resumed and by /pins.");
+		return Ok(());
+	};
+
+	if pinned {
+		match Database::add_pin(&mgr.connection, session.conversation_id, msg.id)? {
+			0 => println!("Message {} is already pinned.", msg.id),
+			_ => println!("Pinned message {}: {}", msg.id, snippet_of(&msg.content)),
+		}
+	}
+	else {
+		match Database::remove_pin(&mgr.connection, session.conversation_id, msg.id)? {
+			0 => println!("Message {} is not pinned.", msg.id),
+			_ => println!("Unpinned message {}.", msg.id),
+		}
+	}
+	Ok(())
+}
+
+fn print_pins(mgr: &ChatManager) -> Result<(), MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let pins = Database::get_pinned_messages(&mgr.connection, session.conversation_id)?;
+	if pins.is_empty() {
+		println!("No pinned messages.");
+	}
+	let mut tokens = 0;
+	for msg in session.history.iter().filter(|msg| pins.contains(&msg.id)) {
+		tokens += estimate_tokens(&msg.content, &msg.attachments);
+		println!("#{} {}: {}", msg.id, speaker_of(msg), snippet_of(&msg.content));
+	}
+	if !pins.is_empty() {
+		println!("Pinned messages use about {} of {} context tokens.", tokens, mgr.max_token);
+	}
+	Ok(())
+}
+
+/// Runs a slash command typed in the REPL. `line` is the input without the leading slash.
+/// Returns a prompt to send when the command expands to one.
+pub async fn execute_command(mgr: &mut ChatManager, reader: &mut LineReader, line: &str) -> Result<Option<String>, MainError> {
+	let (name, argument) = match line.split_once(char::is_whitespace) {
+		Some((name, argument)) => (name, argument.trim()),
+		None => (line, ""),
+	};
+	match name {
+		"help" => print_help(),
+		"attach" => attach(mgr.current_session.as_mut().unwrap(), argument),
+		"detach" => {
+			let session = mgr.current_session.as_mut().unwrap();
+			println!("Discarded {} pending attachment(s) and {} command output(s).", session.pending_attachments.len(), session.pending_commands.len());
+			session.pending_attachments.clear();
+			session.pending_commands.clear();
+		},
+		"t" => return Ok(expand_template(mgr, argument)),
+		"override" => {
+			let session = mgr.current_session.as_mut().unwrap();
+			session.budget_override = true;
+			if let Some(prompt) = session.refused_prompt.take() {
+				return Ok(Some(prompt));
+			}
+			println!("Your next message will be sent regardless of budgets.");
+		},
+		"system" => system(mgr, argument)?,
+		"pin" => pin(mgr, argument, true)?,
+		"unpin" => pin(mgr, argument, false)?,
+		"pins" => print_pins(mgr)?,
+		"edit" if argument.is_empty() => return compose(mgr, argument, false),
+		"edit" => edit_message(mgr, argument)?,
+		"quote" => return compose(mgr, argument, true),
+		"revisions" => print_revisions(mgr, argument)?,
+		"files" => print_included_files(mgr)?,
+		"code" => list_code_blocks(mgr, argument)?,
+		"save" => save_code_blocks(mgr, argument)?,
+		"copy" => copy_code_block(mgr, argument)?,
+		"apply" => apply_patches(mgr, reader, argument)?,
+		_ => println!("Unknown command: /{}. Type /help for a list of commands.", name),
+	}
+	Ok(None)
+}
@@ -0,0 +1,88 @@
+use database::*;
+
+use crate::editor::compose_text;
+use crate::error::*;
+use crate::types::*;
+
+/// Quotes `content` as Markdown, one `> ` per line.
+fn quote(content: &str) -> String {
+	content
+		.trim()
+		.lines()
+		.map(|line| if line.is_empty() { ">".to_string() } else { format!("> {}", line) })
+		.collect::<Vec<String>>()
+		.join("\n")
+}
+
+/// Writes the next message in the editor, starting from the unsent draft of the conversation and, for /quote,
+/// a quote of message `argument`. The text is kept as the draft until a reply to it is stored.
+/// Returns the message to send, if any.
+pub fn compose(mgr: &mut ChatManager, argument: &str, quoting: bool) -> Result<Option<String>, MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let mut initial = Database::get_draft(&mgr.connection, session.conversation_id)?.unwrap_or_default();
+	if quoting {
+		let id = argument.trim_start_matches('#').parse::<u32>().ok();
+		let Some(msg) = session.history.iter().find(|msg| Some(msg.id) == id) else {
+			println!("No such message in this conversation. Message IDs are shown when a conversation is resumed.");
+			return Ok(None);
+		};
+		if !initial.trim().is_empty() {
+			initial = format!("{}\n\n", initial.trim_end());
+		}
+		initial = format!("{}{}\n\n", initial, quote(&msg.content));
+	}
+
+	let (composed, success) = compose_text(&initial)?;
+	if composed.trim().is_empty() {
+		Database::delete_draft(&mgr.connection, session.conversation_id)?;
+		println!("The message is empty; nothing was sent.");
+		return Ok(None);
+	}
+	Database::save_draft(&mgr.connection, session.conversation_id, &composed)?;
+	if !success {
+		println!("The text was kept as a draft. Type /edit to continue it.");
+		return Ok(None);
+	}
+	Ok(Some(composed))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use std::os::unix::fs::PermissionsExt;
+
+	use super::*;
+	use crate::chat::open_session;
+	use crate::test_support::*;
+
+	/// Makes `$VISUAL` a script that records the text it is given and appends `typed`, exiting with `status`.
+	fn fake_editor(typed: &str, status: i32) -> std::path::PathBuf {
+		let dir = std::env::temp_dir().join(format!("ai-compose-test-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let script = dir.join("editor.sh");
+		let script_text = format!("#!/bin/sh\ncp \"$1\" \"{}\"\nprintf '%s' '{}' >> \"$1\"\nexit {}\n", dir.join("given.md").display(), typed, status);
+		std::fs::write(&script, script_text).unwrap();
+		std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+		std::env::set_var("VISUAL", &script);
+		dir.join("given.md")
+	}
+
+	#[test]
+	fn composes_from_the_draft_and_a_quote() {
+		let mut mgr = test_manager("http://localhost/v1");
+		let id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		let message_id = Database::add_client_message(&mgr.connection, id, "line one\n\nline two").unwrap();
+		Database::save_draft(&mgr.connection, id, "My draft").unwrap();
+		let (session, _) = open_session(&mut mgr, id).unwrap();
+		mgr.current_session = Some(session);
+
+		let given = fake_editor("and more", 1);
+		assert_eq!(compose(&mut mgr, &format!("#{}", message_id), true).unwrap(), None);
+		assert_eq!(std::fs::read_to_string(&given).unwrap(), "My draft\n\n> line one\n>\n> line two\n\n");
+		let draft = Database::get_draft(&mgr.connection, id).unwrap().unwrap();
+		assert_eq!(draft, "My draft\n\n> line one\n>\n> line two\n\nand more");
+
+		fake_editor(" and done", 0);
+		assert_eq!(compose(&mut mgr, "", false).unwrap(), Some(format!("{} and done", draft)));
+		assert!(compose(&mut mgr, "#999", true).unwrap().is_none());
+	}
+}
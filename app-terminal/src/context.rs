@@ -0,0 +1,330 @@
+use std::collections::HashSet;
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use openai::prelude::*;
+use database::*;
+
+use crate::attachment::*;
+use crate::budget::RequestEstimate;
+use crate::error::*;
+use crate::recall::{estimate_recall, estimate_recalled_tokens, recall_context};
+use crate::types::*;
+
+/// What to do with messages that no longer fit in `--max-dialog` or `--max-token`.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ContextStrategy {
+	/// Drop the oldest messages
+	Truncate,
+
+	/// Replace the oldest messages with a summary written by the model
+	Summarize,
+}
+
+static SUMMARY_INSTRUCTION: &str = "You maintain a running summary of a conversation between a user and an AI assistant. \
+	Update the summary with the new messages below. Keep the user's goals, decisions, constraints, \
+	names and code identifiers; drop pleasantries. Reply with the updated summary only.";
+
+pub fn role_of(msg: &SavedMessage) -> MessageRole {
+	match &msg.role[..] {
+		"assistant" => MessageRole::Assistant,
+		"user" => MessageRole::User,
+		"system" => MessageRole::System,
+		_ => panic!("Database error! Message ID {} does not have a valid role!", msg.id)
+	}
+}
+
+pub fn speaker_of(msg: &SavedMessage) -> &'static str {
+	match role_of(msg) {
+		MessageRole::Assistant => "ChatGPT",
+		MessageRole::User => "You",
+		MessageRole::System => "System",
+	}
+}
+
+pub fn to_message(msg: &SavedMessage) -> Message {
+	Message { role: role_of(msg), content: build_content(&msg.content, &msg.attachments) }
+}
+
+/// Returns the index of the oldest message that still fits in the window. Pinned messages are skipped,
+/// as they are always included and budgeted separately.
+fn window_start(history: &[SavedMessage], pinned: &HashSet<u32>, max_dialog: u64, max_token: u64) -> usize {
+	let mut i = 0;
+	let mut j = 0;
+	let mut start = history.len();
+	for (index, msg) in history.iter().enumerate().rev() {
+		if pinned.contains(&msg.id) {
+			continue
+		}
+		i += 1;
+		j += estimate_tokens(&msg.content, &msg.attachments);
+		if i > max_dialog || j > max_token {
+			break;
+		}
+		start = index;
+	}
+	start
+}
+
+fn transcript_of(messages: &[SavedMessage]) -> String {
+	messages
+		.iter()
+		.map(|msg| format!("{}: {}", msg.role, msg.content.trim()))
+		.collect::<Vec<String>>()
+		.join("\n\n")
+}
+
+/// Splits the messages to summarize into batches, so that each request stays within `max_token`.
+fn summary_batches(pending: &[SavedMessage], max_token: u64) -> Vec<&[SavedMessage]> {
+	let mut batches = vec![];
+	let mut batch_start = 0;
+	while batch_start < pending.len() {
+		let mut batch_end = batch_start;
+		let mut tokens = 0;
+		while batch_end < pending.len() && (batch_end == batch_start || tokens < max_token / 2) {
+			tokens += estimate_tokens(&pending[batch_end].content, &[]);
+			batch_end += 1;
+		}
+		batches.push(&pending[batch_start..batch_end]);
+		batch_start = batch_end;
+	}
+	batches
+}
+
+fn summary_request(previous: &str, batch: &[SavedMessage]) -> Vec<Message> {
+	vec![
+		Message { role: MessageRole::System, content: SUMMARY_INSTRUCTION.to_string().into() },
+		Message {
+			role: MessageRole::User,
+			content: format!("Current summary:\n{}\n\nNew messages:\n{}", previous, transcript_of(batch)).into()
+		},
+	]
+}
+
+/// Folds `pending` into the latest stored summary, a batch at a time. Failures are logged without the
+/// conversation, so that retrying them cannot append the summarization prompt to it.
+async fn update_summary(mgr: &ChatManager, conversation_id: u32, mut summary: Option<Summary>, pending: &[SavedMessage]) -> Result<Option<Summary>, MainError> {
+	for batch in summary_batches(pending, mgr.max_token) {
+		let previous = summary.as_ref().map_or("(none)".into(), |summary| summary.content.clone());
+		let context = summary_request(&previous, batch);
+
+		match get_response(&context, &mgr.api_key, &mgr.proxy, &mgr.api_base, &mgr.model, &CompletionParams::default()).await {
+			Ok(OpenAIResponse::Success(completion_response)) => {
+				let from_seq = summary.as_ref().map_or(batch[0].seq, |summary| summary.from_seq);
+				let to_seq = batch[batch.len() - 1].seq;
+				Database::add_summary(&mgr.connection, conversation_id, from_seq, to_seq, &completion_response)?;
+				summary = Database::get_latest_summary(&mgr.connection, conversation_id)?;
+			},
+			Ok(OpenAIResponse::Failure(openai_error)) => {
+				Database::add_error_log(&mgr.connection, &mgr.api_key, &mgr.model, None, &context, &json!(openai_error).to_string(), Some(&openai_error))?;
+				println!("Could not summarize older messages: {}", openai_error.error.message);
+				break;
+			},
+			Err(err) => {
+				Database::add_error_log(&mgr.connection, &mgr.api_key, &mgr.model, None, &context, &err, None)?;
+				println!("Could not summarize older messages: {}", err);
+				break;
+			}
+		}
+	}
+
+	Ok(summary)
+}
+
+/// The parts of the context of the current session that are known without making any request.
+struct ContextPlan {
+	pinned: HashSet<u32>,
+	summary: Option<Summary>,
+	/// Index of the oldest message of the history in the window.
+	start: usize,
+	/// Messages before the window that the summary does not cover yet, when it has to be updated.
+	unsummarized: Vec<SavedMessage>
+}
+
+fn plan_context(mgr: &ChatManager) -> Result<ContextPlan, MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let history = &session.history;
+
+	// Pinned messages are counted against the token budget first
+	let pinned: HashSet<u32> = Database::get_pinned_messages(&mgr.connection, session.conversation_id)?.into_iter().collect();
+	let mut max_token = mgr.max_token.saturating_sub(history
+		.iter()
+		.filter(|msg| pinned.contains(&msg.id))
+		.map(|msg| estimate_tokens(&msg.content, &msg.attachments))
+		.sum());
+	let mut summary = None;
+	if mgr.context_strategy == ContextStrategy::Summarize {
+		summary = Database::get_latest_summary(&mgr.connection, session.conversation_id)?;
+		if let Some(summary) = &summary {
+			max_token = max_token.saturating_sub(estimate_tokens(&summary.content, &[]));
+		}
+	}
+	if let Some(system_prompt) = &session.system_prompt {
+		max_token = max_token.saturating_sub(estimate_tokens(system_prompt, &[]));
+	}
+
+	let start = window_start(history, &pinned, mgr.max_dialog, max_token);
+	let mut unsummarized = vec![];
+	if mgr.context_strategy == ContextStrategy::Summarize {
+		let covered = summary.as_ref().map_or(0, |summary| summary.to_seq);
+		unsummarized = history[..start]
+			.iter()
+			.filter(|msg| !pinned.contains(&msg.id) && msg.seq > covered)
+			.cloned()
+			.collect();
+	}
+	Ok(ContextPlan { pinned, summary, start, unsummarized })
+}
+
+/// Estimates the requests that sending the prompt of the current session makes: updating the summary, embedding
+/// messages for recall and the chat request itself, so that budgets can be checked before any of them is made.
+pub fn estimate_requests(mgr: &ChatManager) -> Result<Vec<RequestEstimate>, MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let plan = plan_context(mgr)?;
+	let mut requests = vec![];
+
+	// Each batch sends the summary written for the previous one
+	let mut summary_tokens = plan.summary.as_ref().map_or(0, |summary| estimate_tokens(&summary.content, &[]));
+	for batch in summary_batches(&plan.unsummarized, mgr.max_token) {
+		let mut request = RequestEstimate::chat(&mgr.model, &summary_request("", batch), None);
+		request.prompt_tokens += summary_tokens;
+		summary_tokens = request.completion_tokens;
+		requests.push(request);
+	}
+	let mut recalled_tokens = 0;
+	if mgr.recall_k > 0 {
+		requests.push(estimate_recall(mgr, &session.prompt)?);
+		recalled_tokens = estimate_recalled_tokens(mgr.recall_k);
+	}
+
+	let mut context = context_of(mgr, &plan, None, None);
+	context.push(Message { role: MessageRole::User, content: build_content(&session.prompt, &session.pending_attachments) });
+	let mut request = RequestEstimate::chat(&mgr.model, &context, mgr.params.max_tokens);
+	request.prompt_tokens += summary_tokens + recalled_tokens;
+	requests.push(request);
+	Ok(requests)
+}
+
+fn context_of(mgr: &ChatManager, plan: &ContextPlan, summary: Option<&str>, recalled: Option<Message>) -> Vec<Message> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let mut context: Vec<Message> = vec![];
+	if let Some(system_prompt) = &session.system_prompt {
+		context.push(Message { role: MessageRole::System, content: system_prompt.clone().into() });
+	}
+	if let Some(summary) = summary {
+		context.push(Message {
+			role: MessageRole::System,
+			content: format!("Summary of the earlier part of this conversation:\n{}", summary).into()
+		});
+	}
+	context.extend(recalled);
+	context.extend(session.history
+		.iter()
+		.enumerate()
+		.filter(|(index, msg)| *index >= plan.start || plan.pinned.contains(&msg.id))
+		.map(|(_, msg)| to_message(msg)));
+	context
+}
+
+/// Builds the context sent ahead of the user's prompt from the current session's history.
+pub async fn build_context(mgr: &ChatManager) -> Result<Vec<Message>, MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let mut plan = plan_context(mgr)?;
+	if !plan.unsummarized.is_empty() {
+		plan.summary = update_summary(mgr, session.conversation_id, plan.summary.take(), &plan.unsummarized).await?;
+	}
+	// Without evicted messages, an existing summary describes messages that are still in the window
+	let evicted = session.history[..plan.start].iter().any(|msg| !plan.pinned.contains(&msg.id));
+	let summary = plan.summary.as_ref().filter(|_| evicted).map(|summary| summary.content.clone());
+
+	let mut recalled = None;
+	if mgr.recall_k > 0 {
+		recalled = recall_context(mgr, session.conversation_id, &session.prompt).await?;
+	}
+	Ok(context_of(mgr, &plan, summary.as_deref(), recalled))
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::http::StatusCode;
+	use axum::routing::post;
+	use axum::{Json, Router};
+
+	use super::*;
+	use crate::chat::open_session;
+	use crate::error_log::retry_error;
+	use crate::test_support::*;
+
+	fn history(contents: &[&str]) -> Vec<SavedMessage> {
+		contents
+			.iter()
+			.enumerate()
+			.map(|(index, content)| SavedMessage {
+				id: index as u32 + 1,
+				conversation_id: 1,
+				role: "user".into(),
+				content: content.to_string(),
+				prompt_tokens: 0,
+				completion_tokens: 0,
+				updateat: Default::default(),
+				seq: index as u64 + 1,
+				createdat: Default::default(),
+				attachments: vec![]
+			})
+			.collect()
+	}
+
+	#[test]
+	fn keeps_pinned_messages_out_of_the_window_limits() {
+		// Each message is about 10 tokens
+		let history = history(&["a".repeat(20).as_str(), &"b".repeat(20), &"c".repeat(20), &"d".repeat(20), &"e".repeat(20)]);
+		let none = HashSet::new();
+		assert_eq!(window_start(&history, &none, 32, 30), 2);
+		assert_eq!(window_start(&history, &none, 2, 1000), 3);
+
+		let pinned = HashSet::from([1, 4]);
+		assert_eq!(window_start(&history, &pinned, 32, 30), 1);
+		assert_eq!(window_start(&history, &pinned, 2, 1000), 2);
+		assert_eq!(window_start(&history, &pinned, 32, 5), 5);
+	}
+
+	#[tokio::test]
+	async fn counts_pinned_messages_against_the_token_limit() {
+		let mut mgr = test_manager("http://localhost/v1");
+		mgr.overrides.max_token = Some(30);
+		let id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		let mut ids = vec![];
+		for letter in ["a", "b", "c", "d", "e"] {
+			ids.push(Database::add_client_message(&mgr.connection, id, &letter.repeat(20)).unwrap());
+		}
+		Database::add_pin(&mgr.connection, id, ids[0]).unwrap();
+		let (session, _) = open_session(&mut mgr, id).unwrap();
+		mgr.current_session = Some(session);
+
+		let context: Vec<String> = build_context(&mgr).await.unwrap().iter().map(|msg| msg.content.text()).collect();
+		assert_eq!(context, ["a".repeat(20), "d".repeat(20), "e".repeat(20)]);
+	}
+
+	#[tokio::test]
+	async fn does_not_replay_failed_summaries_into_the_conversation() {
+		let router = Router::new().route("/v1/chat/completions", post(|| async { StatusCode::INTERNAL_SERVER_ERROR }));
+		let mut mgr = test_manager(&serve_stub(router).await);
+		mgr.overrides.max_dialog = Some(2);
+		mgr.overrides.context_strategy = Some("summarize".into());
+		let id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		for index in 0..6 {
+			Database::add_client_message(&mgr.connection, id, &format!("message {}", index)).unwrap();
+		}
+		let (session, _) = open_session(&mut mgr, id).unwrap();
+		mgr.current_session = Some(session);
+		build_context(&mgr).await.unwrap();
+
+		let log = Database::get_error_logs(&mgr.connection, None, 1).unwrap().remove(0);
+		assert_eq!(log.conversation_id, None);
+		let router = Router::new().route("/v1/chat/completions", post(|| async { Json(completion_json("summary", 10, 5)) }));
+		mgr.api_base = serve_stub(router).await;
+		retry_error(&mgr, log.id, false).await.unwrap();
+		assert_eq!(Database::get_all_messages_in_conversation(&mgr.connection, id).unwrap().len(), 6);
+	}
+}
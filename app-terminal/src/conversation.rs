@@ -0,0 +1,148 @@
+use clap::Subcommand;
+use rusqlite::Connection;
+
+use database::*;
+
+use crate::error::*;
+use crate::stats::mask_key;
+
+#[derive(Debug, Subcommand)]
+pub enum ConversationCommand {
+	/// List conversations of every profile
+	List {
+		/// List archived conversations instead
+		#[arg(long, conflicts_with_all = ["trash", "all"])]
+		archived: bool,
+
+		/// List conversations in the trash instead
+		#[arg(long, conflicts_with = "all")]
+		trash: bool,
+
+		/// List all conversations regardless of their state
+		#[arg(long)]
+		all: bool,
+	},
+
+	/// Hide conversations from the listing without deleting them
+	Archive {
+		#[arg(required = true)]
+		ids: Vec<u32>,
+	},
+
+	/// Bring archived conversations back to the listing
+	Unarchive {
+		#[arg(required = true)]
+		ids: Vec<u32>,
+	},
+
+	/// Move conversations to the trash
+	Delete {
+		#[arg(required = true)]
+		ids: Vec<u32>,
+	},
+
+	/// Restore conversations from the trash
+	Restore {
+		#[arg(required = true)]
+		ids: Vec<u32>,
+	},
+
+	/// File a conversation under a topic, or remove it from its topic when no name is given
+	Topic {
+		id: u32,
+		name: Option<String>,
+	},
+
+	/// Permanently delete conversations in the trash, with their messages, attachments and error logs
+	Purge {
+		/// Only purge these conversations
+		ids: Vec<u32>,
+
+		/// Only purge conversations trashed more than this many days ago
+		#[arg(long, value_name = "Days")]
+		older_than: Option<u32>,
+	},
+}
+
+fn print_list(conn: &Connection, filter: ConversationFilter) -> Result<(), MainError> {
+	let conversations = Database::get_conversations(conn, None, filter)?;
+	if conversations.is_empty() {
+		println!("No conversations.");
+	}
+	for conv in conversations.iter() {
+		let mut state = String::new();
+		if let Some(topic) = &conv.topic {
+			state.push_str(&format!(", topic {}", topic));
+		}
+		if let Some(archived_at) = conv.archived_at {
+			state.push_str(&format!(", archived {}", archived_at.format("%Y-%m-%d")));
+		}
+		if let Some(deleted_at) = conv.deleted_at {
+			state.push_str(&format!(", trashed {}", deleted_at.format("%Y-%m-%d")));
+		}
+		println!("[{}] {}: {} (Profile: {}, Usage: {} tokens{})",
+			conv.lastupdate.format("%Y-%m-%d %H:%M:%S"), conv.id, conv.title, mask_key(&conv.key), conv.usage, state);
+	}
+	Ok(())
+}
+
+/// Applies `update` to each conversation and reports those it did not change.
+fn update_each(ids: &[u32], done: &str, unchanged: &str, update: impl Fn(u32) -> rusqlite::Result<usize>) -> Result<(), MainError> {
+	let mut changed = 0;
+	for id in ids {
+		if update(*id)? == 0 {
+			println!("Conversation {} {}.", id, unchanged);
+		}
+		else {
+			changed += 1;
+		}
+	}
+	println!("{} {} conversation(s).", done, changed);
+	Ok(())
+}
+
+pub fn run_conversation_command(conn: &Connection, command: ConversationCommand) -> Result<(), MainError> {
+	match command {
+		ConversationCommand::List { archived, trash, all } => {
+			let filter = match (archived, trash, all) {
+				(true, _, _) => ConversationFilter::Archived,
+				(_, true, _) => ConversationFilter::Trash,
+				(_, _, true) => ConversationFilter::All,
+				_ => ConversationFilter::Active,
+			};
+			print_list(conn, filter)
+		},
+		ConversationCommand::Archive { ids } => update_each(&ids, "Archived", "does not exist or is already archived",
+			|id| Database::set_conversation_archived(conn, id, true)),
+		ConversationCommand::Unarchive { ids } => update_each(&ids, "Unarchived", "does not exist or is not archived",
+			|id| Database::set_conversation_archived(conn, id, false)),
+		ConversationCommand::Delete { ids } => update_each(&ids, "Trashed", "does not exist or is already in the trash",
+			|id| Database::set_conversation_deleted(conn, id, true)),
+		ConversationCommand::Restore { ids } => update_each(&ids, "Restored", "is not in the trash",
+			|id| Database::set_conversation_deleted(conn, id, false)),
+		ConversationCommand::Topic { id, name } => {
+			let name = name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+			match (Database::set_conversation_topic(conn, id, name)?, name) {
+				(0, _) => println!("No such conversation."),
+				(_, Some(name)) => println!("Conversation {} filed under {}.", id, name),
+				(_, None) => println!("Conversation {} removed from its topic.", id),
+			}
+			Ok(())
+		},
+		ConversationCommand::Purge { ids, older_than } => {
+			let mut purged = 0;
+			if ids.is_empty() {
+				purged = Database::purge_conversations(conn, None, older_than)?;
+			}
+			for id in ids {
+				let count = Database::purge_conversations(conn, Some(id), older_than)?;
+				if count == 0 {
+					println!("Conversation {} is not in the trash.", id);
+				}
+				purged += count;
+			}
+			println!("Permanently deleted {} conversation(s).", purged);
+			Ok(())
+		}
+	}
+}
@@ -0,0 +1,192 @@
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+use rusqlite::Connection;
+
+use database::*;
+
+use crate::error::*;
+
+static PASSPHRASE_ENV: &str = "AI_DB_PASSPHRASE";
+static MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Subcommand)]
+pub enum DatabaseCommand {
+	/// Encrypt conversation titles, messages and error contexts with a passphrase
+	Encrypt,
+
+	/// Change the passphrase of an encrypted database
+	Rotate,
+
+	/// Copy the database to a file; safe while other sessions are open
+	Backup {
+		path: PathBuf,
+	},
+
+	/// Replace the database with a backup
+	Restore {
+		path: PathBuf,
+	},
+
+	/// Rebuild the database file to reclaim unused space
+	Vacuum,
+}
+
+fn read_passphrase(prompt: &str) -> Result<String, MainError> {
+	Ok(rpassword::prompt_password(prompt)?)
+}
+
+fn read_new_passphrase() -> Result<String, MainError> {
+	loop {
+		let passphrase = read_passphrase("New passphrase: ")?;
+		if passphrase.is_empty() {
+			println!("The passphrase cannot be empty.");
+			continue
+		}
+		if read_passphrase("Repeat new passphrase: ")? != passphrase {
+			println!("Passphrases do not match.");
+			continue
+		}
+		return Ok(passphrase);
+	}
+}
+
+/// Asks for the passphrase of an encrypted database, unless it is supplied through `AI_DB_PASSPHRASE`.
+/// Returns the passphrase that unlocked the connection, or `None` if the database is not encrypted.
+pub fn unlock_database(conn: &Connection) -> Result<Option<String>, MainError> {
+	if !Database::is_encrypted(conn)? {
+		return Ok(None);
+	}
+
+	if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+		if Database::unlock(conn, &passphrase)? {
+			return Ok(Some(passphrase));
+		}
+		return Err(ArgumentError::new("passphrase", &format!("{} does not unlock the database", PASSPHRASE_ENV)).into());
+	}
+
+	for _ in 0..MAX_ATTEMPTS {
+		let passphrase = read_passphrase("Database passphrase: ")?;
+		if Database::unlock(conn, &passphrase)? {
+			return Ok(Some(passphrase));
+		}
+		println!("Incorrect passphrase.");
+	}
+	Err(ArgumentError::new("passphrase", "Incorrect passphrase").into())
+}
+
+fn backup_dir(db_path: &Path) -> PathBuf {
+	let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+	name.push(".backups");
+	db_path.with_file_name(name)
+}
+
+fn format_size(bytes: u64) -> String {
+	if bytes >= 1024 * 1024 {
+		format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+	}
+	else {
+		format!("{:.1} KiB", bytes as f64 / 1024.0)
+	}
+}
+
+/// Removes the oldest automatic backups of `db_path` so that at most `retention` remain.
+fn prune_backups(db_path: &Path, retention: usize) -> Result<(), MainError> {
+	let prefix = format!("{}-", db_path.file_stem().unwrap_or_default().to_string_lossy());
+	let mut backups: Vec<PathBuf> = std::fs::read_dir(backup_dir(db_path))?
+		.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+		.filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&prefix)))
+		.collect();
+	backups.sort();
+	let excess = backups.len().saturating_sub(retention);
+	for path in backups.iter().take(excess) {
+		std::fs::remove_file(path)?;
+	}
+	Ok(())
+}
+
+/// Takes a timestamped backup of `db_path` into the `<database>.backups` directory next to it,
+/// then prunes old backups down to `retention`. Does nothing if `retention` is zero.
+pub fn auto_backup(conn: &Connection, db_path: &Path, reason: &str, retention: usize) -> Result<Option<PathBuf>, MainError> {
+	if retention == 0 {
+		return Ok(None);
+	}
+	let dir = backup_dir(db_path);
+	std::fs::create_dir_all(&dir)?;
+	let path = dir.join(format!("{}-{}-{}.db",
+		db_path.file_stem().unwrap_or_default().to_string_lossy(),
+		chrono::Local::now().format("%Y%m%d-%H%M%S"),
+		reason));
+	Database::backup_database(conn, &path)?;
+	prune_backups(db_path, retention)?;
+	Ok(Some(path))
+}
+
+/// Backs up the database before `init_current_schema` upgrades it to a newer schema version.
+pub fn backup_before_migration(conn: &Connection, db_path: &Path, retention: usize) -> Result<(), MainError> {
+	let version = Database::get_schema_version(conn)?;
+	if version >= CurrentSchema::version() {
+		return Ok(());
+	}
+	if let Some(path) = auto_backup(conn, db_path, &format!("v{}", version), retention)? {
+		println!("Upgrading the database from schema v{} to v{}. A backup was saved to {}.", version, CurrentSchema::version(), path.display());
+	}
+	Ok(())
+}
+
+pub fn run_database_command(conn: &mut Connection, db_path: &Path, unlocked_with: Option<String>, backup_retention: usize, command: DatabaseCommand) -> Result<(), MainError> {
+	match command {
+		DatabaseCommand::Encrypt => {
+			if unlocked_with.is_some() {
+				println!("The database is already encrypted. Use `db rotate` to change the passphrase.");
+				return Ok(());
+			}
+			let passphrase = read_new_passphrase()?;
+			Database::encrypt_database(conn, &passphrase)?;
+			println!("The database is now encrypted. Keep the passphrase safe: it cannot be recovered.");
+		},
+		DatabaseCommand::Rotate => {
+			let Some(old_passphrase) = unlocked_with else {
+				println!("The database is not encrypted. Use `db encrypt` first.");
+				return Ok(());
+			};
+			let new_passphrase = read_new_passphrase()?;
+			if !Database::rotate_passphrase(conn, &old_passphrase, &new_passphrase)? {
+				return Err(ArgumentError::new("passphrase", "Incorrect passphrase").into());
+			}
+			println!("The passphrase has been changed.");
+		},
+		DatabaseCommand::Backup { path } => {
+			if path.exists() {
+				println!("{} already exists.", path.display());
+				return Ok(());
+			}
+			Database::backup_database(conn, &path)?;
+			println!("Backed up {} to {}.", format_size(Database::get_database_size(conn)?), path.display());
+		},
+		DatabaseCommand::Restore { path } => {
+			if !path.is_file() {
+				println!("{} does not exist.", path.display());
+				return Ok(());
+			}
+			if !Database::is_conversation_database(&path)? {
+				println!("{} is not a conversation database.", path.display());
+				return Ok(());
+			}
+			let safety_backup = auto_backup(conn, db_path, "restore", backup_retention)?;
+			Database::restore_database(conn, &path)?;
+			CurrentSchema::init_current_schema(conn)?;
+			println!("Restored the database from {}.", path.display());
+			if let Some(safety_backup) = safety_backup {
+				println!("The previous contents were saved to {}.", safety_backup.display());
+			}
+		},
+		DatabaseCommand::Vacuum => {
+			let before = Database::get_database_size(conn)?;
+			Database::vacuum(conn)?;
+			let after = Database::get_database_size(conn)?;
+			println!("Vacuumed the database: {} -> {} ({} reclaimed).", format_size(before), format_size(after), format_size(before.saturating_sub(after)));
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,59 @@
+use std::{path::PathBuf, process::{Command, ExitStatus}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::error::*;
+
+#[cfg(windows)]
+static DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+static DEFAULT_EDITOR: &str = "vi";
+
+/// The editor command from `$VISUAL` or `$EDITOR`, which may include arguments such as `code --wait`.
+fn editor_command() -> Vec<String> {
+	let editor = std::env::var("VISUAL")
+		.or_else(|_| std::env::var("EDITOR"))
+		.ok()
+		.filter(|editor| !editor.trim().is_empty())
+		.unwrap_or(DEFAULT_EDITOR.into());
+	editor.split_whitespace().map(String::from).collect()
+}
+
+fn temp_file() -> PathBuf {
+	let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+	std::env::temp_dir().join(format!("ai-{}-{}.md", std::process::id(), nanos))
+}
+
+/// Opens `initial` in the user's editor and returns how the editor exited with the saved text.
+fn run_editor(initial: &str) -> Result<(ExitStatus, String), MainError> {
+	let path = temp_file();
+	std::fs::write(&path, initial)?;
+
+	let command = editor_command();
+	let status = Command::new(&command[0]).args(&command[1..]).arg(&path).status();
+	let edited = std::fs::read_to_string(&path);
+	let _ = std::fs::remove_file(&path);
+
+	Ok((status?, edited?))
+}
+
+/// Opens `initial` in the user's editor and returns the saved text, or `None` if it was left unchanged.
+pub fn edit_text(initial: &str) -> Result<Option<String>, MainError> {
+	let (status, edited) = run_editor(initial)?;
+	if !status.success() {
+		println!("The editor exited with {}; the edit was discarded.", status);
+		return Ok(None);
+	}
+	if edited.trim_end() == initial.trim_end() {
+		return Ok(None);
+	}
+	Ok(Some(edited.trim_end().to_string()))
+}
+
+/// Opens `initial` in the user's editor to write a message. Returns the saved text, which is kept even if
+/// unchanged, and whether the editor exited successfully.
+pub fn compose_text(initial: &str) -> Result<(String, bool), MainError> {
+	let (status, composed) = run_editor(initial)?;
+	if !status.success() {
+		println!("The editor exited with {}.", status);
+	}
+	Ok((composed.trim_end().to_string(), status.success()))
+}
@@ -21,6 +21,7 @@ impl Display for ArgumentError {
 impl Error for ArgumentError {}
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum MainError {
 	ArgumentError(ArgumentError),
 
@@ -40,18 +41,27 @@ impl Display for MainError {
 
 impl From::<ArgumentError> for MainError {
     fn from(value: ArgumentError) -> Self {
-        return Self::ArgumentError(value)
+        Self::ArgumentError(value)
     }
 }
 
 impl From::<std::io::Error> for MainError {
     fn from(value: std::io::Error) -> Self {
-        return Self::IOError(value)
+        Self::IOError(value)
     }
 }
 
 impl From::<rusqlite::Error> for MainError {
 	fn from(value: rusqlite::Error) -> Self {
-		return Self::SQLiteError(value)
+		Self::SQLiteError(value)
+	}
+}
+
+impl From::<rustyline::error::ReadlineError> for MainError {
+	fn from(value: rustyline::error::ReadlineError) -> Self {
+		match value {
+			rustyline::error::ReadlineError::Io(err) => Self::IOError(err),
+			err => Self::IOError(std::io::Error::other(err.to_string())),
+		}
 	}
 }
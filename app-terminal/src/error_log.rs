@@ -0,0 +1,315 @@
+use std::time::Instant;
+
+use clap::Subcommand;
+use rusqlite::Connection;
+use serde_json::json;
+
+use openai::prelude::*;
+use database::*;
+
+use crate::budget::{check_budgets, RequestEstimate};
+use crate::error::*;
+use crate::markdown::print_reply;
+use crate::stats::mask_key;
+use crate::types::*;
+
+#[derive(Debug, Subcommand)]
+pub enum ErrorCommand {
+	/// List individual failures, most recent first
+	List {
+		/// Only show failures with this error code
+		#[arg(long)]
+		code: Option<String>,
+
+		/// Number of failures to show
+		#[arg(long, default_value = "20")]
+		limit: u32,
+	},
+
+	/// Show the full details and stored request context of a failure
+	Show {
+		id: u32,
+	},
+
+	/// Resend the stored request context of a failure
+	Retry {
+		id: u32,
+
+		/// Resend a failure that has already been retried successfully, adding its reply again
+		#[arg(long)]
+		force: bool,
+	},
+
+	/// Delete stored failures
+	Purge {
+		/// Only delete failures older than this many days
+		#[arg(long, value_name = "Days", required_unless_present = "all")]
+		older_than: Option<u32>,
+
+		/// Only delete failures that have been retried successfully
+		#[arg(long)]
+		retried: bool,
+
+		/// Delete all failures regardless of age
+		#[arg(long, conflicts_with = "older_than")]
+		all: bool,
+	},
+}
+
+fn describe(log: &ErrorLog) -> String {
+	match (&log.code, &log.message) {
+		(Some(code), Some(message)) => format!("{}: {}", code, message),
+		(None, Some(message)) => message.clone(),
+		_ => log.error.clone().unwrap_or_default(),
+	}
+}
+
+fn print_summary(conn: &Connection) -> Result<(), MainError> {
+	let summary = Database::get_error_summary(conn)?;
+	if summary.is_empty() {
+		println!("No errors recorded.");
+		return Ok(());
+	}
+
+	println!("{:<32}  {:<32}  {:>6}  Last Seen", "Code", "Type", "Count");
+	for group in summary.iter() {
+		println!("{:<32}  {:<32}  {:>6}  {}",
+			group.code.as_deref().unwrap_or("(request failed)"),
+			group.r#type.as_deref().unwrap_or("-"),
+			group.count,
+			group.lastupdate.format("%Y-%m-%d %H:%M:%S"));
+	}
+	println!("Use `errors list` to see individual failures and `errors show <id>` for details.");
+	Ok(())
+}
+
+fn print_list(conn: &Connection, code: Option<&str>, limit: u32) -> Result<(), MainError> {
+	let logs = Database::get_error_logs(conn, code, limit)?;
+	if logs.is_empty() {
+		println!("No errors recorded.");
+	}
+	for log in logs.iter() {
+		let mut summary = describe(log);
+		if summary.chars().count() > 80 {
+			summary = format!("{}...", summary.chars().take(77).collect::<String>());
+		}
+		println!("[{}] {}: {} (Conversation: {}{})",
+			log.updateat.format("%Y-%m-%d %H:%M:%S"),
+			log.id,
+			summary.replace('\n', " "),
+			log.conversation_id.map_or("-".into(), |id| id.to_string()),
+			if log.retried_at.is_some() { ", retried" } else { "" });
+	}
+	Ok(())
+}
+
+fn print_details(conn: &Connection, id: u32) -> Result<(), MainError> {
+	let Some(log) = Database::get_error_log(conn, id)? else {
+		println!("No such error.");
+		return Ok(());
+	};
+
+	println!("Error {} at {}", log.id, log.updateat.format("%Y-%m-%d %H:%M:%S"));
+	println!("Profile: {}", mask_key(&log.key));
+	println!("Model: {}", log.model.as_deref().unwrap_or("-"));
+	println!("Conversation: {}", log.conversation_id.map_or("-".into(), |id| id.to_string()));
+	println!("Code: {}", log.code.as_deref().unwrap_or("-"));
+	println!("Type: {}", log.r#type.as_deref().unwrap_or("-"));
+	println!("Param: {}", log.param.as_deref().unwrap_or("-"));
+	println!("Message: {}", log.message.as_deref().unwrap_or("-"));
+	println!("Error: {}", log.error.as_deref().unwrap_or("-"));
+	if let Some(retried_at) = log.retried_at {
+		println!("Retried successfully at {}", retried_at.format("%Y-%m-%d %H:%M:%S"));
+	}
+	println!("Context ({} message(s)):", log.context.len());
+	println!("{}", serde_json::to_string_pretty(&log.context).unwrap());
+	Ok(())
+}
+
+fn purge(conn: &Connection, older_than: Option<u32>, retried: bool) -> Result<(), MainError> {
+	let deleted = Database::purge_error_logs(conn, older_than, retried)?;
+	println!("Deleted {} error log(s).", deleted);
+	Ok(())
+}
+
+pub fn run_error_command(conn: &Connection, command: Option<ErrorCommand>) -> Result<(), MainError> {
+	match command {
+		None => print_summary(conn),
+		Some(ErrorCommand::List { code, limit }) => print_list(conn, code.as_deref(), limit),
+		Some(ErrorCommand::Show { id }) => print_details(conn, id),
+		Some(ErrorCommand::Purge { older_than, retried, .. }) => purge(conn, older_than, retried),
+		Some(ErrorCommand::Retry { .. }) => unreachable!("retrying requires a ChatManager"),
+	}
+}
+
+/// Resends the context stored with an error log and appends a successful answer to its conversation.
+/// The request is made with the key and model of the failure and the parameters of its conversation.
+pub async fn retry_error(mgr: &ChatManager, id: u32, force: bool) -> Result<(), MainError> {
+	let Some(log) = Database::get_error_log(&mgr.connection, id)? else {
+		println!("No such error.");
+		return Ok(());
+	};
+	if log.context.is_empty() {
+		println!("Error {} has no stored request context to resend.", id);
+		return Ok(());
+	}
+	if let (Some(retried_at), false) = (log.retried_at, force) {
+		println!("Error {} was already retried successfully at {}. Use --force to send it again.", id, retried_at.format("%Y-%m-%d %H:%M:%S"));
+		return Ok(());
+	}
+
+	let settings = match log.conversation_id {
+		Some(conversation_id) => Database::get_conversation_settings(&mgr.connection, conversation_id)?,
+		None => None
+	};
+	let model = log.model.clone()
+		.or_else(|| settings.as_ref().and_then(|settings| settings.model.clone()))
+		.unwrap_or(mgr.model.clone());
+	let params = match settings {
+		Some(settings) => settings.params,
+		None => {
+			if mgr.params != CompletionParams::default() {
+				println!("Error {} has no stored parameters, so the current ones are used.", id);
+			}
+			mgr.params.clone()
+		}
+	};
+	if log.key != mgr.api_key {
+		println!("Resending with the API key of the failed request ({}).", mask_key(&log.key));
+	}
+
+	if !mgr.ignore_budget {
+		let check = check_budgets(mgr, &log.key, log.conversation_id, &[RequestEstimate::chat(&model, &log.context, params.max_tokens)])?;
+		if let Some(refusal) = check.refusal {
+			println!("{}\nThe request was not resent. Use --ignore-budget to send it anyway.", refusal);
+			return Ok(());
+		}
+		for warning in check.warnings.iter() {
+			println!("{}", warning);
+		}
+	}
+
+	let attachments = Database::get_error_attachments(&mgr.connection, id)?;
+	let files = Database::get_error_files(&mgr.connection, id)?;
+	let started = Instant::now();
+	let openai_response = get_response(&log.context, &log.key, &mgr.proxy, &mgr.api_base, &model, &params).await;
+	let latency_ms = started.elapsed().as_millis() as u64;
+
+	match openai_response {
+		Ok(OpenAIResponse::Success(completion_response)) => {
+			match (log.conversation_id, &log.prompt) {
+				(Some(conversation_id), Some(prompt)) => {
+					let message_id = Database::add_client_message(&mgr.connection, conversation_id, prompt)?;
+					Database::add_message_files(&mgr.connection, message_id, &files)?;
+					for (position, attachment) in attachments.iter().enumerate() {
+						Database::add_attachment(&mgr.connection, message_id, position as u32, attachment)?;
+					}
+					Database::add_server_message(&mgr.connection, conversation_id, &completion_response, latency_ms)?;
+					println!("The reply has been appended to conversation {}.", conversation_id);
+				},
+				_ => println!("Error {} is not linked to a conversation, so the reply was not saved.", id),
+			}
+			Database::mark_error_retried(&mgr.connection, id)?;
+			print_reply(mgr.raw, "ChatGPT", &completion_response.msg());
+		},
+		Ok(OpenAIResponse::Failure(openai_error)) => {
+			let error_id = Database::add_error_log(&mgr.connection, &log.key, &model, log.conversation_id, &log.context, &json!(openai_error).to_string(), Some(&openai_error))?;
+			Database::add_error_attachments(&mgr.connection, error_id, &attachments)?;
+			Database::add_error_files(&mgr.connection, error_id, &files)?;
+			println!("Error: {}", openai_error.error.message);
+		},
+		Err(err) => {
+			let error_id = Database::add_error_log(&mgr.connection, &log.key, &model, log.conversation_id, &log.context, &err, None)?;
+			Database::add_error_attachments(&mgr.connection, error_id, &attachments)?;
+			Database::add_error_files(&mgr.connection, error_id, &files)?;
+			println!("Error: {}", err);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+
+	use axum::extract::State;
+	use axum::http::HeaderMap;
+	use axum::routing::post;
+	use axum::{Json, Router};
+	use serde_json::Value;
+
+	use super::*;
+	use crate::attachment::build_content;
+	use crate::chat::{open_session, record_chat};
+	use crate::test_support::*;
+
+	type Requests = Arc<Mutex<Vec<(String, Value)>>>;
+
+	async fn record_request(State(requests): State<Requests>, headers: HeaderMap, Json(body): Json<Value>) -> Json<Value> {
+		let key = headers["authorization"].to_str().unwrap().trim_start_matches("Bearer ").to_string();
+		requests.lock().unwrap().push((key, body));
+		Json(completion_json("retried", 10, 5))
+	}
+
+	async fn failed_request() -> (ChatManager, Requests, u32, u32) {
+		let requests = Requests::default();
+		let router = Router::new().route("/v1/chat/completions", post(record_request)).with_state(requests.clone());
+		let mgr = test_manager(&serve_stub(router).await);
+		let conversation_id = Database::add_conversation(&mgr.connection, "title", "sk-failed").unwrap();
+		let context = [Message { role: MessageRole::User, content: MessageContent::Text("hello".into()) }];
+		Database::add_error_log(&mgr.connection, "sk-failed", "gpt-3.5-turbo", Some(conversation_id), &context, "timed out", None).unwrap();
+		let id = Database::get_error_logs(&mgr.connection, None, 1).unwrap()[0].id;
+		(mgr, requests, conversation_id, id)
+	}
+
+	#[tokio::test]
+	async fn retries_with_the_key_and_model_of_the_failure() {
+		let (mgr, requests, conversation_id, id) = failed_request().await;
+		retry_error(&mgr, id, false).await.unwrap();
+
+		let (key, body) = requests.lock().unwrap()[0].clone();
+		assert_eq!((key.as_str(), body["model"].as_str()), ("sk-failed", Some("gpt-3.5-turbo")));
+		assert_eq!(body["messages"][0]["content"], "hello");
+		let history = Database::get_all_messages_in_conversation(&mgr.connection, conversation_id).unwrap();
+		let texts: Vec<&str> = history.iter().map(|msg| msg.content.as_str()).collect();
+		assert_eq!(texts, ["hello", "retried"]);
+		assert!(Database::get_error_log(&mgr.connection, id).unwrap().unwrap().retried_at.is_some());
+	}
+
+	#[tokio::test]
+	async fn stores_the_attachments_and_files_of_a_failed_prompt_with_its_retry() {
+		let requests = Requests::default();
+		let router = Router::new().route("/v1/chat/completions", post(record_request)).with_state(requests.clone());
+		let mut mgr = test_manager(&serve_stub(router).await);
+		let conversation_id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		let notes = Attachment { name: "notes.txt".into(), mime: "text/plain".into(), data: b"some notes".to_vec() };
+		let (mut session, _) = open_session(&mut mgr, conversation_id).unwrap();
+		session.prompt = "summarize".into();
+		session.pending_attachments = vec![notes.clone()];
+		let file = IncludedFile { path: "/src/main.rs".into(), sha256: "0123456789abcdef".into(), size: 12 };
+		session.included_files = vec![file.clone()];
+		let context = [Message { role: MessageRole::User, content: build_content(&session.prompt, &session.pending_attachments) }];
+		mgr.current_session = Some(session);
+		record_chat(&mut mgr, &context, Err("timed out".into()), 0).unwrap();
+
+		let id = Database::get_error_logs(&mgr.connection, None, 1).unwrap()[0].id;
+		retry_error(&mgr, id, false).await.unwrap();
+		assert_eq!(requests.lock().unwrap()[0].1["messages"][0]["content"][1]["text"], "File `notes.txt`:\n```\nsome notes\n```");
+		let history = Database::get_all_messages_in_conversation(&mgr.connection, conversation_id).unwrap();
+		assert_eq!((history[0].content.as_str(), &history[0].attachments), ("summarize", &vec![notes]));
+		let files = Database::get_message_files_in_conversation(&mgr.connection, conversation_id).unwrap();
+		assert_eq!(files, [(history[0].id, file)]);
+	}
+
+	#[tokio::test]
+	async fn resends_a_retried_failure_only_with_force() {
+		let (mgr, requests, conversation_id, id) = failed_request().await;
+		retry_error(&mgr, id, false).await.unwrap();
+		retry_error(&mgr, id, false).await.unwrap();
+		assert_eq!(requests.lock().unwrap().len(), 1);
+
+		retry_error(&mgr, id, true).await.unwrap();
+		assert_eq!(requests.lock().unwrap().len(), 2);
+		assert_eq!(Database::get_all_messages_in_conversation(&mgr.connection, conversation_id).unwrap().len(), 4);
+	}
+}
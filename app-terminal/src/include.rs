@@ -0,0 +1,347 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use globset::GlobBuilder;
+use ignore::WalkBuilder;
+use sha2::{Digest, Sha256};
+
+use openai::types::*;
+use database::*;
+
+use crate::attachment::estimate_tokens;
+use crate::error::*;
+use crate::types::*;
+
+/// Most files a single glob reference can include.
+static MAX_GLOB_FILES: usize = 100;
+
+/// A prompt with its `@path` references expanded.
+pub struct Inclusion {
+	pub text: String,
+	pub files: Vec<IncludedFile>,
+	/// What was included or skipped, for the user.
+	pub notes: Vec<String>
+}
+
+fn is_glob(reference: &str) -> bool {
+	reference.contains(['*', '?', '[', '{'])
+}
+
+/// Whether `rest` has a run of exactly `length` backticks, which closes a code span opened by such a run.
+fn closes_code_span(rest: &str, length: usize) -> bool {
+	rest.split(|c| c != '`').any(|run| run.len() == length)
+}
+
+/// Finds `@path` references outside code blocks and code spans: an `@` at the start of a line or after
+/// whitespace, up to the next whitespace. Returns the byte ranges of the references including the `@`.
+fn find_references(text: &str) -> Vec<(usize, usize)> {
+	let mut references = vec![];
+	let mut in_fence = false;
+	let mut offset = 0;
+	for line in text.split_inclusive('\n') {
+		let trimmed = line.trim_start();
+		if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+			in_fence = !in_fence;
+		}
+		else if !in_fence {
+			let mut previous = ' ';
+			let mut code_span = 0;
+			let mut index = 0;
+			while let Some(c) = line[index..].chars().next() {
+				if c == '`' {
+					let length = line[index..].len() - line[index..].trim_start_matches('`').len();
+					if code_span == length {
+						code_span = 0;
+					}
+					else if code_span == 0 && closes_code_span(&line[index + length..], length) {
+						code_span = length;
+					}
+					index += length;
+					previous = c;
+					continue
+				}
+				if c == '@' && code_span == 0 && previous.is_whitespace() {
+					let end = line[index..].find(char::is_whitespace).map_or(line.len(), |length| index + length);
+					if end > index + 1 {
+						references.push((offset + index, offset + end));
+					}
+				}
+				previous = c;
+				index += c.len_utf8();
+			}
+		}
+		offset += line.len();
+	}
+	references
+}
+
+/// Lists the files matching a glob, skipping hidden files and those ignored by `.gitignore` and similar files.
+fn glob_files(pattern: &str) -> Result<Vec<PathBuf>, String> {
+	let matcher = GlobBuilder::new(pattern).literal_separator(true).build().map_err(|err| err.to_string())?.compile_matcher();
+	let base: PathBuf = Path::new(pattern)
+		.components()
+		.take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+		.collect();
+	let root = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base };
+
+	let mut files = vec![];
+	for entry in WalkBuilder::new(&root).require_git(false).build().flatten() {
+		if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+			continue
+		}
+		let path = entry.path().strip_prefix("./").unwrap_or(entry.path());
+		if matcher.is_match(path) {
+			files.push(path.to_path_buf());
+		}
+	}
+	files.sort();
+	Ok(files)
+}
+
+/// The files a reference stands for. A reference that matches nothing is `None`, and is left as it is.
+fn resolve(reference: &str) -> Option<Result<Vec<PathBuf>, String>> {
+	if is_glob(reference) {
+		return match glob_files(reference) {
+			Ok(files) if files.is_empty() => None,
+			result => Some(result),
+		};
+	}
+	let path = Path::new(reference);
+	if path.is_dir() {
+		return Some(Err(format!("is a directory; use @{}/**/* to include its files", reference.trim_end_matches('/'))));
+	}
+	path.is_file().then(|| Ok(vec![path.to_path_buf()]))
+}
+
+pub fn sha256_of(data: &[u8]) -> String {
+	Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn language_of(path: &Path) -> &str {
+	let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+	match extension {
+		"rs" => "rust",
+		"py" => "python",
+		"js" | "mjs" | "cjs" => "javascript",
+		"ts" => "typescript",
+		"tsx" => "tsx",
+		"jsx" => "jsx",
+		"go" => "go",
+		"c" | "h" => "c",
+		"cc" | "cpp" | "cxx" | "hpp" => "cpp",
+		"cs" => "csharp",
+		"java" => "java",
+		"kt" => "kotlin",
+		"rb" => "ruby",
+		"php" => "php",
+		"swift" => "swift",
+		"sh" | "bash" => "bash",
+		"ps1" => "powershell",
+		"sql" => "sql",
+		"html" | "htm" => "html",
+		"css" => "css",
+		"json" => "json",
+		"toml" => "toml",
+		"yaml" | "yml" => "yaml",
+		"xml" => "xml",
+		"md" => "markdown",
+		"" => "text",
+		other => other,
+	}
+}
+
+/// A fence longer than any run of backticks in `content`, so that the content cannot close it.
+pub fn fence_for(content: &str) -> String {
+	let longest = content
+		.split(|c| c != '`')
+		.map(str::len)
+		.max()
+		.unwrap_or_default();
+	"`".repeat(longest.max(2) + 1)
+}
+
+/// Expands the `@path` and `@glob` references of a prompt. Each reference is replaced with the quoted path and
+/// the content of the files is appended as fenced blocks tagged with their language and path.
+/// Binary files, files larger than `max_file_size` bytes and files that would take the included content over
+/// `max_tokens` are skipped with a note.
+pub fn include_files(text: &str, max_file_size: u64, max_tokens: u64) -> Inclusion {
+	let mut inclusion = Inclusion { text: String::new(), files: vec![], notes: vec![] };
+	let mut blocks = vec![];
+	let mut seen = HashSet::new();
+	let mut tokens = 0;
+	let mut last = 0;
+
+	for (start, end) in find_references(text) {
+		let mut reference = &text[start + 1..end];
+		let mut resolved = resolve(reference);
+		if resolved.is_none() {
+			let trimmed = reference.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '\'', '"']);
+			if trimmed != reference && !trimmed.is_empty() {
+				reference = trimmed;
+				resolved = resolve(reference);
+			}
+		}
+		let files = match resolved {
+			Some(Ok(files)) => files,
+			Some(Err(err)) => {
+				inclusion.notes.push(format!("Cannot include @{}: {}", reference, err));
+				continue
+			},
+			None => {
+				if reference.contains('/') || is_glob(reference) {
+					inclusion.notes.push(format!("No file matches @{}; it was sent as it is.", reference));
+				}
+				continue
+			}
+		};
+		inclusion.text.push_str(&text[last..start]);
+		inclusion.text.push_str(&format!("`{}`", reference));
+		last = start + 1 + reference.len();
+
+		if files.len() > MAX_GLOB_FILES {
+			inclusion.notes.push(format!("@{} matches {} files; only the first {} were considered.", reference, files.len(), MAX_GLOB_FILES));
+		}
+		for path in files.into_iter().take(MAX_GLOB_FILES) {
+			let display = path.to_string_lossy().to_string();
+			let Ok(absolute) = std::fs::canonicalize(&path) else {
+				continue
+			};
+			if !seen.insert(absolute.clone()) {
+				continue
+			}
+			let size = std::fs::metadata(&path).map_or(0, |metadata| metadata.len());
+			if size > max_file_size {
+				inclusion.notes.push(format!("Skipped {}: larger than {} KB.", display, max_file_size / 1024));
+				continue
+			}
+			let data = match std::fs::read(&path) {
+				Ok(data) => data,
+				Err(err) => {
+					inclusion.notes.push(format!("Skipped {}: {}", display, err));
+					continue
+				}
+			};
+			if content_inspector::inspect(&data).is_binary() {
+				inclusion.notes.push(format!("Skipped {}: binary file.", display));
+				continue
+			}
+			let content = String::from_utf8_lossy(&data);
+			let file_tokens = estimate_tokens(&content, &[]);
+			if tokens + file_tokens > max_tokens {
+				inclusion.notes.push(format!("Skipped {}: included files would use more than {} tokens.", display, max_tokens));
+				continue
+			}
+			tokens += file_tokens;
+
+			let fence = fence_for(&content);
+			blocks.push(format!("{}{} {}\n{}\n{}", fence, language_of(&path), display, content.trim_end_matches('\n'), fence));
+			inclusion.notes.push(format!("Included {} ({} bytes, about {} tokens).", display, size, file_tokens));
+			inclusion.files.push(IncludedFile { path: absolute.to_string_lossy().to_string(), sha256: sha256_of(&data), size });
+		}
+	}
+	inclusion.text.push_str(&text[last..]);
+	if !blocks.is_empty() {
+		inclusion.text = format!("{}\n\n{}", inclusion.text.trim_end(), blocks.join("\n\n"));
+	}
+	inclusion
+}
+
+/// Lists the files included in the current conversation and whether they have changed since.
+pub fn print_included_files(mgr: &ChatManager) -> Result<(), MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let files = Database::get_message_files_in_conversation(&mgr.connection, session.conversation_id)?;
+	if files.is_empty() {
+		println!("No files were included in this conversation. Mention a file as @path/to/file or @src/**/*.rs in a message to include it.");
+		return Ok(());
+	}
+
+	let current_dir = std::env::current_dir()?;
+	for (message_id, file) in files.iter() {
+		let path = Path::new(&file.path);
+		let status = match std::fs::read(path) {
+			Ok(data) if sha256_of(&data) == file.sha256 => "unchanged".to_string(),
+			Ok(data) => format!("changed, now {} bytes", data.len()),
+			Err(_) => "missing".to_string(),
+		};
+		let display = path.strip_prefix(&current_dir).unwrap_or(path);
+		println!("#{} {} ({} bytes, sha256 {}): {}", message_id, display.display(), file.size, &file.sha256[..12], status);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// An empty directory of its own for a test, with `files` written into it.
+	fn test_dir(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("ai-include-test-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		let dir = dir.canonicalize().unwrap();
+		for (file, data) in files {
+			std::fs::write(dir.join(file), data).unwrap();
+		}
+		dir
+	}
+
+	fn paths(inclusion: &Inclusion) -> Vec<String> {
+		inclusion.files.iter().map(|file| Path::new(&file.path).file_name().unwrap().to_string_lossy().to_string()).collect()
+	}
+
+	#[test]
+	fn skips_ignored_files_in_globs() {
+		let dir = test_dir("glob", &[(".gitignore", b"generated.rs\n"), ("main.rs", b"fn main() {}\n"), ("generated.rs", b"fn generated() {}\n")]);
+		let pattern = format!("{}/*.rs", dir.display());
+		let inclusion = include_files(&format!("Review @{} please", pattern), 1024, 1000);
+
+		assert_eq!(paths(&inclusion), ["main.rs"]);
+		let main = dir.join("main.rs");
+		assert_eq!(inclusion.text, format!("Review `{}` please\n\n```rust {}\nfn main() {{}}\n```", pattern, main.display()));
+	}
+
+	#[test]
+	fn skips_binary_files() {
+		let dir = test_dir("binary", &[("image.bin", b"\x00\x01\x02\x03")]);
+		let inclusion = include_files(&format!("@{}/image.bin", dir.display()), 1024, 1000);
+
+		assert!(inclusion.files.is_empty());
+		assert_eq!(inclusion.notes, [format!("Skipped {}/image.bin: binary file.", dir.display())]);
+	}
+
+	#[test]
+	fn respects_the_file_size_and_token_limits() {
+		let dir = test_dir("limits", &[("a.txt", &[b'a'; 100]), ("b.txt", &[b'b'; 100]), ("c.txt", &[b'c'; 4096])]);
+		let inclusion = include_files(&format!("@{0}/a.txt @{0}/b.txt @{0}/c.txt", dir.display()), 2048, 80);
+
+		assert_eq!(paths(&inclusion), ["a.txt"]);
+		assert_eq!(inclusion.notes[1..], [
+			format!("Skipped {}/b.txt: included files would use more than 80 tokens.", dir.display()),
+			format!("Skipped {}/c.txt: larger than 2 KB.", dir.display())
+		]);
+	}
+
+	#[test]
+	fn fences_content_with_more_backticks_than_it_has() {
+		assert_eq!(fence_for("plain text"), "```");
+		assert_eq!(fence_for("```rust\nfn main() {}\n```"), "````");
+		assert_eq!(fence_for("a ````` run"), "``````");
+
+		let dir = test_dir("fence", &[("notes.md", b"```\ncode\n```\n")]);
+		let inclusion = include_files(&format!("@{}/notes.md", dir.display()), 1024, 1000);
+		let notes = dir.join("notes.md");
+		assert_eq!(inclusion.text, format!("`{}`\n\n````markdown {}\n```\ncode\n```\n````", notes.display(), notes.display()));
+	}
+
+	#[test]
+	fn leaves_code_spans_and_email_addresses_as_typed() {
+		let dir = test_dir("spans", &[("a.txt", b"a")]);
+		let text = format!("Run `cat @{0}/a.txt`, mail me@{0}/a.txt or see ``a ` @{0}/a.txt``.", dir.display());
+		let inclusion = include_files(&text, 1024, 1000);
+
+		assert!(inclusion.files.is_empty());
+		assert_eq!(inclusion.text, text);
+		let unclosed = format!("A ` and @{}/a.txt", dir.display());
+		assert_eq!(paths(&include_files(&unclosed, 1024, 1000)), ["a.txt"]);
+	}
+}
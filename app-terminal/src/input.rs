@@ -0,0 +1,231 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, CompletionType, Config, Context, Editor, EventHandler, Helper, KeyCode, KeyEvent, Modifiers};
+use sha2::{Digest, Sha256};
+
+use crate::commands::COMMANDS;
+use crate::error::*;
+
+/// Commands whose argument is the ID of a message in the current conversation.
+static MESSAGE_COMMANDS: &[&str] = &["/pin", "/unpin", "/edit", "/quote", "/revisions", "/code", "/apply"];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum EditMode {
+	Emacs,
+	Vi,
+}
+
+/// Completes slash commands, message IDs after the commands that take one, paths after `@`, and conversation
+/// IDs while a conversation is being chosen. A line ending with a backslash continues on the next line.
+#[derive(Default)]
+struct InputHelper {
+	conversations: Vec<(u32, String)>,
+	messages: Vec<u32>,
+	files: FilenameCompleter
+}
+
+impl Completer for InputHelper {
+	type Candidate = Pair;
+
+	fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+		let typed = &line[..pos];
+		let start = typed.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+		let word = &typed[start..];
+
+		if start == 0 && word.starts_with('/') {
+			let candidates = COMMANDS
+				.iter()
+				.filter_map(|(usage, _)| usage.split_whitespace().next())
+				.filter(|name| name.starts_with(word))
+				.map(|name| Pair { display: name.into(), replacement: format!("{} ", name) })
+				.collect();
+			return Ok((0, candidates));
+		}
+
+		if let Some(path) = word.strip_prefix('@') {
+			let (offset, candidates) = self.files.complete_path(path, path.len())?;
+			return Ok((start + 1 + offset, candidates));
+		}
+
+		let command = typed.split_whitespace().next().unwrap_or_default();
+		if start > 0 && MESSAGE_COMMANDS.contains(&command) && typed[..start].split_whitespace().count() == 1 {
+			let candidates = self.messages
+				.iter()
+				.rev()
+				.map(|id| id.to_string())
+				.filter(|id| id.starts_with(word.trim_start_matches('#')))
+				.map(|id| Pair { display: id.clone(), replacement: id })
+				.collect();
+			return Ok((start, candidates));
+		}
+
+		if start == 0 && word.chars().all(|c| c.is_ascii_digit()) {
+			let candidates = self.conversations
+				.iter()
+				.filter(|(id, _)| id.to_string().starts_with(word))
+				.map(|(id, title)| Pair { display: format!("{}: {}", id, title), replacement: id.to_string() })
+				.collect();
+			return Ok((0, candidates));
+		}
+		Ok((pos, vec![]))
+	}
+}
+
+impl Hinter for InputHelper {
+	type Hint = String;
+}
+
+impl Highlighter for InputHelper {}
+
+impl Validator for InputHelper {
+	fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+		if ctx.input().ends_with('\\') {
+			return Ok(ValidationResult::Incomplete);
+		}
+		Ok(ValidationResult::Valid(None))
+	}
+}
+
+impl Helper for InputHelper {}
+
+/// Removes the backslashes that continued an entry on the next line.
+fn join_continued_lines(line: &str) -> String {
+	line.replace("\\\r\n", "\n").replace("\\\n", "\n")
+}
+
+/// Line editor of the REPL, with the input history of the current profile.
+pub struct LineReader {
+	editor: Editor<InputHelper, FileHistory>,
+	history_path: Option<PathBuf>
+}
+
+/// File holding the input history of the profile using `api_key`, next to the database. The file is named
+/// after a hash of the key so that the key itself is not written to disk.
+pub fn history_path(db_path: &Path, api_key: &str) -> PathBuf {
+	let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+	name.push(".history");
+	let digest = Sha256::digest(api_key.trim().as_bytes());
+	let profile: String = digest.iter().take(8).map(|byte| format!("{:02x}", byte)).collect();
+	db_path.with_file_name(name).join(format!("{}.txt", profile))
+}
+
+impl LineReader {
+	/// Creates a line editor keeping up to `history_size` entries, which are loaded from and saved to
+	/// `history_path` when one is given.
+	pub fn new(edit_mode: EditMode, history_size: usize, history_path: Option<PathBuf>) -> Result<Self, MainError> {
+		let config = Config::builder()
+			.edit_mode(match edit_mode {
+				EditMode::Emacs => rustyline::EditMode::Emacs,
+				EditMode::Vi => rustyline::EditMode::Vi,
+			})
+			.max_history_size(history_size.max(1))?
+			.history_ignore_dups(true)?
+			.history_ignore_space(true)
+			.completion_type(CompletionType::List)
+			.build();
+		let mut editor = Editor::with_config(config)?;
+		editor.set_helper(Some(InputHelper::default()));
+		editor.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), EventHandler::Simple(Cmd::Newline));
+
+		let history_path = history_path.filter(|_| history_size > 0);
+		if let Some(path) = &history_path {
+			if path.exists() {
+				editor.load_history(path)?;
+			}
+		}
+		Ok(Self { editor, history_path })
+	}
+
+	/// Reads one entry, which may span several lines. Returns `None` when the user presses Ctrl+C or Ctrl+D,
+	/// or when the input ends.
+	pub fn read(&mut self, prompt: &str) -> Result<Option<String>, MainError> {
+		let line = match self.editor.readline(prompt) {
+			Ok(line) => line,
+			Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(None),
+			Err(err) => return Err(err.into()),
+		};
+		let line = join_continued_lines(&line);
+
+		if !line.trim().is_empty() && self.editor.add_history_entry(line.as_str())? {
+			if let Some(path) = &self.history_path {
+				if let Some(dir) = path.parent() {
+					std::fs::create_dir_all(dir)?;
+				}
+				self.editor.append_history(path)?;
+			}
+		}
+		Ok(Some(line))
+	}
+
+	/// Asks a yes or no question, without recording the answer in the history. Anything but y or yes is a no.
+	pub fn confirm(&mut self, question: &str) -> Result<bool, MainError> {
+		let answer = match self.editor.readline(question) {
+			Ok(answer) => answer,
+			Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(false),
+			Err(err) => return Err(err.into()),
+		};
+		Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+	}
+
+	/// Sets the conversations offered by tab completion while one is being chosen.
+	pub fn set_conversations(&mut self, conversations: Vec<(u32, String)>) {
+		if let Some(helper) = self.editor.helper_mut() {
+			helper.conversations = conversations;
+		}
+	}
+
+	/// Sets the message IDs offered by tab completion after commands such as /pin.
+	pub fn set_messages(&mut self, messages: Vec<u32>) {
+		if let Some(helper) = self.editor.helper_mut() {
+			helper.messages = messages;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rustyline::history::DefaultHistory;
+
+	use super::*;
+
+	fn complete(helper: &InputHelper, line: &str) -> (usize, Vec<String>) {
+		let history = DefaultHistory::new();
+		let (start, candidates) = helper.complete(line, line.len(), &Context::new(&history)).unwrap();
+		(start, candidates.into_iter().map(|pair| pair.replacement).collect())
+	}
+
+	#[test]
+	fn keeps_a_history_per_profile_without_the_key() {
+		let db = Path::new("/data/ai.db");
+		let first = history_path(db, "sk-first");
+		assert_eq!(first.parent(), Some(Path::new("/data/ai.db.history")));
+		assert_eq!(first, history_path(db, "sk-first\n"));
+		assert_ne!(first, history_path(db, "sk-second"));
+		assert!(!first.to_string_lossy().contains("sk-first"));
+	}
+
+	#[test]
+	fn joins_continued_lines() {
+		assert_eq!(join_continued_lines("fn main() {\\\n\tprintln!();\\\r\n}"), "fn main() {\n\tprintln!();\n}");
+		assert_eq!(join_continued_lines("a \\ b"), "a \\ b");
+	}
+
+	#[test]
+	fn completes_commands_messages_and_conversations() {
+		let helper = InputHelper { conversations: vec![(12, "Rust".into()), (3, "SQL".into())], messages: vec![7, 71, 9], ..Default::default() };
+
+		assert_eq!(complete(&helper, "/pi"), (0, vec!["/pin ".to_string(), "/pins ".to_string()]));
+		assert_eq!(complete(&helper, "/pin 7"), (5, vec!["71".to_string(), "7".to_string()]));
+		assert_eq!(complete(&helper, "/pin #"), (5, vec!["9".to_string(), "71".to_string(), "7".to_string()]));
+		assert!(complete(&helper, "/pin 7 7").1.is_empty());
+		assert_eq!(complete(&helper, "1"), (0, vec!["12".to_string()]));
+		assert!(complete(&helper, "hello 1").1.is_empty());
+	}
+}
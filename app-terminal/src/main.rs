@@ -1,6 +1,6 @@
-use std::{io::Write, path::PathBuf};
-use clap::Parser;
-use serde_json::json;
+use std::{io::Write, path::PathBuf, time::Instant};
+use clap::{Parser, Subcommand};
+use rusqlite::Connection;
 use spinners::{Spinner, Spinners};
 
 use openai::prelude::*;
@@ -10,6 +10,48 @@ mod error;
 use error::*;
 mod types;
 use types::*;
+mod db;
+use db::*;
+mod pricing;
+mod stats;
+use stats::*;
+mod error_log;
+use error_log::*;
+mod attachment;
+use attachment::*;
+mod commands;
+use commands::*;
+mod context;
+use context::*;
+mod recall;
+use recall::*;
+mod persona;
+use persona::*;
+mod settings;
+use settings::*;
+mod conversation;
+use conversation::*;
+mod editor;
+mod compose;
+mod include;
+mod shell;
+use shell::*;
+mod codeblock;
+mod patch;
+mod revision;
+mod budget;
+use budget::*;
+mod input;
+use input::*;
+mod markdown;
+use markdown::*;
+mod chat;
+use chat::*;
+mod tui;
+mod serve;
+mod proxy;
+#[cfg(test)]
+mod test_support;
 
 static SEPARATOR: &str = "===========================================================================";
 
@@ -32,46 +74,232 @@ struct CommandLineParser {
 	database: String,
 
 	// Max token
-	#[arg(long, value_name = "Size", default_value = "3800")]
-	max_token: u64,
+	#[arg(long, value_name = "Size [default: 3800]")]
+	max_token: Option<u64>,
 
 	// Max remembered conversation
-	#[arg(long, value_name = "Remembered Conversation", default_value = "32")]
-	max_dialog: u64,
+	#[arg(long, value_name = "Remembered Conversation [default: 32]")]
+	max_dialog: Option<u64>,
 
 	// Proxy
 	#[arg(short, long, value_name = "Proxy Address, for example: \"socks5://127.0.0.1:1080\"")]
 	proxy: Option<String>,
 
 	// Model
-	#[arg(short, long, value_name = "Model, such as \"gpt-3.5-turbo\" and \"gpt-4\"", default_value = "gpt-4")]
-	model: String,
+	#[arg(short, long, value_name = "Model, such as \"gpt-3.5-turbo\" and \"gpt-4\" [default: gpt-4]")]
+	model: Option<String>,
+
+	// Sampling temperature
+	#[arg(long, value_name = "Temperature")]
+	temperature: Option<f32>,
+
+	// Nucleus sampling probability mass
+	#[arg(long, value_name = "Probability")]
+	top_p: Option<f32>,
+
+	// System prompt of the conversation
+	#[arg(long, value_name = "Prompt", conflicts_with = "system_file")]
+	system: Option<String>,
+
+	// Read the system prompt of the conversation from a file
+	#[arg(long, value_name = "Path")]
+	system_file: Option<String>,
+
+	// Directory of persona and template Markdown files
+	#[arg(long, value_name = "Directory", default_value = "$personas")]
+	persona_dir: String,
+
+	// Start with a persona's system prompt, model and parameters
+	#[arg(long, value_name = "Name")]
+	persona: Option<String>,
+
+	// Value for a {{placeholder}} in the persona, as key=value
+	#[arg(long = "var", value_name = "key=value")]
+	vars: Vec<String>,
+
+	// Base URL of an OpenAI-compatible API
+	#[arg(long, value_name = "URL", default_value = DEFAULT_API_BASE)]
+	api_base: String,
+
+	// Model used to embed messages for semantic search
+	#[arg(long, value_name = "Model", default_value = "text-embedding-3-small")]
+	embedding_model: String,
+
+	// Inject this many relevant snippets from other conversations into the context
+	#[arg(long, value_name = "Count", default_value = "0")]
+	recall_k: usize,
+
+	// How to handle messages that no longer fit in the context [default: truncate]
+	#[arg(long, value_enum)]
+	context_strategy: Option<ContextStrategy>,
+
+	// Save the settings given on the command line to a resumed conversation
+	#[arg(long)]
+	update_settings: bool,
+
+	// Delete error logs older than this many days on startup
+	#[arg(long, value_name = "Days")]
+	error_retention: Option<u32>,
+
+	// Permanently delete conversations that have been in the trash for this many days on startup
+	#[arg(long, value_name = "Days")]
+	trash_retention: Option<u32>,
+
+	// Warn when a request would use this share of a budget
+	#[arg(long, value_name = "Percent", default_value = "80")]
+	budget_warn: u32,
+
+	// Send requests even when they exceed a budget
+	#[arg(long)]
+	ignore_budget: bool,
+
+	// Skip files larger than this when expanding @path references in messages
+	#[arg(long, value_name = "KB", default_value = "256")]
+	include_max_size: u64,
+
+	// Most tokens that the files included with @path references may use [default: half of --max-token]
+	#[arg(long, value_name = "Tokens")]
+	include_max_tokens: Option<u64>,
+
+	// Run !commands without asking for confirmation
+	#[arg(long)]
+	no_shell_confirm: bool,
+
+	// Stop !commands that run longer than this
+	#[arg(long, value_name = "Seconds", default_value = "60")]
+	shell_timeout: u64,
+
+	// Keep at most this many lines of the output of a !command in a message, cutting lines in the middle
+	#[arg(long, value_name = "Lines", default_value = "200")]
+	shell_max_lines: usize,
+
+	// Print replies as they are instead of rendering Markdown
+	#[arg(long)]
+	raw: bool,
+
+	// Key bindings of the line editor
+	#[arg(long, value_enum, default_value = "emacs")]
+	edit_mode: EditMode,
+
+	// Number of inputs remembered per profile, 0 to disable; the history is not saved for encrypted databases
+	#[arg(long, value_name = "Count", default_value = "1000")]
+	history_size: usize,
+
+	// Number of automatic backups (taken before migrations and restores) to keep, 0 to disable
+	#[arg(long, value_name = "Count", default_value = "5")]
+	backup_retention: usize,
+
+	#[command(subcommand)]
+	command: Option<Command>,
 }
 
-fn init() -> Result<ChatManager, MainError> {
-	let args = CommandLineParser::parse();
-	let exe_dir = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
-	let cw_dir = std::env::current_dir().unwrap().to_path_buf();
+#[derive(Debug, Subcommand)]
+enum Command {
+	/// Maintain the conversation database
+	Db {
+		#[command(subcommand)]
+		action: DatabaseCommand
+	},
+
+	/// Archive, trash, restore and purge conversations
+	Conversation {
+		#[command(subcommand)]
+		action: ConversationCommand
+	},
+
+	/// Set daily or monthly spending limits
+	Budget {
+		#[command(subcommand)]
+		action: BudgetCommand
+	},
+
+	/// Report token usage, cost, latency and error rates
+	Stats(StatsArgs),
+
+	/// Inspect, retry and purge failed requests
+	Errors {
+		#[command(subcommand)]
+		action: Option<ErrorCommand>
+	},
+
+	/// Search past conversations by meaning
+	Recall(RecallArgs),
+
+	/// Browse personas and prompt templates
+	Persona {
+		#[command(subcommand)]
+		action: PersonaCommand
+	},
+
+	/// Browse and continue conversations in a full-screen interface
+	Tui,
+
+	/// Serve conversations over HTTP to other tools, such as editor plugins
+	Serve(serve::ServeArgs),
+
+	/// Relay OpenAI-compatible chat completion requests to the API, recording them as conversations
+	Proxy(proxy::ProxyArgs),
+}
+
+fn exit_on_argument_error(error: MainError) -> ! {
+	match error {
+		MainError::ArgumentError(error_argument) => match error_argument.argument.as_str() {
+			"api_key" => {
+				println!("Please provide an API Key. See -h for more details.");
+				std::process::exit(1);
+			},
+			"passphrase" | "persona" | "system_file" | "token" => {
+				println!("{}", error_argument);
+				std::process::exit(1);
+			},
+			_ => panic!("{}", error_argument)
+		},
+		_ => panic!("{}", error)
+	}
+}
+
+fn resolve_path(path: &str) -> PathBuf {
+	let mut resolved: PathBuf;
+	if let Some(relative) = path.strip_prefix('$') {
+		resolved = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+		resolved.push(relative);
+	}
+	else {
+		resolved = std::env::current_dir().unwrap();
+		resolved.push(path);
+	}
+	resolved
+}
+
+fn open_database(args: &CommandLineParser) -> Result<(Connection, Option<String>), MainError> {
+	let db_dir = resolve_path(&args.database);
 
+	let existed = db_dir.metadata().is_ok_and(|metadata| metadata.len() > 0);
+	let conn = open_connection(&db_dir);
+	if existed {
+		backup_before_migration(&conn, &db_dir, args.backup_retention)?;
+	}
+	CurrentSchema::init_current_schema(&conn)?;
+	let passphrase = unlock_database(&conn)?;
+
+	if let Some(days) = args.error_retention {
+		Database::purge_error_logs(&conn, Some(days), false)?;
+	}
+	if let Some(days) = args.trash_retention {
+		Database::purge_conversations(&conn, None, Some(days))?;
+	}
+
+	Ok((conn, passphrase))
+}
+
+fn init(args: CommandLineParser, conn: Connection) -> Result<ChatManager, MainError> {
     let mut api_key = String::new();
 	
     if let Some(key) = args.key {
         api_key = key;
     }
-    else {
-		let mut api_dir: PathBuf;
-		if args.key_file.starts_with("$") {
-			api_dir = exe_dir.clone();
-			api_dir.push(&args.key_file[1..]);
-		}
-		else {
-			api_dir = cw_dir.clone();
-			api_dir.push(args.key_file);
-		}
-
-		if let Ok(str) = std::fs::read_to_string(&api_dir) {
-			api_key = str;
-		}
+    else if let Ok(str) = std::fs::read_to_string(resolve_path(&args.key_file)) {
+		api_key = str;
     }
 
     if api_key.is_empty() {
@@ -79,39 +307,84 @@ fn init() -> Result<ChatManager, MainError> {
         return Err(MainError::ArgumentError(error));
     }
 
-	let mut db_dir: PathBuf;
-	if args.database.starts_with("$") {
-		db_dir = exe_dir.clone();
-		db_dir.push(&args.database[1..]);
+	let proxy = args.proxy;
+	let persona_dir = resolve_path(&args.persona_dir);
+
+	// Settings given explicitly, either directly or through a persona, override those stored with a conversation
+	let mut overrides = ConversationSettings {
+		model: args.model,
+		params: CompletionParams::default(),
+		context_strategy: args.context_strategy.map(strategy_name),
+		max_token: args.max_token,
+		max_dialog: args.max_dialog,
+		system_prompt: None,
+		persona: args.persona.clone()
+	};
+	if let Some(name) = &args.persona {
+		let persona = load_persona(&persona_dir, name)?;
+		let vars = parse_vars(&args.vars).map_err(|err| ArgumentError::new("persona", &err))?;
+		overrides.system_prompt = Some(render(&persona.prompt, &vars).map_err(|err| ArgumentError::new("persona", &err))?);
+		overrides.model = overrides.model.or(persona.model);
+		overrides.params = persona.params;
 	}
-	else {
-		db_dir = cw_dir.clone();
-		db_dir.push(args.database);
+	if let Some(system) = args.system {
+		overrides.system_prompt = Some(system);
 	}
-
-	let conn = open_connection(&db_dir);
-	Database::init_current_schema(&conn)?;
-
-	let max_dialog = args.max_dialog;
-	let max_token = args.max_token;
-	let proxy = args.proxy;
-	let model = args.model;
-
-	Ok(ChatManager {
-		max_token,
-		max_dialog,
+	else if let Some(path) = &args.system_file {
+		let system = std::fs::read_to_string(resolve_path(path))
+			.map_err(|err| ArgumentError::new("system_file", &format!("{}: {}", path, err)))?;
+		overrides.system_prompt = Some(system);
+	}
+	overrides.params.temperature = args.temperature.or(overrides.params.temperature);
+	overrides.params.top_p = args.top_p.or(overrides.params.top_p);
+
+	let api_base = args.api_base;
+	let embedding_model = args.embedding_model;
+	let recall_k = args.recall_k;
+	let update_settings = args.update_settings;
+	let budget_warn_percent = args.budget_warn;
+	let ignore_budget = args.ignore_budget;
+	let include_max_size = args.include_max_size * 1024;
+	let include_max_tokens = args.include_max_tokens;
+	let shell_confirm = !args.no_shell_confirm;
+	let shell_timeout = args.shell_timeout;
+	let shell_max_lines = args.shell_max_lines;
+	let raw = args.raw;
+
+	let mut mgr = ChatManager {
+		max_token: DEFAULT_MAX_TOKEN,
+		max_dialog: DEFAULT_MAX_DIALOG,
 		api_key,
 		proxy,
-		model,
+		api_base,
+		model: DEFAULT_MODEL.into(),
+		params: CompletionParams::default(),
+		context_strategy: ContextStrategy::Truncate,
+		embedding_model,
+		recall_k,
+		persona_dir,
+		overrides,
+		update_settings,
+		budget_warn_percent,
+		ignore_budget,
+		include_max_size,
+		include_max_tokens,
+		shell_confirm,
+		shell_timeout,
+		shell_max_lines,
+		raw,
 		connection: conn,
 		current_session: None
-	})
+	};
+	let overrides = mgr.overrides.clone();
+	apply_settings(&mut mgr, &overrides);
+	Ok(mgr)
 }
 
-fn create_session(mgr: &ChatManager) -> Result<ChatSession, MainError> {
+/// Lets the user resume or start a conversation. Returns `None` if the user quits instead.
+fn create_session(mgr: &mut ChatManager, reader: &mut LineReader) -> Result<Option<ChatSession>, MainError> {
 	let conversation_id: u32;
 	let mut all_conv_id: Vec<u32> = vec![];
-	let mut all_messages: Vec<SavedMessage> = vec![];
 
 	let all_conversations = Database::get_all_conversations(&mgr.connection, &mgr.api_key)?;
 	println!("You have {} conversation(s) currently saved.", all_conversations.len());
@@ -121,11 +394,13 @@ fn create_session(mgr: &ChatManager) -> Result<ChatSession, MainError> {
 	}
 
 	println!("Enter a number to continue the desired conversation, or enter a piece of text to create a new one: ");
+	reader.set_conversations(all_conversations.iter().map(|conv| (conv.id, conv.title.clone())).collect());
 
 	loop {
-		let mut prompt = String::new();
-        std::io::stdin().read_line(&mut prompt).unwrap();
-        prompt = prompt.trim().to_owned();
+		let Some(prompt) = reader.read("")? else {
+			return Ok(None);
+		};
+		let prompt = prompt.trim().to_owned();
 
 		if prompt.is_empty() {
             continue
@@ -138,109 +413,146 @@ fn create_session(mgr: &ChatManager) -> Result<ChatSession, MainError> {
 				continue
 			}
 			conversation_id = number;
-			all_messages = Database::get_all_messages_in_conversation(&mgr.connection, conversation_id)?;
 		}
 		else {
 			conversation_id = Database::add_conversation(&mgr.connection, &prompt, &mgr.api_key)?;
 		}
 
-		for msg in all_messages.iter() {
-			println!("{}\n{}: {}", SEPARATOR,
-				match &msg.role[..] {
-					"assistant" => "ChatGPT",
-					"user" => "You",
-					"system" => "System",
-					_ => panic!("Database error! Message ID {} does not have a valid role!", msg.id)
-				}, msg.content.trim());
+		break;
+	}
+
+	let (session, notes) = open_session(mgr, conversation_id)?;
+	for note in notes.iter() {
+		println!("{}", note);
+	}
+	if let Some(system_prompt) = &session.system_prompt {
+		println!("{}\nSystem: {}", SEPARATOR, system_prompt.trim());
+	}
+	let runs = Database::get_message_commands_in_conversation(&mgr.connection, conversation_id)?;
+	for msg in session.history.iter() {
+		println!("{}", SEPARATOR);
+		match role_of(msg) {
+			MessageRole::Assistant => print_reply(mgr.raw, &format!("{} (#{})", speaker_of(msg), msg.id), &msg.content),
+			_ => println!("{} (#{}): {}", speaker_of(msg), msg.id, msg.content.trim()),
+		}
+		for attachment in msg.attachments.iter() {
+			println!("[Attachment: {}]", describe_attachment(attachment));
+		}
+		for (_, run) in runs.iter().filter(|(message_id, _)| *message_id == msg.id) {
+			println!("[Command: {} ({})]", run.command, describe_run(run));
 		}
+	}
 
-		break;
+	if let Some(draft) = Database::get_draft(&mgr.connection, conversation_id)? {
+		println!("{}\nYou have an unsent draft of {} line(s). Type /edit to continue it.", SEPARATOR, draft.lines().count());
 	}
 
-	Ok(ChatSession { conversation_id, history: all_messages, prompt: String::new() })
+	reader.set_conversations(vec![]);
+	Ok(Some(session))
 }
 
 async fn execute_chat(mgr: &mut ChatManager) -> Result<(), MainError> {
-	let mut session = mgr.current_session.as_mut().unwrap();
 	let mut spinner = Spinner::new(
 		Spinners::Dots,
 		"ChatGPT is thinking...".to_string(),
 	);
 
-	let mut context: Vec<Message> = vec![];
-	let mut i = 0;
-	let mut j = 0;
-	'context_filler: for msg in session.history.iter().rev() {
-		i += 1;
-		j += msg.content.len() as u64 / 2;
-		if i > mgr.max_dialog || j > mgr.max_token {
-			break 'context_filler;
+	let prepared = match prepare_chat(mgr, true).await? {
+		Preparation::Ready(prepared) => prepared,
+		Preparation::Refused(refusal) => {
+			spinner.stop_with_message(SEPARATOR.into());
+			println!("{}\nThe message was not sent. Type /override to send it anyway.", refusal);
+			return Ok(());
 		}
-		let role_str = &msg.role[..];
-		context.insert(0, Message {
-			role: match role_str {
-				"assistant" => MessageRole::Assistant,
-				"user" => MessageRole::User,
-				"system" => MessageRole::System,
-				_ => panic!("Database error! Message ID {} does not have a valid role!", msg.id)
-			},
-			content: msg.content.clone()
-		});
-	}
-
-	context.push(Message { role: MessageRole::User, content: session.prompt.clone() });
-
-	let openai_response = get_response(&context, &mgr.api_key, &mgr.proxy, &mgr.model).await;
-	match openai_response {
-		Ok(response) => match response {
-			OpenAIResponse::Success(completion_response) => {
-				Database::add_client_message(&mgr.connection, session.conversation_id, &session.prompt)?;
-				Database::add_server_message(&mgr.connection, session.conversation_id, &completion_response)?;
-				session.history = Database::get_all_messages_in_conversation(&mgr.connection, session.conversation_id)?;
-				spinner.stop_with_message(SEPARATOR.into());
-
-				println!("ChatGPT: {}", completion_response.msg().trim());
-			},
-			OpenAIResponse::Failure(openai_error) => {
-				Database::add_error_log(&mgr.connection, &mgr.api_key, &context, &json!(openai_error).to_string(), Some(&openai_error))?;
-				spinner.stop_with_message(SEPARATOR.into());
+	};
 
-				println!("Error: {}", openai_error.error.message);
-			}
-		},
-		Err(err) => {
-			Database::add_error_log(&mgr.connection, &mgr.api_key, &context, &err, None)?;
+	// The spinner runs until the first text of the reply arrives, which is then printed as it streams in
+	let mut spinner = Some(spinner);
+	let mut printer = ReplyPrinter::new(mgr.raw, "ChatGPT");
+	let started = Instant::now();
+	let openai_response = get_response_stream(&prepared.context, &mgr.api_key, &mgr.proxy, &mgr.api_base, &mgr.model, &mgr.params, |delta| {
+		let output = printer.push(delta);
+		if output.is_empty() {
+			return;
+		}
+		if let Some(mut spinner) = spinner.take() {
 			spinner.stop_with_message(SEPARATOR.into());
-
-			println!("Error: {}", err);
 		}
+		print!("{}", output);
+		let _ = std::io::stdout().flush();
+	}).await;
+	let latency_ms = started.elapsed().as_millis() as u64;
+	let outcome = record_chat(mgr, &prepared.context, openai_response, latency_ms)?;
+	if let Some(mut spinner) = spinner.take() {
+		spinner.stop_with_message(SEPARATOR.into());
 	}
 
+	match outcome {
+		ChatOutcome::Reply => print!("{}", printer.finish()),
+		ChatOutcome::Error(error) => println!("Error: {}", error),
+	}
+	for note in prepared.notes.iter().chain(prepared.budget_warnings.iter()) {
+		println!("{}", note);
+	}
 	Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), MainError> {
-	let mut mgr: ChatManager;
-	match init() {
-		Ok(manager) => mgr = manager,
-		Err(error) => match error {
-			MainError::ArgumentError(error_argument) => match error_argument.argument.as_str() {
-				"api_key" => {
-					println!("Please provide an API Key. See -h for more details.");
-					std::process::exit(1);
-				},
-				_ => panic!("{}", error_argument)
+	let mut args = CommandLineParser::parse();
+	let (mut conn, passphrase) = open_database(&args).unwrap_or_else(|error| exit_on_argument_error(error));
+
+	if let Some(command) = args.command.take() {
+		match command {
+			Command::Db { action } => run_database_command(&mut conn, &resolve_path(&args.database), passphrase, args.backup_retention, action)
+				.unwrap_or_else(|error| exit_on_argument_error(error)),
+			Command::Conversation { action } => run_conversation_command(&conn, action)?,
+			Command::Stats(stats_args) => run_stats_command(&conn, stats_args)?,
+			Command::Errors { action: Some(ErrorCommand::Retry { id, force }) } => {
+				let mgr = init(args, conn).unwrap_or_else(|error| exit_on_argument_error(error));
+				retry_error(&mgr, id, force).await?;
+			},
+			Command::Errors { action } => run_error_command(&conn, action)
+				.unwrap_or_else(|error| exit_on_argument_error(error)),
+			Command::Persona { action } => run_persona_command(&resolve_path(&args.persona_dir), action)
+				.unwrap_or_else(|error| exit_on_argument_error(error)),
+			Command::Budget { action } => {
+				let mgr = init(args, conn).unwrap_or_else(|error| exit_on_argument_error(error));
+				run_budget_command(&mgr, action)?;
+			},
+			Command::Recall(recall_args) => {
+				let mgr = init(args, conn).unwrap_or_else(|error| exit_on_argument_error(error));
+				run_recall_command(&mgr, recall_args).await?;
+			},
+			Command::Tui => {
+				let mut mgr = init(args, conn).unwrap_or_else(|error| exit_on_argument_error(error));
+				tui::run_tui(&mut mgr).await?;
+			},
+			Command::Serve(serve_args) => {
+				let mgr = init(args, conn).unwrap_or_else(|error| exit_on_argument_error(error));
+				serve::run_server(mgr, serve_args).await.unwrap_or_else(|error| exit_on_argument_error(error));
+			},
+			Command::Proxy(proxy_args) => {
+				let mgr = init(args, conn).unwrap_or_else(|error| exit_on_argument_error(error));
+				proxy::run_proxy(mgr, proxy_args).await.unwrap_or_else(|error| exit_on_argument_error(error));
 			},
-			_ => panic!("{}", error)
 		}
-	};
+		return Ok(());
+	}
+
+	let (db_path, edit_mode, history_size) = (resolve_path(&args.database), args.edit_mode, args.history_size);
+	let mut mgr: ChatManager = init(args, conn).unwrap_or_else(|error| exit_on_argument_error(error));
+	let history = passphrase.is_none().then(|| history_path(&db_path, &mgr.api_key));
+	let mut reader = LineReader::new(edit_mode, history_size, history)?;
 	
-    println!("Welcome to OpenAI Playground. Press Ctrl+C to exit the program.");
+    println!("Welcome to OpenAI Playground. Press Ctrl+C or Ctrl+D to exit the program.");
+	println!("End a line with \\ or press Alt+Enter to continue on the next line. Press Tab to complete commands.");
+	println!("Mention a file as @path/to/file or @src/**/*.rs to include it in your message. Type !command to include the output of a command.");
 
 	while mgr.current_session.is_none() {
-		match create_session(&mgr) {
-			Ok(session) => mgr.current_session = Some(session),
+		match create_session(&mut mgr, &mut reader) {
+			Ok(Some(session)) => mgr.current_session = Some(session),
+			Ok(None) => return Ok(()),
 			Err(error) => {
 				panic!("{}", error);
 			}
@@ -250,20 +562,46 @@ async fn main() -> Result<(), MainError> {
 	println!("{}", SEPARATOR);
 
     loop {
-        print!("> ");
-        std::io::stdout().flush().unwrap();
-
-		let mut prompt = String::new();
-        std::io::stdin().read_line(&mut prompt).unwrap();
-        prompt = prompt.trim().to_owned();
+		reader.set_messages(mgr.current_session.as_ref().unwrap().history.iter().map(|msg| msg.id).collect());
+		let Some(prompt) = reader.read("> ")? else {
+			return Ok(());
+		};
+		let prompt = prompt.trim().to_owned();
 
 		mgr.current_session.as_mut().unwrap().prompt = prompt.clone();
 
         if prompt.is_empty() {
             continue
         }
-		else if prompt.starts_with("/") {
-			println!("This is a command: {}. Custom commands are not implemented yet.", &prompt[1..]);
+		else if let Some(command) = prompt.strip_prefix('/') {
+			match execute_command(&mut mgr, &mut reader, command).await {
+				Ok(Some(expanded)) => {
+					println!("You: {}", expanded.trim());
+					mgr.current_session.as_mut().unwrap().prompt = expanded;
+					if let Err(error) = execute_chat(&mut mgr).await {
+						panic!("{}", error)
+					}
+				},
+				Ok(None) => {},
+				Err(error) => panic!("{}", error)
+			}
+		}
+		else if let Some(command) = prompt.strip_prefix('!') {
+			let (command, send_now) = match command.strip_prefix('!') {
+				Some(command) => (command, true),
+				None => (command, false),
+			};
+			match execute_shell_command(&mut mgr, &mut reader, command.trim(), send_now).await {
+				Ok(Some(instruction)) => {
+					println!("You: {}", instruction);
+					mgr.current_session.as_mut().unwrap().prompt = instruction;
+					if let Err(error) = execute_chat(&mut mgr).await {
+						panic!("{}", error)
+					}
+				},
+				Ok(None) => {},
+				Err(error) => panic!("{}", error)
+			}
 		}
         else if let Err(error) = execute_chat(&mut mgr).await {
 			panic!("{}", error)
@@ -0,0 +1,569 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+use unicode_width::UnicodeWidthStr;
+
+static THEME: &str = "base16-ocean.dark";
+static DEFAULT_WIDTH: usize = 80;
+
+fn syntax_set() -> &'static SyntaxSet {
+	static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+	SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+	static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+	&THEMES.get_or_init(ThemeSet::load_defaults).themes[THEME]
+}
+
+/// Whether output may be styled: `NO_COLOR` is not set and stdout is a terminal.
+pub fn use_color() -> bool {
+	std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && std::io::stdout().is_terminal()
+}
+
+pub fn terminal_width() -> usize {
+	terminal_size::terminal_size().map_or(DEFAULT_WIDTH, |(width, _)| width.0 as usize).max(20)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Style {
+	bold: bool,
+	dim: bool,
+	italic: bool,
+	underline: bool,
+	strike: bool,
+	color: Option<&'static str>
+}
+
+static CODE: Style = Style { bold: false, dim: false, italic: false, underline: false, strike: false, color: Some("33") };
+static DIM: Style = Style { bold: false, dim: true, italic: false, underline: false, strike: false, color: None };
+
+fn paint(text: &str, style: Style) -> String {
+	let mut codes = vec![];
+	for (enabled, code) in [(style.bold, "1"), (style.dim, "2"), (style.italic, "3"), (style.underline, "4"), (style.strike, "9")] {
+		if enabled {
+			codes.push(code);
+		}
+	}
+	codes.extend(style.color);
+	if codes.is_empty() || text.is_empty() {
+		return text.into();
+	}
+	format!("\x1b[{}m{}\x1b[0m", codes.join(";"), text)
+}
+
+type Piece = (String, Style);
+
+fn push_piece(pieces: &mut Vec<Piece>, text: &str, style: Style) {
+	match pieces.last_mut() {
+		Some((last, last_style)) if *last_style == style => last.push_str(text),
+		_ if text.is_empty() => {},
+		_ => pieces.push((text.into(), style)),
+	}
+}
+
+fn pieces_width(pieces: &[Piece]) -> usize {
+	pieces.iter().map(|(text, _)| text.width()).sum()
+}
+
+/// Splits inline Markdown into styled pieces: `**bold**`, `*italic*`, `~~strikethrough~~`, `` `code` `` and
+/// `[links](url)`. Markers without a closing counterpart are kept as they are.
+fn inline(text: &str, base: Style) -> Vec<Piece> {
+	let mut pieces = vec![];
+	let mut style = base;
+	let mut rest = text;
+	while let Some(c) = rest.chars().next() {
+		let after = &rest[c.len_utf8()..];
+		if c == '\\' && after.starts_with(|c: char| c.is_ascii_punctuation()) {
+			push_piece(&mut pieces, &after[..1], style);
+			rest = &after[1..];
+		}
+		else if c == '`' {
+			let ticks = rest.len() - rest.trim_start_matches('`').len();
+			let fence = &rest[..ticks];
+			match rest[ticks..].find(fence) {
+				Some(end) => {
+					let code = &rest[ticks..ticks + end];
+					push_piece(&mut pieces, code.strip_prefix(' ').and_then(|code| code.strip_suffix(' ')).unwrap_or(code), CODE);
+					rest = &rest[ticks + end + ticks..];
+				},
+				None => {
+					push_piece(&mut pieces, fence, style);
+					rest = &rest[ticks..];
+				}
+			}
+		}
+		else if rest.starts_with("**") && (style.bold || rest[2..].contains("**")) {
+			style.bold = !style.bold;
+			rest = &rest[2..];
+		}
+		else if rest.starts_with("~~") && (style.strike || rest[2..].contains("~~")) {
+			style.strike = !style.strike;
+			rest = &rest[2..];
+		}
+		else if c == '*' && style.italic {
+			style.italic = false;
+			rest = after;
+		}
+		else if c == '*' && after.starts_with(|c: char| !c.is_whitespace()) && after.contains('*') {
+			style.italic = true;
+			rest = after;
+		}
+		else if let Some((label, url, consumed)) = link(rest) {
+			let link_style = Style { underline: true, ..style };
+			pieces.extend(inline(label, link_style));
+			if url != label && !url.is_empty() {
+				push_piece(&mut pieces, &format!(" ({})", url), DIM);
+			}
+			rest = &rest[consumed..];
+		}
+		else {
+			push_piece(&mut pieces, &rest[..c.len_utf8()], style);
+			rest = after;
+		}
+	}
+	pieces
+}
+
+/// Parses `[label](url)` or `![label](url)` at the start of `text`, returning the label, the URL and the
+/// number of bytes they take.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+	let skip = if text.starts_with("![") { 1 } else { 0 };
+	let body = text[skip..].strip_prefix('[')?;
+	let label_end = body.find("](")?;
+	let url_end = body[label_end + 2..].find(')')?;
+	let url = &body[label_end + 2..label_end + 2 + url_end];
+	Some((&body[..label_end], url, skip + 1 + label_end + 2 + url_end + 1))
+}
+
+/// Wraps styled pieces to `width` columns. The first line starts with `first` and the others with `rest`,
+/// which must be equally wide.
+fn wrap(pieces: &[Piece], width: usize, first: &str, rest: &str) -> String {
+	let mut words: Vec<Vec<Piece>> = vec![];
+	let mut word: Vec<Piece> = vec![];
+	for (text, style) in pieces {
+		for part in text.split_inclusive(char::is_whitespace) {
+			let trimmed = part.trim_end_matches(char::is_whitespace);
+			push_piece(&mut word, trimmed, *style);
+			if trimmed.len() < part.len() && !word.is_empty() {
+				words.push(std::mem::take(&mut word));
+			}
+		}
+	}
+	if !word.is_empty() {
+		words.push(word);
+	}
+
+	let available = width.saturating_sub(first.width()).max(10);
+	let mut output = String::new();
+	let mut line = String::new();
+	let mut line_width = 0;
+	let mut prefix = first;
+	for word in words.iter() {
+		let word_width = pieces_width(word);
+		if line_width > 0 && line_width + 1 + word_width > available {
+			output.push_str(&format!("{}{}\n", prefix, line));
+			line.clear();
+			line_width = 0;
+			prefix = rest;
+		}
+		if line_width > 0 {
+			line.push(' ');
+			line_width += 1;
+		}
+		for (text, style) in word.iter() {
+			line.push_str(&paint(text, *style));
+		}
+		line_width += word_width;
+	}
+	output.push_str(&format!("{}{}\n", prefix, line));
+	output
+}
+
+enum Block {
+	None,
+	Paragraph(Vec<String>),
+	Table(Vec<String>),
+	Code { fence: String, highlighter: Option<Box<HighlightLines<'static>>> },
+}
+
+/// Renders Markdown for the terminal as it arrives. Text is fed with [`push`](Self::push) in pieces of any size;
+/// each call returns the output that is ready, which is every complete line except those of a paragraph or
+/// table still being received, as these are only wrapped and aligned once complete.
+/// Without color, markup is left in place and only wrapping and table alignment apply.
+pub struct MarkdownRenderer {
+	color: bool,
+	width: usize,
+	pending: String,
+	block: Block
+}
+
+fn is_rule(line: &str) -> bool {
+	let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+	marks.len() >= 3 && ['-', '*', '_'].iter().any(|mark| marks.chars().all(|c| c == *mark))
+}
+
+fn fence_of(line: &str) -> Option<&str> {
+	let trimmed = line.trim_start();
+	["```", "~~~"].iter().find(|fence| trimmed.starts_with(**fence)).map(|fence| {
+		let length = trimmed.len() - trimmed.trim_start_matches(&fence[..1]).len();
+		&trimmed[..length]
+	})
+}
+
+/// Returns the marker of a list item (`-`, `*`, `+`, `1.` or `1)`) and the text after it.
+fn list_item(line: &str) -> Option<(&str, &str)> {
+	let (marker, text) = line.split_once(' ')?;
+	let ordered = marker.len() > 1
+		&& marker[..marker.len() - 1].chars().all(|c| c.is_ascii_digit())
+		&& marker.ends_with(['.', ')']);
+	(["-", "*", "+"].contains(&marker) || ordered).then_some((marker, text))
+}
+
+fn split_cells(row: &str) -> Vec<String> {
+	let row = row.trim();
+	let row = row.strip_prefix('|').unwrap_or(row);
+	let row = row.strip_suffix('|').filter(|_| !row.ends_with("\\|")).unwrap_or(row);
+	let mut cells = vec![];
+	let mut cell = String::new();
+	let mut escaped = false;
+	for c in row.chars() {
+		match c {
+			'|' if !escaped => cells.push(std::mem::take(&mut cell).trim().to_string()),
+			_ => cell.push(c),
+		}
+		escaped = c == '\\';
+	}
+	cells.push(cell.trim().to_string());
+	cells
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+	Left,
+	Center,
+	Right,
+}
+
+fn alignment_of(cell: &str) -> Option<Align> {
+	let dashes = cell.trim_matches(':');
+	if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+		return None;
+	}
+	Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+		(true, true) => Align::Center,
+		(false, true) => Align::Right,
+		_ => Align::Left,
+	})
+}
+
+impl MarkdownRenderer {
+	pub fn new(color: bool, width: usize) -> Self {
+		Self { color, width, pending: String::new(), block: Block::None }
+	}
+
+	/// Renders `text` from scratch, for messages that are complete.
+	pub fn render(color: bool, width: usize, text: &str) -> String {
+		let mut renderer = Self::new(color, width);
+		let mut output = renderer.push(text);
+		output.push_str(&renderer.finish());
+		output
+	}
+
+	pub fn push(&mut self, text: &str) -> String {
+		self.pending.push_str(text);
+		let mut output = String::new();
+		while let Some(end) = self.pending.find('\n') {
+			let line: String = self.pending.drain(..=end).collect();
+			output.push_str(&self.line(line.trim_end_matches(['\n', '\r'])));
+		}
+		output
+	}
+
+	/// Renders what is left once the text is complete.
+	pub fn finish(&mut self) -> String {
+		let mut output = String::new();
+		if !self.pending.is_empty() {
+			let line = std::mem::take(&mut self.pending);
+			output.push_str(&self.line(&line));
+		}
+		output.push_str(&self.flush());
+		self.block = Block::None;
+		output
+	}
+
+	fn inline(&self, text: &str, base: Style) -> Vec<Piece> {
+		if self.color { inline(text, base) } else { vec![(text.into(), Style::default())] }
+	}
+
+	fn paint(&self, text: &str, style: Style) -> String {
+		if self.color { paint(text, style) } else { text.into() }
+	}
+
+	/// Ends the paragraph or table being collected and returns it rendered.
+	fn flush(&mut self) -> String {
+		match std::mem::replace(&mut self.block, Block::None) {
+			Block::Paragraph(lines) => wrap(&self.inline(&lines.join(" "), Style::default()), self.width, "", ""),
+			Block::Table(rows) => self.table(&rows),
+			block => {
+				self.block = block;
+				String::new()
+			}
+		}
+	}
+
+	fn line(&mut self, line: &str) -> String {
+		if let Block::Code { fence, highlighter } = &mut self.block {
+			if fence_of(line).is_some_and(|closing| closing.starts_with(fence.as_str()) && line.trim().len() == closing.len()) {
+				self.block = Block::None;
+				return format!("{}\n", self.paint(line, DIM));
+			}
+			let line_with_ending = format!("{}\n", line);
+			let highlighted = highlighter.as_mut()
+				.and_then(|highlighter| highlighter.highlight_line(&line_with_ending, syntax_set()).ok())
+				.map(|ranges| format!("{}\x1b[0m", as_24_bit_terminal_escaped(&ranges, false).trim_end_matches('\n')));
+			return format!("{}\n", highlighted.unwrap_or(line.into()));
+		}
+
+		let trimmed = line.trim_start();
+		let is_table_row = trimmed.starts_with('|');
+		let continues_paragraph = !trimmed.is_empty() && !is_table_row && fence_of(line).is_none()
+			&& !trimmed.starts_with(['#', '>']) && list_item(trimmed).is_none() && !is_rule(trimmed);
+		let mut output = String::new();
+		match &mut self.block {
+			Block::Table(rows) if is_table_row => {
+				rows.push(line.into());
+				return output;
+			},
+			Block::Paragraph(lines) if continues_paragraph => {
+				lines.push(trimmed.into());
+				return output;
+			},
+			_ => output.push_str(&self.flush()),
+		}
+
+		if trimmed.is_empty() {
+			output.push('\n');
+		}
+		else if let Some(fence) = fence_of(line) {
+			let language = trimmed[fence.len()..].split_whitespace().next().unwrap_or_default();
+			let highlighter = self.color.then(|| {
+				let syntaxes = syntax_set();
+				let syntax = syntaxes.find_syntax_by_token(language).unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+				Box::new(HighlightLines::new(syntax, theme()))
+			});
+			self.block = Block::Code { fence: fence.into(), highlighter };
+			output.push_str(&format!("{}\n", self.paint(line, DIM)));
+		}
+		else if is_table_row {
+			self.block = Block::Table(vec![line.into()]);
+		}
+		else if is_rule(trimmed) {
+			output.push_str(&format!("{}\n", self.paint(&if self.color { "─" } else { "-" }.repeat(self.width), DIM)));
+		}
+		else if let Some(text) = trimmed.strip_prefix('#') {
+			let level = 1 + text.len() - text.trim_start_matches('#').len();
+			let text = text.trim_start_matches('#');
+			if level > 6 || !(text.is_empty() || text.starts_with(' ')) {
+				self.block = Block::Paragraph(vec![trimmed.into()]);
+				return output;
+			}
+			if !self.color {
+				output.push_str(&format!("{}\n", trimmed));
+				return output;
+			}
+			let style = Style { bold: true, underline: level == 1, color: Some(if level <= 2 { "35" } else { "36" }), ..Style::default() };
+			output.push_str(&wrap(&inline(text.trim().trim_end_matches('#').trim_end(), style), self.width, "", ""));
+		}
+		else if let Some(text) = trimmed.strip_prefix('>') {
+			let prefix = if self.color { paint("│ ", DIM) } else { "> ".into() };
+			output.push_str(&wrap(&self.inline(text.trim_start(), Style { italic: true, ..Style::default() }), self.width, &prefix, &prefix));
+		}
+		else if let Some((marker, text)) = list_item(trimmed) {
+			let indent = " ".repeat(line.len() - trimmed.len());
+			let bullet = match marker {
+				"-" | "*" | "+" if self.color => "•",
+				_ => marker,
+			};
+			let first = format!("{}{} ", indent, bullet);
+			let rest = " ".repeat(first.width());
+			output.push_str(&wrap(&self.inline(text, Style::default()), self.width, &first, &rest));
+		}
+		else {
+			self.block = Block::Paragraph(vec![trimmed.into()]);
+		}
+		output
+	}
+
+	fn table(&self, rows: &[String]) -> String {
+		let mut rows: Vec<Vec<String>> = rows.iter().map(|row| split_cells(row)).collect();
+		let alignments: Option<Vec<Align>> = rows.get(1).and_then(|row| row.iter().map(|cell| alignment_of(cell)).collect());
+		let has_header = alignments.is_some();
+		if has_header {
+			rows.remove(1);
+		}
+		let alignments = alignments.unwrap_or_default();
+		let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+
+		let cells: Vec<Vec<Vec<Piece>>> = rows
+			.iter()
+			.enumerate()
+			.map(|(index, row)| {
+				let style = Style { bold: has_header && index == 0, ..Style::default() };
+				(0..columns).map(|column| self.inline(row.get(column).map_or("", String::as_str), style)).collect()
+			})
+			.collect();
+		let widths: Vec<usize> = (0..columns)
+			.map(|column| cells.iter().map(|row| pieces_width(&row[column])).max().unwrap_or(0))
+			.collect();
+
+		let (separator, crossing, rule) = if self.color { (" │ ", "─┼─", "─") } else { (" | ", "-+-", "-") };
+		let separator = self.paint(separator, DIM);
+		let mut output = String::new();
+		for (index, row) in cells.iter().enumerate() {
+			let rendered: Vec<String> = row
+				.iter()
+				.enumerate()
+				.map(|(column, pieces)| {
+					let text: String = pieces.iter().map(|(text, style)| paint(text, *style)).collect();
+					let padding = widths[column] - pieces_width(pieces);
+					match alignments.get(column).copied().unwrap_or(Align::Left) {
+						Align::Left => format!("{}{}", text, " ".repeat(padding)),
+						Align::Right => format!("{}{}", " ".repeat(padding), text),
+						Align::Center => format!("{}{}{}", " ".repeat(padding / 2), text, " ".repeat(padding - padding / 2)),
+					}
+				})
+				.collect();
+			output.push_str(rendered.join(&separator).trim_end());
+			output.push('\n');
+			if has_header && index == 0 {
+				let line: Vec<String> = widths.iter().map(|width| rule.repeat(*width)).collect();
+				output.push_str(&format!("{}\n", self.paint(&line.join(crossing), DIM)));
+			}
+		}
+		output
+	}
+}
+
+/// Formats a reply under a header as it streams in, rendered as Markdown unless it is raw. The header is only
+/// written once the reply has some text, and whitespace around the reply is left out.
+pub struct ReplyPrinter {
+	header: String,
+	renderer: Option<MarkdownRenderer>,
+	started: bool,
+	/// Whitespace held back until more text follows it.
+	held: String
+}
+
+impl ReplyPrinter {
+	pub fn new(raw: bool, header: &str) -> Self {
+		let renderer = (!raw).then(|| MarkdownRenderer::new(use_color(), terminal_width()));
+		Self { header: header.into(), renderer, started: false, held: String::new() }
+	}
+
+	/// Takes the next piece of the reply and returns the output that is ready.
+	pub fn push(&mut self, text: &str) -> String {
+		let mut text = std::mem::take(&mut self.held) + text;
+		if !self.started {
+			text = text.trim_start().to_string();
+		}
+		let end = text.trim_end().len();
+		self.held = text.split_off(end);
+		if text.is_empty() {
+			return String::new();
+		}
+
+		let mut output = String::new();
+		if !self.started {
+			self.started = true;
+			output = match self.renderer {
+				Some(_) => format!("{}:\n", self.header),
+				None => format!("{}: ", self.header),
+			};
+		}
+		match self.renderer.as_mut() {
+			Some(renderer) => output.push_str(&renderer.push(&text)),
+			None => output.push_str(&text),
+		}
+		output
+	}
+
+	/// Returns the rest of the reply once it is complete.
+	pub fn finish(&mut self) -> String {
+		if !self.started {
+			return String::new();
+		}
+		match self.renderer.as_mut() {
+			Some(renderer) => renderer.finish(),
+			None => "\n".into(),
+		}
+	}
+}
+
+/// Prints a reply under `header`, rendered as Markdown unless `raw` is set.
+pub fn print_reply(raw: bool, header: &str, text: &str) {
+	let mut printer = ReplyPrinter::new(raw, header);
+	print!("{}{}", printer.push(text), printer.finish());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const REPLY: &str = "Some **bold** text\nover two lines.\n\n```rust\nfn main() {}\n```\n\n- one\n  - nested **item**\n- two\n\n| a | b |\n|---|--:|\n| 1 | 22 |\n";
+
+	#[test]
+	fn renders_fenced_code_and_nested_lists() {
+		let rendered = MarkdownRenderer::render(false, 80, "Text:\n\n```\n# not a heading\n- not an item\n```\n\n- one\n  - nested\n    1. deeper\n- two");
+		assert_eq!(rendered, "Text:\n\n```\n# not a heading\n- not an item\n```\n\n- one\n  - nested\n    1. deeper\n- two\n");
+
+		let rendered = MarkdownRenderer::render(true, 80, "- one\n  - nested\n* two");
+		assert_eq!(rendered, "• one\n  • nested\n• two\n");
+		let rendered = MarkdownRenderer::render(true, 80, "```rust\nlet x = 1;\n```");
+		assert!(rendered.starts_with(&format!("{}\n", paint("```rust", DIM))));
+		assert!(rendered.ends_with(&format!("{}\n", paint("```", DIM))));
+		assert!(!rendered.contains("let x = 1;"), "code is highlighted");
+	}
+
+	#[test]
+	fn chunks_split_anywhere_render_like_the_whole_text() {
+		for color in [false, true] {
+			let whole = MarkdownRenderer::render(color, 40, REPLY);
+			let mut renderer = MarkdownRenderer::new(color, 40);
+			let mut streamed: String = REPLY.chars().map(|c| renderer.push(&c.to_string())).collect();
+			streamed.push_str(&renderer.finish());
+			assert_eq!(streamed, whole);
+
+			// Splitting a fence or a bold marker between pieces changes nothing either
+			for split in [REPLY.find("``").unwrap() + 1, REPLY.find("**").unwrap() + 1] {
+				let mut renderer = MarkdownRenderer::new(color, 40);
+				let mut streamed = renderer.push(&REPLY[..split]);
+				streamed.push_str(&renderer.push(&REPLY[split..]));
+				streamed.push_str(&renderer.finish());
+				assert_eq!(streamed, whole);
+			}
+		}
+		assert!(MarkdownRenderer::render(true, 40, REPLY).contains(&paint("bold", Style { bold: true, ..Style::default() })));
+	}
+
+	#[test]
+	fn prints_the_header_once_the_reply_has_text() {
+		let mut printer = ReplyPrinter::new(true, "ChatGPT");
+		assert_eq!(printer.push("\n  "), "");
+		assert_eq!(printer.push("Hello"), "ChatGPT: Hello");
+		assert_eq!(printer.push(" \n"), "");
+		assert_eq!(printer.push("world\n\n"), " \nworld");
+		assert_eq!(printer.finish(), "\n");
+
+		let mut printer = ReplyPrinter { renderer: Some(MarkdownRenderer::new(false, 80)), ..ReplyPrinter::new(true, "ChatGPT") };
+		assert_eq!(printer.push("- one\n"), "ChatGPT:\n");
+		assert_eq!(printer.push("- two"), "- one\n");
+		assert_eq!(printer.finish(), "- two\n");
+
+		assert_eq!(ReplyPrinter::new(true, "ChatGPT").finish(), "");
+	}
+}
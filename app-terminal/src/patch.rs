@@ -0,0 +1,634 @@
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use similar::{DiffTag, TextDiff};
+
+use openai::types::*;
+use database::*;
+
+use crate::codeblock::{extract_code_blocks, source_message, CodeBlock};
+use crate::error::*;
+use crate::include::sha256_of;
+use crate::input::LineReader;
+use crate::markdown::use_color;
+use crate::types::*;
+
+/// Context lines that may be left out at either end of a hunk that does not match as it is.
+static MAX_FUZZ: usize = 2;
+
+struct Hunk {
+	/// Line of the original file where the hunk starts, counting from 0, when the diff gives it.
+	old_start: Option<usize>,
+	lines: Vec<(char, String)>
+}
+
+enum Change {
+	Patch(Vec<Hunk>),
+	/// The whole new content of the file.
+	Replace(String),
+	Delete,
+}
+
+struct FileChange {
+	path: String,
+	change: Change
+}
+
+/// How loosely the lines of a hunk had to be compared to find it.
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+	Exact,
+	TrailingWhitespace,
+	Indentation,
+}
+
+/// The result of applying the hunks of a file.
+struct Patched {
+	content: String,
+	/// Hunks that matched only approximately.
+	fuzzy_hunks: usize
+}
+
+/// Where a hunk applies in the original file.
+struct Located<'a> {
+	/// The first and last lines, exclusive, of the original file that the hunk replaces.
+	start: usize,
+	end: usize,
+	/// The lines of the hunk that are used, without the context lines that had to be left out.
+	lines: &'a [(char, String)],
+	fuzzy: bool
+}
+
+/// A change checked against the working tree and ready to be written.
+struct PlannedChange {
+	display: String,
+	target: PathBuf,
+	before: Option<String>,
+	after: Option<String>,
+	hunks: usize,
+	fuzzy_hunks: usize
+}
+
+fn is_diff(block: &CodeBlock) -> bool {
+	matches!(block.language.as_str(), "diff" | "patch")
+		|| ["--- ", "diff --git ", "@@ ", "Index: "].iter().any(|start| block.content.trim_start().starts_with(start))
+}
+
+/// The path in a `---` or `+++` header without the `a/` or `b/` prefix, or `None` for /dev/null.
+fn header_path(header: &str) -> Option<String> {
+	let path = header.split('\t').next().unwrap_or_default().trim();
+	if path == "/dev/null" || path.is_empty() {
+		return None;
+	}
+	let path = path.strip_prefix("a/").or(path.strip_prefix("b/")).unwrap_or(path);
+	Some(path.to_string())
+}
+
+/// Reads the files and hunks of a unified diff. The line counts of hunk headers are ignored, as models often get
+/// them wrong. Hunks before any file header belong to `hint`, the file named around the block.
+fn parse_diff(content: &str, hint: Option<&str>) -> Result<Vec<FileChange>, String> {
+	let lines: Vec<&str> = content.lines().collect();
+	let mut changes: Vec<FileChange> = vec![];
+	let mut in_hunk = false;
+	let mut index = 0;
+	while index < lines.len() {
+		let line = lines[index];
+		index += 1;
+		if let (Some(old), Some(new)) = (line.strip_prefix("--- "), lines.get(index).and_then(|next| next.strip_prefix("+++ "))) {
+			index += 1;
+			in_hunk = false;
+			changes.push(match (header_path(old), header_path(new)) {
+				(Some(path), None) => FileChange { path, change: Change::Delete },
+				(_, Some(path)) => FileChange { path, change: Change::Patch(vec![]) },
+				(None, None) => return Err("a file header names no file".into()),
+			});
+			continue
+		}
+
+		if let Some(range) = line.strip_prefix("@@") {
+			// The lines removed with a deleted file are not needed
+			if changes.last().is_some_and(|last| matches!(last.change, Change::Delete)) {
+				in_hunk = false;
+				continue
+			}
+			if !changes.last().is_some_and(|last| matches!(last.change, Change::Patch(_))) {
+				let Some(path) = hint else {
+					return Err("the diff does not name the file it changes".into());
+				};
+				changes.push(FileChange { path: path.into(), change: Change::Patch(vec![]) });
+			}
+			let old_start = range
+				.trim_start()
+				.strip_prefix('-')
+				.and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+				.and_then(|start| start.parse::<usize>().ok())
+				.map(|start| start.saturating_sub(1));
+			if let Some(Change::Patch(hunks)) = changes.last_mut().map(|last| &mut last.change) {
+				hunks.push(Hunk { old_start, lines: vec![] });
+			}
+			in_hunk = true;
+			continue
+		}
+
+		if !in_hunk {
+			continue
+		}
+		let Some(Change::Patch(hunks)) = changes.last_mut().map(|last| &mut last.change) else {
+			continue
+		};
+		let hunk = hunks.last_mut().unwrap();
+		match line.chars().next() {
+			Some(kind @ (' ' | '-' | '+')) => hunk.lines.push((kind, line[1..].to_string())),
+			// Models often drop the space of empty context lines
+			None => hunk.lines.push((' ', String::new())),
+			Some('\\') => {},
+			_ => in_hunk = false,
+		}
+	}
+
+	if let Some(change) = changes.iter().find(|change| matches!(&change.change, Change::Patch(hunks) if hunks.is_empty())) {
+		return Err(format!("the diff has no changes for {}", change.path));
+	}
+	Ok(changes)
+}
+
+fn normalize(line: &str, level: Level) -> &str {
+	match level {
+		Level::Exact => line,
+		Level::TrailingWhitespace => line.trim_end(),
+		Level::Indentation => line.trim(),
+	}
+}
+
+/// Finds `old` in `file`, as close as possible to line `expected`.
+fn find_lines(file: &[&str], old: &[&str], expected: usize, level: Level) -> Option<usize> {
+	if old.is_empty() {
+		return Some(expected.min(file.len()));
+	}
+	if file.len() < old.len() {
+		return None;
+	}
+	(0..=file.len() - old.len())
+		.filter(|start| old.iter().enumerate().all(|(offset, line)| normalize(file[start + offset], level) == normalize(line, level)))
+		.min_by_key(|start| start.abs_diff(expected))
+}
+
+/// Finds where a hunk applies. Whitespace is compared more loosely, then context lines are left out at either end.
+/// Without a line number in the hunk header, the match closest to `near` is taken.
+fn locate<'a>(file: &[&str], hunk: &'a Hunk, near: usize) -> Option<Located<'a>> {
+	let leading = hunk.lines.iter().take_while(|(kind, _)| *kind == ' ').count();
+	let trailing = hunk.lines.iter().rev().take_while(|(kind, _)| *kind == ' ').count();
+	for fuzz in 0..=MAX_FUZZ {
+		if fuzz > 0 && fuzz > leading && fuzz > trailing {
+			break
+		}
+		let (skip_start, skip_end) = (fuzz.min(leading), fuzz.min(trailing));
+		if skip_start + skip_end >= hunk.lines.len() {
+			break
+		}
+		let lines = &hunk.lines[skip_start..hunk.lines.len() - skip_end];
+		let old: Vec<&str> = lines.iter().filter(|(kind, _)| *kind != '+').map(|(_, line)| line.as_str()).collect();
+		let expected = hunk.old_start.map_or(near, |start| start + skip_start);
+		for level in [Level::Exact, Level::TrailingWhitespace, Level::Indentation] {
+			if let Some(position) = find_lines(file, &old, expected, level) {
+				let end = position + old.len();
+				return Some(Located { start: position, end, lines, fuzzy: fuzz > 0 || level != Level::Exact });
+			}
+		}
+	}
+	None
+}
+
+/// Applies hunks to `original`. Each hunk is found on its own, so hunks given out of order still apply, but
+/// hunks that would change the same lines are an error.
+fn apply_hunks(original: &str, hunks: &[Hunk]) -> Result<Patched, String> {
+	let newline = if original.contains("\r\n") { "\r\n" } else { "\n" };
+	let file: Vec<&str> = original.lines().collect();
+	let mut located = vec![];
+	let mut near = 0;
+	for (number, hunk) in hunks.iter().enumerate() {
+		let Some(location) = locate(&file, hunk, near) else {
+			return Err(format!("hunk {} does not match the file", number + 1));
+		};
+		near = location.end;
+		located.push((number, location));
+	}
+	located.sort_by_key(|(_, location)| location.start);
+	for pair in located.windows(2) {
+		if pair[1].1.start < pair[0].1.end {
+			return Err(format!("hunks {} and {} change the same lines", pair[0].0 + 1, pair[1].0 + 1));
+		}
+	}
+
+	let mut result: Vec<String> = vec![];
+	let mut cursor = 0;
+	for (_, location) in located.iter() {
+		result.extend(file[cursor..location.start].iter().map(|line| line.to_string()));
+		let mut at = location.start;
+		for (kind, line) in location.lines {
+			match kind {
+				// Context lines are kept as they are in the file, which may differ in whitespace
+				' ' => {
+					result.push(file[at].to_string());
+					at += 1;
+				},
+				'-' => at += 1,
+				_ => result.push(line.clone()),
+			}
+		}
+		cursor = location.end;
+	}
+	result.extend(file[cursor..].iter().map(|line| line.to_string()));
+
+	let mut content = result.join(newline);
+	if !result.is_empty() && (original.is_empty() || original.ends_with('\n')) {
+		content.push_str(newline);
+	}
+	Ok(Patched { content, fuzzy_hunks: located.iter().filter(|(_, location)| location.fuzzy).count() })
+}
+
+/// The ranges of lines of `before`, counting from 0, that `after` changes. Lines inserted count as changing the
+/// line they are inserted before.
+fn changed_lines(before: &str, after: &str) -> Vec<(usize, usize)> {
+	TextDiff::from_lines(before, after)
+		.ops()
+		.iter()
+		.filter(|op| op.tag() != DiffTag::Equal)
+		.map(|op| {
+			let range = op.old_range();
+			(range.start, range.end.max(range.start + 1))
+		})
+		.collect()
+}
+
+/// Resolves `path` in `current_dir`, refusing absolute paths and paths that lead outside it, also through
+/// symbolic links. Links are followed here, so the path returned has none that writing it could follow, and
+/// links that lead nowhere are refused.
+fn resolve_target(current_dir: &Path, path: &str) -> Result<PathBuf, String> {
+	let relative = Path::new(path);
+	if relative.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+		return Err("outside the current directory".into());
+	}
+	let mut target = current_dir.to_path_buf();
+	for component in relative.components().filter(|component| *component != Component::CurDir) {
+		target.push(component);
+		let is_link = std::fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.file_type().is_symlink());
+		if is_link {
+			target = target.canonicalize().map_err(|_| "a symbolic link in the path leads nowhere".to_string())?;
+			if !target.starts_with(current_dir) {
+				return Err("outside the current directory".into());
+			}
+		}
+	}
+	Ok(target)
+}
+
+/// `path` without `.` components, so that `./src/a.rs` and `src/a.rs` are taken as the same file.
+fn same_file_key(path: &str) -> PathBuf {
+	Path::new(path).components().filter(|component| *component != Component::CurDir).collect()
+}
+
+fn git(current_dir: &Path, args: &[&str]) -> Option<String> {
+	let output = Command::new("git").args(args).current_dir(current_dir).output().ok()?;
+	output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The ranges of lines of `path`, counting from 0, that differ from the last commit. Empty when git does not
+/// track the file or the directory is not a repository. Files with unresolved merge conflicts are an error.
+fn uncommitted_lines(current_dir: &Path, path: &str) -> Result<Vec<(usize, usize)>, String> {
+	if git(current_dir, &["ls-files", "--unmerged", "--", path]).is_some_and(|output| !output.trim().is_empty()) {
+		return Err("it has unresolved merge conflicts".into());
+	}
+	let Some(diff) = git(current_dir, &["diff", "HEAD", "--no-color", "--no-ext-diff", "-U0", "--", path]) else {
+		return Ok(vec![]);
+	};
+	Ok(diff
+		.lines()
+		.filter_map(|line| line.strip_prefix("@@ "))
+		.filter_map(|header| header.split_whitespace().find_map(|range| range.strip_prefix('+')))
+		.filter_map(|range| {
+			let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+			let (start, count) = (start.parse::<usize>().ok()?, count.parse::<usize>().ok()?);
+			// A deletion is between two lines, which both count as edited
+			Some(match count {
+				0 => (start.saturating_sub(1), start + 1),
+				_ => (start - 1, start - 1 + count),
+			})
+		})
+		.collect())
+}
+
+/// Works out the new content of a file from the changes a reply makes to it, each applied to the result of the
+/// ones before, and checks that it may be changed.
+fn plan_change(current_dir: &Path, path: &str, changes: &[&Change]) -> Result<PlannedChange, String> {
+	let target = resolve_target(current_dir, path)?;
+	let before = match std::fs::read(&target) {
+		Ok(data) => Some(String::from_utf8(data).map_err(|_| "not a text file".to_string())?),
+		Err(_) if target.is_dir() => return Err("is a directory".into()),
+		Err(_) => None,
+	};
+	let edited = match &before {
+		Some(_) => uncommitted_lines(current_dir, path)?,
+		None => vec![],
+	};
+
+	let (mut after, mut hunks, mut fuzzy_hunks) = (before.clone(), 0, 0);
+	for (number, change) in changes.iter().enumerate() {
+		after = match (change, &after) {
+			(Change::Delete, None) => return Err("does not exist".into()),
+			(Change::Delete, Some(_)) => None,
+			(Change::Replace(content), _) => Some(content.clone()),
+			(Change::Patch(patch), current) => {
+				let patched = apply_hunks(current.as_deref().unwrap_or_default(), patch).map_err(|err| match changes.len() {
+					1 => err,
+					_ => format!("in diff {} of the file, {}", number + 1, err),
+				})?;
+				hunks += patch.len();
+				fuzzy_hunks += patched.fuzzy_hunks;
+				Some(patched.content)
+			},
+		};
+	}
+
+	if let (Some(before), false) = (&before, edited.is_empty()) {
+		if changes.iter().any(|change| !matches!(change, Change::Patch(_))) {
+			return Err("it has uncommitted changes".into());
+		}
+		let changed = changed_lines(before, after.as_deref().unwrap_or_default());
+		let conflict = changed.iter().any(|(start, end)| edited.iter().any(|(edit_start, edit_end)| edit_start < end && start < edit_end));
+		if conflict {
+			return Err("it has uncommitted changes in the lines the patch changes".into());
+		}
+	}
+	Ok(PlannedChange { display: path.into(), target, before, after, hunks, fuzzy_hunks })
+}
+
+fn print_preview(planned: &PlannedChange, color: bool) {
+	let paint = |line: &str, code: &str| if color { format!("\x1b[{}m{}\x1b[0m", code, line) } else { line.to_string() };
+	let (Some(before), Some(after)) = (&planned.before, &planned.after) else {
+		match planned.after {
+			Some(_) => println!("{}", paint(&format!("Create {}", planned.display), "1;32")),
+			None => println!("{}", paint(&format!("Delete {}", planned.display), "1;31")),
+		}
+		if let Some(after) = &planned.after {
+			for line in after.lines() {
+				println!("{}", paint(&format!("+{}", line), "32"));
+			}
+		}
+		return;
+	};
+
+	let diff = TextDiff::from_lines(before, after);
+	let unified = diff.unified_diff().context_radius(3).header(&format!("a/{}", planned.display), &format!("b/{}", planned.display)).to_string();
+	for line in unified.lines() {
+		let code = match line.chars().next() {
+			_ if line.starts_with("---") || line.starts_with("+++") => "1",
+			Some('@') => "36",
+			Some('-') => "31",
+			Some('+') => "32",
+			_ => "0",
+		};
+		println!("{}", paint(line, code));
+	}
+	if planned.hunks == 0 {
+		let (kept, total) = (diff.ratio(), before.lines().count());
+		if total > 0 && kept < 0.5 {
+			println!("This replaces the whole file and changes most of its {} lines.", total);
+		}
+	}
+}
+
+/// Applies the diffs and whole files of a reply to the files under the current directory, after showing the
+/// changes and asking for confirmation, and records what was changed. Without `argument`, the reply chosen
+/// with /code or else the latest one is used.
+pub fn apply_patches(mgr: &ChatManager, reader: &mut LineReader, argument: &str) -> Result<(), MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let msg = match argument {
+		"" => source_message(session),
+		argument => {
+			let id = argument.trim_start_matches('#').parse::<u32>().ok();
+			session.history.iter().find(|msg| Some(msg.id) == id)
+		}
+	};
+	let Some(msg) = msg else {
+		match argument {
+			"" => println!("There is no reply in this conversation yet."),
+			_ => println!("No such message in this conversation. Message IDs are shown when a conversation is resumed."),
+		}
+		return Ok(());
+	};
+
+	let mut changes = vec![];
+	for (index, block) in extract_code_blocks(&msg.content).iter().enumerate() {
+		if is_diff(block) {
+			match parse_diff(&block.content, block.hint.as_deref()) {
+				Ok(parsed) => changes.extend(parsed),
+				Err(err) => println!("Skipped code block {}: {}.", index + 1, err),
+			}
+		}
+		else if let (Some(hint), true) = (&block.hint, block.names_file) {
+			changes.push(FileChange { path: hint.clone(), change: Change::Replace(format!("{}\n", block.content)) });
+		}
+	}
+	if changes.is_empty() {
+		println!("Message {} has no diffs or files named in it to apply.", msg.id);
+		return Ok(());
+	}
+
+	// Changes to the same file are applied one after the other
+	let mut files: Vec<(&str, Vec<&Change>)> = vec![];
+	for change in changes.iter() {
+		let key = same_file_key(&change.path);
+		match files.iter_mut().find(|(path, _)| same_file_key(path) == key) {
+			Some((_, file_changes)) => file_changes.push(&change.change),
+			None => files.push((&change.path, vec![&change.change])),
+		}
+	}
+
+	let current_dir = std::env::current_dir()?.canonicalize()?;
+	let color = use_color();
+	let mut planned = vec![];
+	for (path, file_changes) in files.iter() {
+		match plan_change(&current_dir, path, file_changes) {
+			Ok(plan) if plan.before == plan.after => println!("{} is already up to date.", path),
+			Ok(plan) => {
+				print_preview(&plan, color);
+				if plan.fuzzy_hunks > 0 {
+					println!("{} of {} hunk(s) matched only approximately.", plan.fuzzy_hunks, plan.hunks);
+				}
+				planned.push(plan);
+			},
+			Err(err) => println!("Cannot apply the changes to {}: {}.", path, err),
+		}
+	}
+	if planned.is_empty() {
+		return Ok(());
+	}
+	if !reader.confirm(&format!("Apply the changes to {} file(s)? [y/N] ", planned.len()))? {
+		println!("Nothing was changed.");
+		return Ok(());
+	}
+
+	let mut applied = vec![];
+	for plan in planned {
+		match &plan.after {
+			Some(after) => {
+				if let Some(dir) = plan.target.parent() {
+					std::fs::create_dir_all(dir)?;
+				}
+				std::fs::write(&plan.target, after)?;
+			},
+			None => std::fs::remove_file(&plan.target)?,
+		}
+		let action = match (&plan.before, &plan.after) {
+			(None, _) => "create",
+			(_, None) => "delete",
+			_ => "modify",
+		};
+		println!("{} {}.", match action { "create" => "Created", "delete" => "Deleted", _ => "Updated" }, plan.display);
+		applied.push(AppliedPatch {
+			path: plan.target.to_string_lossy().to_string(),
+			action: action.into(),
+			hunks: plan.hunks,
+			fuzzy_hunks: plan.fuzzy_hunks,
+			sha256_before: plan.before.as_ref().map(|before| sha256_of(before.as_bytes())),
+			sha256_after: plan.after.as_ref().map(|after| sha256_of(after.as_bytes()))
+		});
+	}
+	Database::add_message_patches(&mgr.connection, msg.id, &applied)?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// An empty directory of its own for a test, canonical as `apply_patches` makes the current directory.
+	fn test_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("ai-patch-test-{}-{}", std::process::id(), name));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir.canonicalize().unwrap()
+	}
+
+	fn diff(content: &str) -> Change {
+		parse_diff(content, Some("file.txt")).unwrap().remove(0).change
+	}
+
+	fn hunks(content: &str) -> Vec<Hunk> {
+		match diff(content) {
+			Change::Patch(hunks) => hunks,
+			_ => unreachable!(),
+		}
+	}
+
+	fn apply(original: &str, patch: &str) -> Result<(String, usize), String> {
+		apply_hunks(original, &hunks(patch)).map(|patched| (patched.content, patched.fuzzy_hunks))
+	}
+
+	#[test]
+	fn reads_files_and_hunks_of_a_diff() {
+		let changes = parse_diff("diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -3,1 +3,1 @@\n-x\n+y\n--- a/old.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone\n", None).unwrap();
+		assert_eq!(changes.len(), 2);
+		assert!(matches!(&changes[0], FileChange { path, change: Change::Patch(hunks) } if path == "a.rs" && hunks[0].old_start == Some(2)));
+		assert!(matches!(&changes[1], FileChange { path, change: Change::Delete } if path == "old.rs"));
+		assert_eq!(parse_diff("@@ -1 +1 @@\n-x\n+y\n", None).err().unwrap(), "the diff does not name the file it changes");
+	}
+
+	#[test]
+	fn applies_hunks_where_they_match() {
+		let original = "a\nb\nc\nd\ne\nf\n";
+		assert_eq!(apply(original, "@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n"), Ok(("a\nb\nC\nd\ne\nf\n".into(), 0)));
+		// Wrong line numbers are an offset, not a failure
+		assert_eq!(apply(original, "@@ -1,3 +1,3 @@\n d\n-e\n+E\n f\n"), Ok(("a\nb\nc\nd\nE\nf\n".into(), 0)));
+		// Hunks out of order are found on their own
+		assert_eq!(apply(original, "@@ -5 +5 @@\n-e\n+E\n@@ -1 +1 @@\n-a\n+A\n"), Ok(("A\nb\nc\nd\nE\nf\n".into(), 0)));
+		assert_eq!(apply("a\r\nb\r\n", "@@ -1 +1 @@\n-a\n+A\n"), Ok(("A\r\nb\r\n".into(), 0)));
+	}
+
+	#[test]
+	fn applies_hunks_that_match_approximately() {
+		let original = "fn main() {\n    let a = 1;\n    let b = 2;\n}\n";
+		// Whitespace differs: the context lines are kept as they are in the file
+		let (content, fuzzy) = apply(original, "@@ -1,3 +1,3 @@\n fn main() {\n-  let a = 1;  \n+    let a = 10;\n   let b = 2;\n").unwrap();
+		assert_eq!((content.as_str(), fuzzy), ("fn main() {\n    let a = 10;\n    let b = 2;\n}\n", 1));
+		// A context line that is not in the file is left out
+		let (content, fuzzy) = apply(original, "@@ -1,3 +1,3 @@\n // main\n fn main() {\n-    let a = 1;\n+    let a = 10;\n").unwrap();
+		assert_eq!((content.as_str(), fuzzy), ("fn main() {\n    let a = 10;\n    let b = 2;\n}\n", 1));
+	}
+
+	#[test]
+	fn refuses_hunks_that_do_not_apply() {
+		let original = "a\nb\nc\n";
+		assert_eq!(apply(original, "@@ -1 +1 @@\n-x\n+y\n").err().unwrap(), "hunk 1 does not match the file");
+		assert_eq!(apply(original, "@@ -1,2 +1,2 @@\n-a\n+A\n b\n@@ -2 +2 @@\n-b\n+B\n").err().unwrap(), "hunks 1 and 2 change the same lines");
+		// Lines changed by the hunk itself are never left out
+		assert_eq!(apply(original, "@@ -1,2 +1,2 @@\n-a\n-x\n+A\n").err().unwrap(), "hunk 1 does not match the file");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn refuses_paths_leading_outside() {
+		use std::os::unix::fs::symlink;
+
+		let dir = test_dir("outside");
+		let outside = test_dir("outside-target");
+		std::fs::create_dir(dir.join("src")).unwrap();
+		symlink(&outside, dir.join("out")).unwrap();
+		symlink(outside.join("missing.txt"), dir.join("dangling.txt")).unwrap();
+		symlink(dir.join("src"), dir.join("linked")).unwrap();
+		symlink("src/new.rs", dir.join("relative.rs")).unwrap();
+
+		for path in ["../up.txt", "/etc/passwd", "src/../../up.txt", "out/file.txt", "out/new/file.txt"] {
+			assert_eq!(resolve_target(&dir, path).err().as_deref(), Some("outside the current directory"), "{}", path);
+		}
+		assert_eq!(resolve_target(&dir, "dangling.txt").err().as_deref(), Some("a symbolic link in the path leads nowhere"));
+		assert_eq!(resolve_target(&dir, "relative.rs").err().as_deref(), Some("a symbolic link in the path leads nowhere"));
+		assert_eq!(resolve_target(&dir, "./src/main.rs"), Ok(dir.join("src/main.rs")));
+		assert_eq!(resolve_target(&dir, "linked/new/main.rs"), Ok(dir.join("src/new/main.rs")));
+		std::fs::remove_dir_all(&dir).unwrap();
+		std::fs::remove_dir_all(&outside).unwrap();
+	}
+
+	#[test]
+	fn applies_changes_to_the_same_file_in_order() {
+		let dir = test_dir("in-order");
+		std::fs::write(dir.join("file.txt"), "one\ntwo\nthree\n").unwrap();
+		let first = diff("@@ -1,2 +1,2 @@\n-one\n+ONE\n two\n");
+		let second = diff("@@ -2,2 +2,2 @@\n two\n-three\n+THREE\n");
+		let plan = plan_change(&dir, "file.txt", &[&first, &second]).unwrap();
+		assert_eq!(plan.after.as_deref(), Some("ONE\ntwo\nTHREE\n"));
+		assert_eq!(plan.hunks, 2);
+
+		// The second change sees what the first one made of the file
+		let replace = Change::Replace("new\n".into());
+		let patch = diff("@@ -1 +1,2 @@\n new\n+more\n");
+		let plan = plan_change(&dir, "file.txt", &[&replace, &patch]).unwrap();
+		assert_eq!(plan.after.as_deref(), Some("new\nmore\n"));
+
+		let err = plan_change(&dir, "file.txt", &[&first, &first]).err().unwrap();
+		assert_eq!(err, "in diff 2 of the file, hunk 1 does not match the file");
+		assert_eq!(same_file_key("./file.txt"), same_file_key("file.txt"));
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn refuses_to_change_lines_with_uncommitted_edits() {
+		let dir = test_dir("uncommitted");
+		std::fs::write(dir.join("file.txt"), "one\ntwo\nthree\nfour\nfive\n").unwrap();
+		for args in [&["init", "-q"][..], &["add", "file.txt"], &["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-qm", "start"]] {
+			assert!(git(&dir, args).is_some(), "git {:?} failed", args);
+		}
+		std::fs::write(dir.join("file.txt"), "one\nTWO\nthree\nfour\nfive\n").unwrap();
+
+		let elsewhere = diff("@@ -4,2 +4,2 @@\n four\n-five\n+FIVE\n");
+		assert!(plan_change(&dir, "file.txt", &[&elsewhere]).is_ok());
+		let after_elsewhere = diff("@@ -3,2 +3,2 @@\n three\n-four\n+FOUR\n");
+		let same_line = diff("@@ -1,2 +1,2 @@\n one\n-TWO\n+2\n");
+		let err = plan_change(&dir, "file.txt", &[&after_elsewhere, &same_line]).err().unwrap();
+		assert_eq!(err, "it has uncommitted changes in the lines the patch changes");
+		let replace = Change::Replace("new\n".into());
+		assert_eq!(plan_change(&dir, "file.txt", &[&replace]).err().unwrap(), "it has uncommitted changes");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,271 @@
+use std::{collections::HashMap, path::Path};
+
+use clap::Subcommand;
+
+use openai::types::CompletionParams;
+
+use crate::error::*;
+
+#[derive(Debug, Subcommand)]
+pub enum PersonaCommand {
+	/// List the personas and templates in the persona directory
+	List,
+
+	/// Show a persona's settings, placeholders and prompt
+	Show {
+		name: String,
+	},
+}
+
+/// A Markdown file in the persona directory. The optional front-matter between `---` lines holds
+/// `key: value` settings; the body is the prompt, which may contain `{{variable}}` placeholders.
+/// A persona's prompt becomes the system prompt of a conversation, a template's is sent as a message.
+pub struct Persona {
+	pub name: String,
+	pub description: Option<String>,
+	pub model: Option<String>,
+	pub params: CompletionParams,
+	pub prompt: String
+}
+
+fn parse_setting<T: std::str::FromStr>(key: &str, value: &str) -> Result<Option<T>, String> {
+	value.parse().map(Some).map_err(|_| format!("invalid value for {}: {}", key, value))
+}
+
+fn parse_persona(name: &str, text: &str) -> Result<Persona, String> {
+	let mut persona = Persona {
+		name: name.into(),
+		description: None,
+		model: None,
+		params: CompletionParams::default(),
+		prompt: text.trim().into()
+	};
+
+	let text = text.trim_start_matches('\u{feff}');
+	let Some(rest) = text.strip_prefix("---").filter(|rest| rest.starts_with(['\n', '\r'])) else {
+		return Ok(persona);
+	};
+	let Some((front_matter, body)) = rest.split_once("\n---") else {
+		return Err("front-matter is not closed with ---".into());
+	};
+	persona.prompt = body.trim_start_matches('-').trim().into();
+
+	for line in front_matter.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+		let Some((key, value)) = line.split_once(':') else {
+			return Err(format!("expected `key: value`, found `{}`", line));
+		};
+		let key = key.trim();
+		let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+		match key {
+			"description" => persona.description = Some(value.into()),
+			"model" => persona.model = Some(value.into()),
+			"temperature" => persona.params.temperature = parse_setting(key, value)?,
+			"top_p" => persona.params.top_p = parse_setting(key, value)?,
+			"max_tokens" => persona.params.max_tokens = parse_setting(key, value)?,
+			"presence_penalty" => persona.params.presence_penalty = parse_setting(key, value)?,
+			"frequency_penalty" => persona.params.frequency_penalty = parse_setting(key, value)?,
+			_ => return Err(format!("unknown setting: {}", key)),
+		}
+	}
+	Ok(persona)
+}
+
+fn is_valid_name(name: &str) -> bool {
+	!name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn load_persona(dir: &Path, name: &str) -> Result<Persona, MainError> {
+	if !is_valid_name(name) {
+		return Err(ArgumentError::new("persona", &format!("invalid persona name: {}", name)).into());
+	}
+	let path = dir.join(format!("{}.md", name));
+	let text = std::fs::read_to_string(&path)
+		.map_err(|_| ArgumentError::new("persona", &format!("no persona named {} in {}", name, dir.display())))?;
+	parse_persona(name, &text)
+		.map_err(|err| ArgumentError::new("persona", &format!("{}: {}", path.display(), err)).into())
+}
+
+/// Loads every persona in `dir`, sorted by name. Files that cannot be parsed are reported and skipped.
+pub fn list_personas(dir: &Path) -> Result<Vec<Persona>, MainError> {
+	if !dir.is_dir() {
+		return Ok(vec![]);
+	}
+	let mut personas = vec![];
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.extension().is_none_or(|extension| extension != "md") {
+			continue
+		}
+		let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+		match parse_persona(&name, &std::fs::read_to_string(&path)?) {
+			Ok(persona) => personas.push(persona),
+			Err(err) => println!("Skipping {}: {}", path.display(), err),
+		}
+	}
+	personas.sort_by(|a, b| a.name.cmp(&b.name));
+	Ok(personas)
+}
+
+/// The variable named between `{{` and `}}`, if it is one: letters, digits, `_` and `-`, with optional spaces around.
+/// Anything else, such as `{{}}` or code, is literal text.
+fn placeholder_name(inner: &str) -> Option<&str> {
+	let name = inner.trim();
+	let valid = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+	valid.then_some(name)
+}
+
+/// Returns the distinct placeholder names in `template`, in order of first appearance.
+pub fn placeholders(template: &str) -> Vec<String> {
+	let mut names: Vec<String> = vec![];
+	let mut rest = template;
+	while let Some(start) = rest.find("{{") {
+		rest = &rest[start + 2..];
+		let Some(end) = rest.find("}}") else {
+			break;
+		};
+		if let Some(name) = placeholder_name(&rest[..end]) {
+			if !names.iter().any(|known| known == name) {
+				names.push(name.to_string());
+			}
+		}
+		rest = &rest[end + 2..];
+	}
+	names
+}
+
+/// Replaces every `{{variable}}` in `template`. Fails with the names of the variables that were not supplied.
+pub fn render(template: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+	let missing: Vec<String> = placeholders(template).into_iter().filter(|name| !vars.contains_key(name)).collect();
+	if !missing.is_empty() {
+		return Err(format!("missing value for {}", missing.join(", ")));
+	}
+
+	let mut rendered = String::new();
+	let mut rest = template;
+	while let Some(start) = rest.find("{{") {
+		let Some(end) = rest[start..].find("}}") else {
+			break;
+		};
+		rendered.push_str(&rest[..start]);
+		match placeholder_name(&rest[start + 2..start + end]).and_then(|name| vars.get(name)) {
+			Some(value) => rendered.push_str(value),
+			None => rendered.push_str(&rest[start..start + end + 2]),
+		}
+		rest = &rest[start + end + 2..];
+	}
+	rendered.push_str(rest);
+	Ok(rendered)
+}
+
+/// Parses `key=value` pairs into template variables.
+pub fn parse_vars<S: AsRef<str>>(pairs: &[S]) -> Result<HashMap<String, String>, String> {
+	let mut vars = HashMap::new();
+	for pair in pairs {
+		let Some((key, value)) = pair.as_ref().split_once('=') else {
+			return Err(format!("expected key=value, found `{}`", pair.as_ref()));
+		};
+		vars.insert(key.trim().to_string(), value.to_string());
+	}
+	Ok(vars)
+}
+
+/// Splits a command line on whitespace, keeping single- or double-quoted sections together.
+pub fn split_arguments(line: &str) -> Vec<String> {
+	let mut arguments = vec![];
+	let mut current = String::new();
+	let mut quote: Option<char> = None;
+	let mut started = false;
+	for c in line.chars() {
+		match (quote, c) {
+			(Some(q), c) if c == q => quote = None,
+			(Some(_), c) => current.push(c),
+			(None, '"' | '\'') => {
+				quote = Some(c);
+				started = true;
+			},
+			(None, c) if c.is_whitespace() => {
+				if started {
+					arguments.push(std::mem::take(&mut current));
+					started = false;
+				}
+			},
+			(None, c) => {
+				current.push(c);
+				started = true;
+			},
+		}
+	}
+	if started {
+		arguments.push(current);
+	}
+	arguments
+}
+
+pub fn describe_params(params: &CompletionParams) -> String {
+	let settings: Vec<String> = [
+		("temperature", params.temperature.map(|value| value.to_string())),
+		("top_p", params.top_p.map(|value| value.to_string())),
+		("max_tokens", params.max_tokens.map(|value| value.to_string())),
+		("presence_penalty", params.presence_penalty.map(|value| value.to_string())),
+		("frequency_penalty", params.frequency_penalty.map(|value| value.to_string())),
+	]
+		.into_iter()
+		.filter_map(|(key, value)| value.map(|value| format!("{}={}", key, value)))
+		.collect();
+	if settings.is_empty() { "-".into() } else { settings.join(", ") }
+}
+
+pub fn run_persona_command(dir: &Path, command: PersonaCommand) -> Result<(), MainError> {
+	match command {
+		PersonaCommand::List => {
+			let personas = list_personas(dir)?;
+			if personas.is_empty() {
+				println!("No personas found. Add Markdown files to {}.", dir.display());
+			}
+			for persona in personas.iter() {
+				println!("{:<24} {}", persona.name, persona.description.as_deref().unwrap_or(""));
+			}
+		},
+		PersonaCommand::Show { name } => {
+			let persona = load_persona(dir, &name)?;
+			println!("Name: {}", persona.name);
+			println!("Description: {}", persona.description.as_deref().unwrap_or("-"));
+			println!("Model: {}", persona.model.as_deref().unwrap_or("-"));
+			println!("Parameters: {}", describe_params(&persona.params));
+			let variables = placeholders(&persona.prompt);
+			println!("Variables: {}", if variables.is_empty() { "-".into() } else { variables.join(", ") });
+			println!("Prompt:\n{}", persona.prompt);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn vars(pairs: &[&str]) -> HashMap<String, String> {
+		parse_vars(pairs).unwrap()
+	}
+
+	#[test]
+	fn names_every_missing_variable_once() {
+		let template = "Review {{ language }} code for {{audience}}, in {{language}}.";
+		assert_eq!(placeholders(template), ["language", "audience"]);
+		assert_eq!(render(template, &vars(&[])).unwrap_err(), "missing value for language, audience");
+		assert_eq!(render(template, &vars(&["language=Rust"])).unwrap_err(), "missing value for audience");
+	}
+
+	#[test]
+	fn replaces_repeated_variables_without_expanding_values() {
+		let rendered = render("{{name}} and {{ name }}: {{value}}", &vars(&["name=Ada", "value={{name}} = 1"])).unwrap();
+		assert_eq!(rendered, "Ada and Ada: {{name}} = 1");
+	}
+
+	#[test]
+	fn keeps_literal_braces() {
+		let template = "fn main() { let map = {}; } {{}} {{\"key\": 1}} {{unclosed";
+		assert!(placeholders(template).is_empty());
+		assert_eq!(render(template, &vars(&[])).unwrap(), template);
+	}
+}
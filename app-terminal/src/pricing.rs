@@ -0,0 +1,28 @@
+/// USD per 1K prompt and completion tokens. Entries are matched by the longest model prefix.
+static PRICES: &[(&str, f64, f64)] = &[
+	("gpt-4o-mini", 0.00015, 0.0006),
+	("gpt-4o", 0.005, 0.015),
+	("gpt-4-turbo", 0.01, 0.03),
+	("gpt-4-1106", 0.01, 0.03),
+	("gpt-4-0125", 0.01, 0.03),
+	("gpt-4-32k", 0.06, 0.12),
+	("gpt-4", 0.03, 0.06),
+	("gpt-3.5-turbo-16k", 0.003, 0.004),
+	("gpt-3.5-turbo", 0.0015, 0.002),
+	("text-embedding-3-small", 0.00002, 0.0),
+	("text-embedding-3-large", 0.00013, 0.0),
+	("text-embedding-ada-002", 0.0001, 0.0),
+];
+
+pub fn price_of(model: &str) -> Option<(f64, f64)> {
+	PRICES
+		.iter()
+		.filter(|(prefix, _, _)| model.starts_with(prefix))
+		.max_by_key(|(prefix, _, _)| prefix.len())
+		.map(|(_, prompt, completion)| (*prompt, *completion))
+}
+
+pub fn cost_of(model: Option<&str>, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+	let (prompt, completion) = price_of(model?)?;
+	Some(prompt_tokens as f64 / 1000.0 * prompt + completion_tokens as f64 / 1000.0 * completion)
+}
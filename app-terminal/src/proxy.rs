@@ -0,0 +1,504 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use clap::Args;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use openai::prelude::*;
+use database::*;
+
+use crate::attachment::{estimate_content_tokens, estimate_tokens};
+use crate::budget::{check_budgets, RequestEstimate};
+use crate::context::role_of;
+use crate::error::*;
+use crate::serve::{authorize, listen_until_interrupted, AccessArgs};
+use crate::tui::title_of;
+use crate::types::*;
+
+/// Header naming the conversation an exchange is recorded in, by ID or by title.
+static CONVERSATION_HEADER: &str = "x-conversation-id";
+/// Conversations ending with a message of a request that are checked for being continued by it.
+static CONTINUATION_CANDIDATES: u32 = 8;
+
+#[derive(Debug, Args)]
+pub struct ProxyArgs {
+	/// Address and port to listen on
+	#[arg(long, value_name = "Address", default_value = "127.0.0.1:8081")]
+	listen: SocketAddr,
+
+	#[command(flatten)]
+	access: AccessArgs,
+}
+
+/// An error returned in the format of the OpenAI API, so that clients report it as they would an API error.
+struct ProxyError(StatusCode, &'static str, String);
+
+impl IntoResponse for ProxyError {
+	fn into_response(self) -> Response {
+		(self.0, Json(json!({ "error": { "message": self.2, "type": self.1, "param": null, "code": self.1 } }))).into_response()
+	}
+}
+
+impl<T: Into<MainError>> From<T> for ProxyError {
+	fn from(value: T) -> Self {
+		Self(StatusCode::INTERNAL_SERVER_ERROR, "server_error", value.into().to_string())
+	}
+}
+
+/// A request on its way upstream, with what is needed to record it.
+struct Exchange {
+	conversation_id: u32,
+	model: String,
+	messages: Vec<Message>,
+	/// The last user message, which is recorded with the reply unless the conversation ends with it already.
+	prompt: Option<String>,
+	started: Instant
+}
+
+/// The messages of a request, for estimating its size and recording it. Roles this app does not know, such as
+/// tool results, count as user messages, and contents it cannot read as empty.
+fn messages_of(request: &Value) -> Vec<Message> {
+	request["messages"]
+		.as_array()
+		.map(|messages| messages.iter().map(|msg| Message {
+			role: match msg["role"].as_str() {
+				Some("system" | "developer") => MessageRole::System,
+				Some("assistant") => MessageRole::Assistant,
+				_ => MessageRole::User,
+			},
+			content: serde_json::from_value(msg["content"].clone()).unwrap_or(MessageContent::Text(String::new()))
+		}).collect())
+		.unwrap_or_default()
+}
+
+/// The user and assistant messages of a request with some text, in order. Tool results, system messages and
+/// tool calls without text are left out.
+fn dialog_of(request: &Value) -> Vec<(MessageRole, String)> {
+	request["messages"]
+		.as_array()
+		.map(|messages| messages.iter().filter_map(|msg| {
+			let role = match msg["role"].as_str() {
+				Some("user") => MessageRole::User,
+				Some("assistant") => MessageRole::Assistant,
+				_ => return None,
+			};
+			let text = serde_json::from_value::<MessageContent>(msg["content"].clone()).ok()?.text();
+			(!text.trim().is_empty()).then_some((role, text))
+		}).collect())
+		.unwrap_or_default()
+}
+
+/// The last user message of a dialog.
+fn prompt_of(dialog: &[(MessageRole, String)]) -> String {
+	dialog.iter().rev().find(|(role, _)| matches!(role, MessageRole::User)).map(|(_, text)| text.clone()).unwrap_or_default()
+}
+
+fn same_message(msg: &SavedMessage, (role, text): &(MessageRole, String)) -> bool {
+	matches!((role_of(msg), role), (MessageRole::User, MessageRole::User) | (MessageRole::Assistant, MessageRole::Assistant))
+		&& msg.content.trim() == text.trim()
+}
+
+/// The index of the last user or assistant message of the dialog that a stored conversation may end with, for the
+/// dialog to continue it: its latest reply or any message after it, which are user messages.
+fn continuation_start(dialog: &[(MessageRole, String)]) -> usize {
+	dialog.iter().rposition(|(role, _)| matches!(role, MessageRole::Assistant)).unwrap_or(0)
+}
+
+/// Where the last stored message of a conversation is in the dialog of a request, if the request continues the
+/// conversation: the dialog starts with the first stored message, the stored messages come in it in the same
+/// order, and only user messages follow the last one. After a reply that only called tools, that is the prompt.
+fn continues(history: &[SavedMessage], dialog: &[(MessageRole, String)]) -> Option<usize> {
+	let (first, rest) = history.split_first()?;
+	if !same_message(first, dialog.first()?) {
+		return None;
+	}
+	let Some((last, middle)) = rest.split_last() else {
+		return dialog[1..].iter().all(|(role, _)| matches!(role, MessageRole::User)).then_some(0);
+	};
+	let end = (continuation_start(dialog).max(1)..dialog.len()).rev().find(|index| same_message(last, &dialog[*index]))?;
+	let mut entries = dialog[1..end].iter();
+	middle.iter().all(|msg| entries.any(|entry| same_message(msg, entry))).then_some(end)
+}
+
+/// The conversation an exchange is recorded in, with the prompt to record unless the conversation ends with it:
+/// the one whose ID or title is given in the X-Conversation-Id header, which is created with that title if there
+/// is none. Without the header, it is the latest conversation with the model of the request that the request
+/// continues, as clients send the whole dialog each time, or else a new one titled after the prompt. New
+/// conversations keep the model and system prompt of the request as their settings.
+fn conversation_for(mgr: &ChatManager, name: Option<&str>, exchange_model: &str, messages: &[Message], dialog: &[(MessageRole, String)]) -> Result<(u32, Option<String>), ProxyError> {
+	let prompt = prompt_of(dialog);
+	// Tool results follow the prompt they answer, which is then stored already
+	let with_prompt = |conversation_id: u32, end: Option<usize>| {
+		let stored = end.is_some_and(|end| end + 1 == dialog.len() && matches!(dialog[end].0, MessageRole::User));
+		(conversation_id, (!stored).then(|| prompt.clone()))
+	};
+
+	let Some(name) = name else {
+		for index in (continuation_start(dialog)..dialog.len()).rev() {
+			let (role, text) = &dialog[index];
+			let role = if matches!(role, MessageRole::User) { "user" } else { "assistant" };
+			for id in Database::get_conversations_ending_with(&mgr.connection, &mgr.api_key, exchange_model, role, text, CONTINUATION_CANDIDATES)? {
+				if let Some(end) = continues(&Database::get_all_messages_in_conversation(&mgr.connection, id)?, dialog) {
+					return Ok(with_prompt(id, Some(end)));
+				}
+			}
+		}
+		let conversation_id = add_conversation(mgr, &title_of(&prompt), exchange_model, messages)?;
+		return Ok(with_prompt(conversation_id, None));
+	};
+
+	let conversations = Database::get_all_conversations(&mgr.connection, &mgr.api_key)?;
+	let conversation_id = match name.parse::<u32>() {
+		Ok(id) => conversations
+			.iter()
+			.find(|conv| conv.id == id)
+			.map(|conv| conv.id)
+			.ok_or(ProxyError(StatusCode::NOT_FOUND, "invalid_request_error", format!("No conversation {}.", id)))?,
+		Err(_) => match conversations.iter().rev().find(|conv| conv.title == name) {
+			Some(conv) => conv.id,
+			None => add_conversation(mgr, name, exchange_model, messages)?,
+		},
+	};
+	let history = Database::get_all_messages_in_conversation(&mgr.connection, conversation_id)?;
+	let end = history.last().and_then(|last| dialog.len().checked_sub(1).filter(|end| same_message(last, &dialog[*end])));
+	Ok(with_prompt(conversation_id, end))
+}
+
+fn add_conversation(mgr: &ChatManager, title: &str, exchange_model: &str, messages: &[Message]) -> Result<u32, MainError> {
+	let conversation_id = Database::add_conversation(&mgr.connection, title, &mgr.api_key)?;
+	let settings = ConversationSettings {
+		model: Some(exchange_model.into()),
+		system_prompt: messages.iter().find(|msg| matches!(msg.role, MessageRole::System)).map(|msg| msg.content.text()),
+		..Default::default()
+	};
+	Database::set_conversation_settings(&mgr.connection, conversation_id, &settings)?;
+	Ok(conversation_id)
+}
+
+/// Stores the last user message of a request with the reply to it, estimating usage the API did not report, as
+/// for streams without `stream_options.include_usage`. A reply without text, which only calls tools, is not
+/// stored, and the prompt is stored alone to be continued by the request with the tool results.
+fn record_reply(mgr: &ChatManager, exchange: &Exchange, model: Option<String>, text: String, usage: Option<TokenUsage>) -> Result<(), MainError> {
+	let usage = usage.filter(|usage| usage.total_tokens > 0).unwrap_or_else(|| {
+		let prompt_tokens: u64 = exchange.messages.iter().map(|msg| estimate_content_tokens(&msg.content)).sum();
+		let completion_tokens = estimate_tokens(&text, &[]);
+		TokenUsage { prompt_tokens, completion_tokens, total_tokens: prompt_tokens + completion_tokens }
+	});
+	let latency_ms = exchange.started.elapsed().as_millis() as u64;
+	println!("Conversation {}: {} tokens with {} in {:.1} s.", exchange.conversation_id, usage.total_tokens, model.as_deref().unwrap_or(&exchange.model), latency_ms as f64 / 1000.0);
+
+	let reply = CompletionResponse {
+		id: String::new(),
+		object: "chat.completion".into(),
+		created: 0,
+		model: model.unwrap_or(exchange.model.clone()),
+		usage,
+		choices: vec![ResponseChoice {
+			index: 0,
+			finish_reason: None,
+			message: Message { role: MessageRole::Assistant, content: MessageContent::Text(text) }
+		}]
+	};
+	if let Some(prompt) = &exchange.prompt {
+		Database::add_client_message(&mgr.connection, exchange.conversation_id, prompt)?;
+	}
+	if !reply.msg().trim().is_empty() {
+		Database::add_server_message(&mgr.connection, exchange.conversation_id, &reply, latency_ms)?;
+	}
+	Ok(())
+}
+
+fn record_error(mgr: &ChatManager, exchange: &Exchange, error: &str) -> Result<(), MainError> {
+	println!("Conversation {}: {}", exchange.conversation_id, error);
+	let openai_error = serde_json::from_str::<OpenAIError>(error).ok();
+	Database::add_error_log(&mgr.connection, &mgr.api_key, &exchange.model, Some(exchange.conversation_id), &exchange.messages, error, openai_error.as_ref())?;
+	Ok(())
+}
+
+/// Collects the reply and usage from the server-sent events of a streamed completion as they are passed on.
+#[derive(Default)]
+struct StreamCollector {
+	buffer: Vec<u8>,
+	text: String,
+	model: Option<String>,
+	usage: Option<TokenUsage>
+}
+
+impl StreamCollector {
+	fn feed(&mut self, bytes: &[u8]) {
+		self.buffer.extend_from_slice(bytes);
+		while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+			let line: Vec<u8> = self.buffer.drain(..=end).collect();
+			let line = String::from_utf8_lossy(&line);
+			let Some(chunk) = line.trim().strip_prefix("data:").and_then(|data| serde_json::from_str::<CompletionChunk>(data.trim()).ok()) else {
+				continue
+			};
+			self.model = Some(chunk.model);
+			self.usage = chunk.usage.or(self.usage.take());
+			for choice in chunk.choices.into_iter().filter(|choice| choice.index == 0) {
+				self.text.push_str(&choice.delta.content.unwrap_or_default());
+			}
+		}
+	}
+}
+
+/// Passes a streamed reply on to the client as it arrives and records it once complete, also when the client
+/// goes away before that.
+fn relay_stream(state: Arc<Mutex<ChatManager>>, exchange: Exchange, mut upstream: reqwest::Response) -> Body {
+	let (sender, receiver) = unbounded_channel::<Result<Bytes, std::io::Error>>();
+	tokio::spawn(async move {
+		let mut collector = StreamCollector::default();
+		let mut failure = None;
+		loop {
+			match upstream.chunk().await {
+				Ok(Some(bytes)) => {
+					collector.feed(&bytes);
+					let _ = sender.send(Ok(bytes));
+				},
+				Ok(None) => break,
+				Err(err) => {
+					failure = Some(err.to_string());
+					break
+				}
+			}
+		}
+		drop(sender);
+
+		let mgr = state.lock().await;
+		let recorded = match failure {
+			Some(error) => record_error(&mgr, &exchange, &error),
+			None => record_reply(&mgr, &exchange, collector.model, collector.text, collector.usage),
+		};
+		if let Err(err) = recorded {
+			println!("Conversation {}: the exchange could not be recorded: {}", exchange.conversation_id, err);
+		}
+	});
+	Body::from_stream(UnboundedReceiverStream::new(receiver))
+}
+
+/// The size of a request for the budgets: its messages with the tools it offers and the tool calls in them, and
+/// replies as long as `max_completion_tokens` or `max_tokens` allows, or else of the expected size, for each of
+/// the `n` choices asked for.
+fn estimate_request(request: &Value, model: &str, messages: &[Message]) -> RequestEstimate {
+	let max_tokens = request["max_completion_tokens"].as_u64().or(request["max_tokens"].as_u64());
+	let mut estimate = RequestEstimate::chat(model, messages, max_tokens.map(|tokens| u32::try_from(tokens).unwrap_or(u32::MAX)));
+	let tool_calls = request["messages"].as_array().into_iter().flatten().map(|msg| &msg["tool_calls"]);
+	estimate.prompt_tokens += [&request["tools"], &request["functions"]]
+		.into_iter()
+		.chain(tool_calls)
+		.filter(|value| !value.is_null())
+		.map(|value| estimate_tokens(&value.to_string(), &[]))
+		.sum::<u64>();
+	estimate.completion_tokens *= request["n"].as_u64().unwrap_or(1).max(1);
+	estimate
+}
+
+/// Forwards a chat completion request upstream with the stored key, after checking it against the budgets, and
+/// records the exchange.
+async fn chat_completions(State(state): State<Arc<Mutex<ChatManager>>>, headers: HeaderMap, body: Bytes) -> Result<Response, ProxyError> {
+	let request: Value = serde_json::from_slice(&body)
+		.ok()
+		.filter(Value::is_object)
+		.ok_or(ProxyError(StatusCode::BAD_REQUEST, "invalid_request_error", "The body must be a JSON object.".into()))?;
+	let model = request["model"]
+		.as_str()
+		.ok_or(ProxyError(StatusCode::BAD_REQUEST, "invalid_request_error", "The request has no model.".into()))?
+		.to_string();
+	let messages = messages_of(&request);
+	let dialog = dialog_of(&request);
+	let name = headers.get(CONVERSATION_HEADER).and_then(|value| value.to_str().ok()).map(str::trim).filter(|name| !name.is_empty());
+
+	let (exchange, api_key, proxy, api_base) = {
+		let mgr = state.lock().await;
+		let (conversation_id, prompt) = conversation_for(&mgr, name, &model, &messages, &dialog)?;
+		if !mgr.ignore_budget {
+			let check = check_budgets(&mgr, &mgr.api_key, Some(conversation_id), &[estimate_request(&request, &model, &messages)])?;
+			if let Some(refusal) = check.refusal {
+				println!("Conversation {}: {}", conversation_id, refusal);
+				return Err(ProxyError(StatusCode::TOO_MANY_REQUESTS, "budget_exceeded", refusal));
+			}
+			for warning in check.warnings.iter() {
+				println!("Conversation {}: {}", conversation_id, warning);
+			}
+		}
+		let exchange = Exchange { conversation_id, model, messages, prompt, started: Instant::now() };
+		(exchange, mgr.api_key.clone(), mgr.proxy.clone(), mgr.api_base.clone())
+	};
+
+	let upstream = match forward_request("chat/completions", body.to_vec(), &api_key, &proxy, &api_base).await {
+		Ok(upstream) => upstream,
+		Err(err) => {
+			record_error(&*state.lock().await, &exchange, &err)?;
+			return Err(ProxyError(StatusCode::BAD_GATEWAY, "upstream_error", err));
+		}
+	};
+
+	let status = StatusCode::from_u16(upstream.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+	let content_type = upstream.headers().get("content-type").and_then(|value| value.to_str().ok()).unwrap_or("application/json").to_string();
+	let conversation_id = HeaderValue::from(exchange.conversation_id);
+	let mut response = if status.is_success() && content_type.starts_with("text/event-stream") {
+		let body = relay_stream(state.clone(), exchange, upstream);
+		(status, [(header::CACHE_CONTROL, "no-cache")], body).into_response()
+	}
+	else {
+		let text = upstream.text().await.map_err(|err| ProxyError(StatusCode::BAD_GATEWAY, "upstream_error", err.to_string()))?;
+		let mgr = state.lock().await;
+		match serde_json::from_str::<Value>(&text) {
+			Ok(reply) if status.is_success() => {
+				let content = reply["choices"][0]["message"]["content"].as_str().unwrap_or_default().to_string();
+				let usage = serde_json::from_value::<TokenUsage>(reply["usage"].clone()).ok();
+				record_reply(&mgr, &exchange, reply["model"].as_str().map(str::to_string), content, usage)?;
+			},
+			_ => record_error(&mgr, &exchange, &text)?,
+		}
+		(status, text).into_response()
+	};
+	response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_str(&content_type).unwrap_or(HeaderValue::from_static("application/json")));
+	response.headers_mut().insert(CONVERSATION_HEADER, conversation_id);
+	Ok(response)
+}
+
+/// Relays chat completions to the API until interrupted, recording each exchange in the database.
+pub async fn run_proxy(mgr: ChatManager, args: ProxyArgs) -> Result<(), MainError> {
+	let access = Arc::new(args.access.into_access(&args.listen)?);
+	let router = Router::new()
+		.route("/v1/chat/completions", post(chat_completions))
+		.layer(middleware::from_fn_with_state(access, authorize))
+		.with_state(Arc::new(Mutex::new(mgr)));
+	println!("Point clients at http://{}/v1 as their API base. Exchanges are recorded in conversations named by the X-Conversation-Id header.", args.listen);
+	listen_until_interrupted(router, args.listen).await
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use super::*;
+	use crate::test_support::*;
+
+	async fn numbered_reply(State(replies): State<Arc<AtomicUsize>>) -> Json<Value> {
+		let number = replies.fetch_add(1, Ordering::SeqCst) + 1;
+		Json(json!({
+			"id": "chatcmpl-test",
+			"object": "chat.completion",
+			"created": 0,
+			"model": "gpt-4o",
+			"choices": [{ "index": 0, "message": { "role": "assistant", "content": format!("reply {}", number) }, "finish_reason": "stop" }],
+			"usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+		}))
+	}
+
+	async fn send(state: &Arc<Mutex<ChatManager>>, messages: Value) -> u32 {
+		let body = json!({ "model": "gpt-4o", "messages": messages }).to_string();
+		let response = chat_completions(State(state.clone()), HeaderMap::new(), Bytes::from(body)).await.map_err(|err| err.2).unwrap();
+		response.headers()[CONVERSATION_HEADER].to_str().unwrap().parse().unwrap()
+	}
+
+	#[tokio::test]
+	async fn continues_conversations_without_the_header() {
+		let router = Router::new().route("/v1/chat/completions", post(numbered_reply)).with_state(Arc::new(AtomicUsize::new(0)));
+		let state = Arc::new(Mutex::new(test_manager(&serve_stub(router).await)));
+
+		let system = json!({ "role": "system", "content": "Be brief." });
+		let first = send(&state, json!([system, { "role": "user", "content": "hello" }])).await;
+		let other = send(&state, json!([system, { "role": "user", "content": "something else" }])).await;
+		assert_ne!(first, other);
+
+		let second = send(&state, json!([
+			system,
+			{ "role": "user", "content": "hello" },
+			{ "role": "assistant", "content": "reply 1" },
+			{ "role": "user", "content": "what time is it?" },
+			{ "role": "assistant", "content": null, "tool_calls": [{ "id": "call", "type": "function", "function": { "name": "now", "arguments": "{}" } }] },
+			{ "role": "tool", "tool_call_id": "call", "content": "12:00" }
+		])).await;
+		assert_eq!(second, first);
+
+		let mgr = state.lock().await;
+		assert_eq!(mgr.model, "gpt-4");
+		let texts: Vec<String> = Database::get_all_messages_in_conversation(&mgr.connection, first).unwrap().into_iter().map(|msg| msg.content).collect();
+		assert_eq!(texts, ["hello", "reply 1", "what time is it?", "reply 3"]);
+	}
+
+	/// Calls a tool for the latest user message, and answers once given its result.
+	async fn tool_reply(Json(request): Json<Value>) -> Json<Value> {
+		let last = request["messages"].as_array().unwrap().last().unwrap().clone();
+		let message = match last["role"].as_str() {
+			Some("tool") => json!({ "role": "assistant", "content": format!("It is {}.", last["content"].as_str().unwrap()) }),
+			_ => json!({ "role": "assistant", "content": null, "tool_calls": [{ "id": "call", "type": "function", "function": { "name": "now", "arguments": "{}" } }] }),
+		};
+		Json(json!({
+			"id": "chatcmpl-test",
+			"object": "chat.completion",
+			"created": 0,
+			"model": "gpt-4o",
+			"choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+			"usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+		}))
+	}
+
+	#[tokio::test]
+	async fn records_tool_calls_in_the_conversation_they_continue() {
+		let router = Router::new().route("/v1/chat/completions", post(tool_reply));
+		let state = Arc::new(Mutex::new(test_manager(&serve_stub(router).await)));
+
+		let question = json!({ "role": "user", "content": "What time is it?" });
+		let tool_call = json!({ "role": "assistant", "content": null, "tool_calls": [{ "id": "call", "type": "function", "function": { "name": "now", "arguments": "{}" } }] });
+		let tool_result = json!({ "role": "tool", "tool_call_id": "call", "content": "noon" });
+		let first = send(&state, json!([question])).await;
+		let second = send(&state, json!([question, tool_call, tool_result])).await;
+		let third = send(&state, json!([question, tool_call, tool_result, { "role": "assistant", "content": "It is noon." }, { "role": "user", "content": "Thanks" }])).await;
+		assert_eq!((second, third), (first, first));
+		let again = send(&state, json!([question, tool_call, tool_result, { "role": "assistant", "content": "It is noon." }, { "role": "user", "content": "Thanks" }, tool_call, tool_result])).await;
+		assert_eq!(again, first);
+
+		let mgr = state.lock().await;
+		assert_eq!(Database::get_all_conversations(&mgr.connection, &mgr.api_key).unwrap().len(), 1);
+		let texts: Vec<String> = Database::get_all_messages_in_conversation(&mgr.connection, first).unwrap().into_iter().map(|msg| msg.content).collect();
+		assert_eq!(texts, ["What time is it?", "It is noon.", "Thanks", "It is noon."]);
+	}
+
+	#[tokio::test]
+	async fn only_continues_conversations_with_the_same_model() {
+		let router = Router::new().route("/v1/chat/completions", post(numbered_reply)).with_state(Arc::new(AtomicUsize::new(0)));
+		let state = Arc::new(Mutex::new(test_manager(&serve_stub(router).await)));
+
+		let first = send(&state, json!([{ "role": "user", "content": "hello" }])).await;
+		let dialog = json!([{ "role": "user", "content": "hello" }, { "role": "assistant", "content": "reply 1" }, { "role": "user", "content": "again" }]);
+		let body = json!({ "model": "gpt-4o-mini", "messages": dialog }).to_string();
+		let response = chat_completions(State(state.clone()), HeaderMap::new(), Bytes::from(body)).await.map_err(|err| err.2).unwrap();
+		let other: u32 = response.headers()[CONVERSATION_HEADER].to_str().unwrap().parse().unwrap();
+		assert_ne!(other, first);
+		assert_eq!(send(&state, dialog).await, first);
+	}
+
+	#[test]
+	fn estimates_the_whole_request() {
+		let request = json!({
+			"model": "gpt-4o",
+			"messages": [{ "role": "user", "content": "x".repeat(200) }],
+			"tools": [{ "type": "function", "function": { "name": "now", "description": "x".repeat(100) } }],
+			"max_completion_tokens": 1000,
+			"n": 2
+		});
+		let messages = messages_of(&request);
+		let estimate = estimate_request(&request, "gpt-4o", &messages);
+		assert_eq!(estimate.model, "gpt-4o");
+		assert!(estimate.prompt_tokens > 150);
+		assert_eq!(estimate.completion_tokens, 2000);
+
+		let estimate = estimate_request(&json!({ "max_tokens": 300 }), "gpt-4o", &messages);
+		assert_eq!((estimate.prompt_tokens, estimate.completion_tokens), (100, 300));
+	}
+}
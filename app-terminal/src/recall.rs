@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+
+use clap::Args;
+use serde_json::json;
+
+use openai::prelude::*;
+use database::*;
+
+use crate::attachment::estimate_tokens;
+use crate::budget::RequestEstimate;
+use crate::error::*;
+use crate::types::*;
+
+/// Messages embedded per request while indexing.
+static INDEX_BATCH_SIZE: u32 = 64;
+
+/// Embedding inputs are cut to this many characters to stay below the model's input limit.
+static MAX_INPUT_CHARS: usize = 8000;
+
+static SNIPPET_CHARS: usize = 300;
+
+#[derive(Debug, Args)]
+pub struct RecallArgs {
+	/// What to look for
+	#[arg(required = true, trailing_var_arg = true)]
+	query: Vec<String>,
+
+	/// Number of results
+	#[arg(long, default_value = "5")]
+	top: usize,
+}
+
+/// Records the usage of a successful embedding request for budgets and stats, or logs the error.
+fn record_embedding(mgr: &ChatManager, response: Result<OpenAIResponse<EmbeddingResponse>, String>) -> Result<Option<EmbeddingResponse>, MainError> {
+	match response {
+		Ok(OpenAIResponse::Success(embedding_response)) => {
+			Database::add_embedding_usage(&mgr.connection, &mgr.api_key, &mgr.embedding_model, embedding_response.usage.prompt_tokens)?;
+			Ok(Some(embedding_response))
+		},
+		Ok(OpenAIResponse::Failure(openai_error)) => {
+			Database::add_error_log(&mgr.connection, &mgr.api_key, &mgr.embedding_model, None, &[], &json!(openai_error).to_string(), Some(&openai_error))?;
+			println!("Embedding error: {}", openai_error.error.message);
+			Ok(None)
+		},
+		Err(err) => {
+			Database::add_error_log(&mgr.connection, &mgr.api_key, &mgr.embedding_model, None, &[], &err, None)?;
+			println!("Embedding error: {}", err);
+			Ok(None)
+		}
+	}
+}
+
+/// Turns a response that does not match the inputs into an error, so that it is logged like a failed request.
+fn check_indices(response: OpenAIResponse<EmbeddingResponse>, inputs: usize) -> Result<OpenAIResponse<EmbeddingResponse>, String> {
+	if let OpenAIResponse::Success(embedding_response) = &response {
+		if let Some(embedding) = embedding_response.data.iter().find(|embedding| embedding.index >= inputs) {
+			return Err(format!("The embedding server returned index {} for {} input(s)", embedding.index, inputs));
+		}
+	}
+	Ok(response)
+}
+
+fn embedding_input(text: &str) -> String {
+	let text = text.trim();
+	if text.is_empty() {
+		return " ".into();
+	}
+	text.chars().take(MAX_INPUT_CHARS).collect()
+}
+
+/// Estimates the embedding requests that recalling messages for `query` makes: indexing the messages that have
+/// no embedding yet, and the query itself.
+pub fn estimate_recall(mgr: &ChatManager, query: &str) -> Result<RequestEstimate, MainError> {
+	let unindexed = Database::get_messages_without_embedding(&mgr.connection, &mgr.api_key, &mgr.embedding_model, u32::MAX)?;
+	let prompt_tokens = unindexed
+		.iter()
+		.map(|(_, content)| content.as_str())
+		.chain([query])
+		.map(|text| estimate_tokens(&embedding_input(text), &[]))
+		.sum();
+	Ok(RequestEstimate { model: mgr.embedding_model.clone(), prompt_tokens, completion_tokens: 0 })
+}
+
+/// Estimates the prompt tokens that `k` recalled excerpts, each with the title of its conversation, add to the context.
+pub fn estimate_recalled_tokens(k: usize) -> u64 {
+	(SNIPPET_CHARS as u64 + 100) / 2 * k as u64
+}
+
+/// Embeds every message of the current profile that has not been embedded yet. Returns the number of new embeddings.
+/// Stops early if the server fails or leaves out every input of a batch.
+pub async fn index_messages(mgr: &ChatManager) -> Result<usize, MainError> {
+	let mut indexed = 0;
+	loop {
+		let messages = Database::get_messages_without_embedding(&mgr.connection, &mgr.api_key, &mgr.embedding_model, INDEX_BATCH_SIZE)?;
+		if messages.is_empty() {
+			break;
+		}
+
+		let input: Vec<String> = messages.iter().map(|(_, content)| embedding_input(content)).collect();
+		let response = get_embeddings(&input, &mgr.api_key, &mgr.proxy, &mgr.api_base, &mgr.embedding_model).await
+			.and_then(|response| check_indices(response, messages.len()));
+		let Some(embedding_response) = record_embedding(mgr, response)? else {
+			break;
+		};
+
+		let mut embedded = HashSet::new();
+		for embedding in embedding_response.data.iter() {
+			let Some((message_id, _)) = messages.get(embedding.index) else {
+				continue
+			};
+			Database::add_embedding(&mgr.connection, *message_id, &mgr.embedding_model, &embedding.embedding)?;
+			embedded.insert(*message_id);
+		}
+		if embedded.is_empty() {
+			println!("Embedding error: the server returned no embeddings for {} message(s).", messages.len());
+			break;
+		}
+		indexed += embedded.len();
+	}
+	Ok(indexed)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+	if a.len() != b.len() {
+		return 0.0;
+	}
+	let mut dot = 0.0;
+	let mut norm_a = 0.0;
+	let mut norm_b = 0.0;
+	for (x, y) in a.iter().zip(b.iter()) {
+		dot += x * y;
+		norm_a += x * x;
+		norm_b += y * y;
+	}
+	if norm_a == 0.0 || norm_b == 0.0 {
+		return 0.0;
+	}
+	dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Finds the `top` stored messages most similar to `query`, best match first.
+pub async fn search(mgr: &ChatManager, query: &str, exclude_conversation: Option<u32>, top: usize) -> Result<Vec<(f32, EmbeddedMessage)>, MainError> {
+	index_messages(mgr).await?;
+
+	let response = get_embeddings(&[embedding_input(query)], &mgr.api_key, &mgr.proxy, &mgr.api_base, &mgr.embedding_model).await;
+	let Some(embedding_response) = record_embedding(mgr, response)? else {
+		return Ok(vec![]);
+	};
+	let Some(query_vector) = embedding_response.data.first().map(|embedding| &embedding.embedding) else {
+		return Ok(vec![]);
+	};
+
+	let mut scored: Vec<(f32, EmbeddedMessage)> = Database::get_embedded_messages(&mgr.connection, &mgr.api_key, &mgr.embedding_model, exclude_conversation)?
+		.into_iter()
+		.map(|msg| (cosine_similarity(query_vector, &msg.vector), msg))
+		.collect();
+	scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+	scored.truncate(top);
+	Ok(scored)
+}
+
+fn snippet_of(content: &str) -> String {
+	let flattened = content.split_whitespace().collect::<Vec<&str>>().join(" ");
+	if flattened.chars().count() > SNIPPET_CHARS {
+		format!("{}...", flattened.chars().take(SNIPPET_CHARS).collect::<String>())
+	}
+	else {
+		flattened
+	}
+}
+
+/// Builds a system message with the past exchanges most relevant to `prompt`, taken from other conversations.
+pub async fn recall_context(mgr: &ChatManager, conversation_id: u32, prompt: &str) -> Result<Option<Message>, MainError> {
+	let results = search(mgr, prompt, Some(conversation_id), mgr.recall_k).await?;
+	if results.is_empty() {
+		return Ok(None);
+	}
+
+	let snippets = results
+		.iter()
+		.map(|(_, msg)| format!("- From \"{}\" ({}): {}", msg.title, msg.role, snippet_of(&msg.content)))
+		.collect::<Vec<String>>()
+		.join("\n");
+	Ok(Some(Message {
+		role: MessageRole::System,
+		content: format!("Possibly relevant excerpts from the user's other conversations:\n{}", snippets).into()
+	}))
+}
+
+pub async fn run_recall_command(mgr: &ChatManager, args: RecallArgs) -> Result<(), MainError> {
+	let query = args.query.join(" ");
+	let indexed = index_messages(mgr).await?;
+	if indexed > 0 {
+		println!("Indexed {} new message(s).", indexed);
+	}
+
+	let results = search(mgr, &query, None, args.top).await?;
+	if results.is_empty() {
+		println!("Nothing found.");
+	}
+	for (score, msg) in results.iter() {
+		println!("[{:.3}] {}: {} (Message {}, {})", score, msg.conversation_id, msg.title, msg.message_id, msg.role);
+		println!("    {}", snippet_of(&msg.content));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::{routing::post, Json, Router};
+	use serde_json::Value;
+
+	use super::*;
+	use crate::test_support::*;
+
+	static TOPICS: &[&str] = &["cat", "rust", "sql"];
+
+	/// Embeds a text as how often it mentions each topic, so that similar texts share a topic.
+	fn topic_vector(text: &str) -> Vec<f32> {
+		let text = text.to_lowercase();
+		TOPICS.iter().map(|topic| text.matches(topic).count() as f32).collect()
+	}
+
+	async fn embeddings(Json(request): Json<Value>) -> Json<Value> {
+		let data: Vec<Value> = request["input"]
+			.as_array()
+			.unwrap()
+			.iter()
+			.enumerate()
+			.map(|(index, input)| json!({ "index": index, "embedding": topic_vector(input.as_str().unwrap()) }))
+			.collect();
+		Json(json!({ "model": request["model"], "data": data, "usage": { "prompt_tokens": 1, "total_tokens": 1 } }))
+	}
+
+	fn add_messages(mgr: &ChatManager, messages: &[&str]) {
+		let id = Database::add_conversation(&mgr.connection, "Pets and code", &mgr.api_key).unwrap();
+		for content in messages {
+			Database::add_client_message(&mgr.connection, id, content).unwrap();
+		}
+	}
+
+	#[tokio::test]
+	async fn recalls_indexed_messages_by_meaning() {
+		let api_base = serve_stub(Router::new().route("/v1/embeddings", post(embeddings))).await;
+		let mgr = test_manager(&api_base);
+		add_messages(&mgr, &["My cat sleeps all day", "Rust lifetimes are hard", "An SQL join question"]);
+
+		assert_eq!(index_messages(&mgr).await.unwrap(), 3);
+		assert_eq!(index_messages(&mgr).await.unwrap(), 0);
+
+		let results = search(&mgr, "why does my cat purr", None, 1).await.unwrap();
+		assert_eq!(results.len(), 1);
+		assert_eq!(results[0].1.content, "My cat sleeps all day");
+		let results = search(&mgr, "rust borrow checker", None, 3).await.unwrap();
+		assert_eq!(results[0].1.content, "Rust lifetimes are hard");
+
+		// One indexing request and two queries
+		let usage = Database::get_usage_records(&mgr.connection).unwrap();
+		assert_eq!(usage.len(), 3);
+		assert!(usage.iter().all(|record| record.model.as_deref() == Some("text-embedding-3-small") && record.conversation_id.is_none()));
+	}
+
+	#[tokio::test]
+	async fn stops_on_bad_or_missing_embeddings() {
+		let out_of_range = Router::new().route("/v1/embeddings", post(|| async {
+			Json(json!({ "model": "m", "data": [{ "index": 5, "embedding": [1.0] }], "usage": { "prompt_tokens": 1, "total_tokens": 1 } }))
+		}));
+		let mgr = test_manager(&serve_stub(out_of_range).await);
+		add_messages(&mgr, &["one", "two"]);
+		assert_eq!(index_messages(&mgr).await.unwrap(), 0);
+
+		let empty = Router::new().route("/v1/embeddings", post(|| async {
+			Json(json!({ "model": "m", "data": [], "usage": { "prompt_tokens": 1, "total_tokens": 1 } }))
+		}));
+		let mgr = test_manager(&serve_stub(empty).await);
+		add_messages(&mgr, &["one", "two"]);
+		assert_eq!(index_messages(&mgr).await.unwrap(), 0);
+	}
+}
@@ -0,0 +1,60 @@
+use similar::TextDiff;
+
+use database::*;
+
+use crate::editor::edit_text;
+use crate::error::*;
+use crate::types::*;
+
+fn find_message(session: &ChatSession, argument: &str) -> Option<usize> {
+	let id = argument.trim_start_matches('#').parse::<u32>().ok()?;
+	session.history.iter().position(|msg| msg.id == id)
+}
+
+/// Opens a message of the current conversation in the editor and saves the result as its latest revision.
+pub fn edit_message(mgr: &mut ChatManager, argument: &str) -> Result<(), MainError> {
+	let session = mgr.current_session.as_mut().unwrap();
+	let Some(index) = find_message(session, argument) else {
+		println!("No such message in this conversation. Message IDs are shown when a conversation is resumed.");
+		return Ok(());
+	};
+	let msg = &session.history[index];
+
+	let Some(content) = edit_text(&msg.content)? else {
+		println!("Message {} was not changed.", msg.id);
+		return Ok(());
+	};
+	let revision = Database::edit_message(&mgr.connection, msg.id, &content)?;
+	println!("Message {} updated. The previous content was kept as revision {}.", msg.id, revision);
+	session.history = Database::get_all_messages_in_conversation(&mgr.connection, session.conversation_id)?;
+	Ok(())
+}
+
+/// Shows how a message changed from one revision to the next, ending with its current content.
+pub fn print_revisions(mgr: &ChatManager, argument: &str) -> Result<(), MainError> {
+	let session = mgr.current_session.as_ref().unwrap();
+	let Some(index) = find_message(session, argument) else {
+		println!("No such message in this conversation. Message IDs are shown when a conversation is resumed.");
+		return Ok(());
+	};
+	let msg = &session.history[index];
+
+	let revisions = Database::get_message_revisions(&mgr.connection, msg.id)?;
+	if revisions.is_empty() {
+		println!("Message {} has not been edited.", msg.id);
+		return Ok(());
+	}
+
+	let mut versions: Vec<(String, &str)> = revisions
+		.iter()
+		.map(|revision| (format!("revision {} (replaced {})", revision.revision, revision.updateat.format("%Y-%m-%d %H:%M:%S")), revision.content.as_str()))
+		.collect();
+	versions.push(("current".into(), msg.content.as_str()));
+
+	for pair in versions.windows(2) {
+		let ((old_name, old), (new_name, new)) = (&pair[0], &pair[1]);
+		let diff = TextDiff::from_lines(*old, *new);
+		print!("{}", diff.unified_diff().context_radius(3).missing_newline_hint(false).header(old_name, new_name));
+	}
+	Ok(())
+}
@@ -0,0 +1,397 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use clap::Args;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::runtime::Handle;
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+
+use openai::prelude::*;
+use database::*;
+
+use crate::chat::*;
+use crate::error::*;
+use crate::settings::restore_settings;
+use crate::types::*;
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+	/// Address and port to listen on
+	#[arg(long, value_name = "Address", default_value = "127.0.0.1:8080")]
+	listen: SocketAddr,
+
+	#[command(flatten)]
+	access: AccessArgs,
+}
+
+/// Who may use a server, shared by `ai serve` and `ai proxy`.
+#[derive(Debug, Args)]
+pub struct AccessArgs {
+	/// Token that clients must send as "Authorization: Bearer <token>"; required to listen on other addresses than loopback
+	#[arg(long, value_name = "Token")]
+	token: Option<String>,
+
+	/// Read the token from a file
+	#[arg(long, value_name = "Path", conflicts_with = "token")]
+	token_file: Option<PathBuf>,
+
+	/// Let web pages from this origin, such as "http://localhost:3000", call the server
+	#[arg(long, value_name = "Origin")]
+	allow_origin: Vec<String>,
+}
+
+pub struct Access {
+	token: Option<String>,
+	allowed_origins: Vec<String>
+}
+
+impl AccessArgs {
+	/// Reads the token, which is required unless the server only listens on a loopback address.
+	pub fn into_access(self, listen: &SocketAddr) -> Result<Access, MainError> {
+		let token = match (self.token, self.token_file) {
+			(Some(token), _) => Some(token),
+			(None, Some(path)) => Some(std::fs::read_to_string(&path)
+				.map_err(|err| ArgumentError::new("token", &format!("{}: {}", path.display(), err)))?
+				.trim()
+				.to_string()),
+			(None, None) => None,
+		};
+		if token.as_ref().is_some_and(|token| token.is_empty()) {
+			return Err(ArgumentError::new("token", "The token is empty.").into());
+		}
+		if token.is_none() && !listen.ip().is_loopback() {
+			return Err(ArgumentError::new("token", "A token is required to listen on other addresses than loopback. Pass --token or --token-file.").into());
+		}
+		Ok(Access { token, allowed_origins: self.allow_origin })
+	}
+}
+
+struct ServerState {
+	/// Each request opens the conversation it is about as the current session, so requests take turns.
+	mgr: Arc<Mutex<ChatManager>>,
+	/// Conversations whose reply is being streamed.
+	replying: std::sync::Mutex<HashSet<u32>>
+}
+
+type SharedState = Arc<ServerState>;
+
+/// An error returned to the client as `{"error": message}`.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+	fn into_response(self) -> Response {
+		(self.0, Json(json!({ "error": self.1 }))).into_response()
+	}
+}
+
+impl<T: Into<MainError>> From<T> for ApiError {
+	fn from(value: T) -> Self {
+		Self(StatusCode::INTERNAL_SERVER_ERROR, value.into().to_string())
+	}
+}
+
+#[derive(Deserialize)]
+struct NewConversation {
+	title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Rename {
+	title: String,
+}
+
+#[derive(Deserialize)]
+struct NewMessage {
+	content: String,
+	/// Send the message even if it exceeds a budget, as /override does.
+	#[serde(default, rename = "override")]
+	budget_override: bool,
+}
+
+fn conversation_json(conv: &ConversationListing) -> Value {
+	json!({
+		"id": conv.id,
+		"title": conv.title,
+		"topic": conv.topic,
+		"usage": conv.usage,
+		"updated_at": conv.lastupdate.to_rfc3339(),
+		"archived": conv.archived_at.is_some()
+	})
+}
+
+fn message_json(msg: &SavedMessage) -> Value {
+	json!({
+		"id": msg.id,
+		"role": msg.role,
+		"content": msg.content,
+		"prompt_tokens": msg.prompt_tokens,
+		"completion_tokens": msg.completion_tokens,
+		"created_at": msg.createdat.to_rfc3339(),
+		"attachments": msg.attachments.iter().map(|attachment| json!({ "name": attachment.name, "mime": attachment.mime })).collect::<Vec<Value>>()
+	})
+}
+
+/// Conversation `id` of the current profile, unless it is in the trash.
+fn find_conversation(mgr: &ChatManager, id: u32) -> Result<ConversationListing, ApiError> {
+	Database::get_conversations(&mgr.connection, Some(&mgr.api_key), ConversationFilter::All)?
+		.into_iter()
+		.find(|conv| conv.id == id && conv.deleted_at.is_none())
+		.ok_or(ApiError(StatusCode::NOT_FOUND, format!("No conversation {}.", id)))
+}
+
+async fn list_conversations(State(state): State<SharedState>) -> Result<Json<Value>, ApiError> {
+	let mgr = state.mgr.lock().await;
+	let mut conversations = Database::get_all_conversations(&mgr.connection, &mgr.api_key)?;
+	conversations.reverse();
+	Ok(Json(Value::Array(conversations.iter().map(conversation_json).collect())))
+}
+
+/// Creates a conversation with the settings given on the command line, as the terminal does.
+async fn create_conversation(State(state): State<SharedState>, Json(body): Json<NewConversation>) -> Result<(StatusCode, Json<Value>), ApiError> {
+	let mut mgr = state.mgr.lock().await;
+	let title = body.title.map(|title| title.trim().to_string()).filter(|title| !title.is_empty()).unwrap_or("New conversation".into());
+	let conversation_id = Database::add_conversation(&mgr.connection, &title, &mgr.api_key)?;
+	restore_settings(&mut mgr, conversation_id)?;
+	Ok((StatusCode::CREATED, Json(conversation_json(&find_conversation(&mgr, conversation_id)?))))
+}
+
+async fn get_conversation(State(state): State<SharedState>, Path(id): Path<u32>) -> Result<Json<Value>, ApiError> {
+	let mgr = state.mgr.lock().await;
+	let mut conversation = conversation_json(&find_conversation(&mgr, id)?);
+	let settings = Database::get_conversation_settings(&mgr.connection, id)?.unwrap_or_default();
+	let messages = Database::get_all_messages_in_conversation(&mgr.connection, id)?;
+	conversation["system_prompt"] = json!(settings.system_prompt);
+	conversation["model"] = json!(settings.model);
+	conversation["draft"] = json!(Database::get_draft(&mgr.connection, id)?);
+	conversation["messages"] = Value::Array(messages.iter().map(message_json).collect());
+	Ok(Json(conversation))
+}
+
+async fn rename_conversation(State(state): State<SharedState>, Path(id): Path<u32>, Json(body): Json<Rename>) -> Result<Json<Value>, ApiError> {
+	let mgr = state.mgr.lock().await;
+	find_conversation(&mgr, id)?;
+	let title = body.title.trim();
+	if title.is_empty() {
+		return Err(ApiError(StatusCode::BAD_REQUEST, "The title is empty.".into()));
+	}
+	Database::set_conversation_title(&mgr.connection, id, title)?;
+	Ok(Json(conversation_json(&find_conversation(&mgr, id)?)))
+}
+
+/// Moves a conversation to the trash, as `ai conversation delete` does.
+async fn delete_conversation(State(state): State<SharedState>, Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+	let mgr = state.mgr.lock().await;
+	find_conversation(&mgr, id)?;
+	Database::set_conversation_deleted(&mgr.connection, id, true)?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+fn send_event(events: &UnboundedSender<Event>, name: &str, data: Value) {
+	let _ = events.send(Event::default().event(name).data(data.to_string()));
+}
+
+/// Sends a message in a conversation and streams the reply as server-sent events: `delta` events with pieces of
+/// the reply, then `done` with the stored messages, or `error`. The exchange is stored as in the terminal, also
+/// when the client goes away before the reply is complete.
+async fn post_message(State(state): State<SharedState>, Path(id): Path<u32>, Json(body): Json<NewMessage>) -> Result<Response, ApiError> {
+	let prompt = body.content.trim().to_string();
+	if prompt.is_empty() {
+		return Err(ApiError(StatusCode::BAD_REQUEST, "The message is empty.".into()));
+	}
+	let mut mgr = state.mgr.clone().lock_owned().await;
+	find_conversation(&mgr, id)?;
+	if !state.replying.lock().unwrap().insert(id) {
+		return Err(ApiError(StatusCode::CONFLICT, "A reply is already being written in this conversation.".into()));
+	}
+
+	// Building the context keeps the connection, which cannot be shared between threads, across awaits, so it
+	// runs on a thread of its own. Clients may not read local files, so `@path` references are not expanded.
+	let (mgr, preparation) = tokio::task::spawn_blocking(move || {
+		let preparation = open_session(&mut mgr, id).and_then(|(mut session, _)| {
+			session.prompt = prompt;
+			session.budget_override = body.budget_override;
+			mgr.current_session = Some(session);
+			Handle::current().block_on(prepare_chat(&mut mgr, false))
+		});
+		(mgr, preparation)
+	}).await.map_err(|err| ApiError(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+	let mut mgr = mgr;
+	let session = mgr.current_session.take();
+	let prepared = match preparation {
+		Ok(Preparation::Ready(prepared)) => prepared,
+		Ok(Preparation::Refused(refusal)) => {
+			state.replying.lock().unwrap().remove(&id);
+			return Err(ApiError(StatusCode::PAYMENT_REQUIRED, format!("{} Send it again with \"override\": true to send it anyway.", refusal)));
+		},
+		Err(err) => {
+			state.replying.lock().unwrap().remove(&id);
+			return Err(err.into());
+		}
+	};
+	let (api_key, proxy, api_base, model, params) = (mgr.api_key.clone(), mgr.proxy.clone(), mgr.api_base.clone(), mgr.model.clone(), mgr.params.clone());
+	drop(mgr);
+
+	let (events, receiver) = unbounded_channel();
+	let state = state.clone();
+	tokio::spawn(async move {
+		let started = Instant::now();
+		let response = get_response_stream(&prepared.context, &api_key, &proxy, &api_base, &model, &params, |delta| {
+			send_event(&events, "delta", json!({ "content": delta }));
+		}).await;
+		let latency_ms = started.elapsed().as_millis() as u64;
+
+		let mut mgr = state.mgr.lock().await;
+		let recorded = restore_settings(&mut mgr, id).and_then(|_| {
+			mgr.current_session = session;
+			record_chat(&mut mgr, &prepared.context, response, latency_ms)
+		});
+		let session = mgr.current_session.take();
+		drop(mgr);
+		state.replying.lock().unwrap().remove(&id);
+
+		match recorded {
+			Ok(ChatOutcome::Reply) => {
+				let messages: Vec<Value> = session.iter().flat_map(|session| session.history.iter().rev().take(2).rev().map(message_json)).collect();
+				send_event(&events, "done", json!({
+					"messages": messages,
+					"model": model,
+					"notes": prepared.notes,
+					"budget_warnings": prepared.budget_warnings
+				}));
+			},
+			Ok(ChatOutcome::Error(error)) => send_event(&events, "error", json!({ "error": error })),
+			Err(err) => send_event(&events, "error", json!({ "error": err.to_string() })),
+		}
+	});
+
+	let stream = UnboundedReceiverStream::new(receiver).map(Ok::<Event, Infallible>);
+	Ok(Sse::new(stream).keep_alive(KeepAlive::default()).into_response())
+}
+
+fn is_loopback_host(host: &str) -> bool {
+	let host = match host.rsplit_once(':') {
+		Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) && !name.ends_with(':') => name,
+		_ => host,
+	};
+	let host = host.trim_start_matches('[').trim_end_matches(']');
+	host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Compares in constant time, so that the time taken does not tell how much of a guessed token is right.
+fn tokens_match(given: &str, token: &str) -> bool {
+	given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+	headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Bearer ")
+}
+
+/// Checks the token, or without one that the request is addressed to a loopback name, which keeps web pages
+/// from reaching the server through DNS rebinding. Requests from web pages are only accepted from the allowed
+/// origins, which get the CORS headers they need.
+pub async fn authorize(State(access): State<Arc<Access>>, request: Request, next: Next) -> Response {
+	let origin = request.headers().get(header::ORIGIN).cloned();
+	if let Some(origin) = &origin {
+		if !access.allowed_origins.iter().any(|allowed| allowed.as_bytes() == origin.as_bytes()) {
+			return ApiError(StatusCode::FORBIDDEN, "Requests from this origin are not allowed. Start the server with --allow-origin to allow them.".into()).into_response();
+		}
+		if request.method() == Method::OPTIONS {
+			return (StatusCode::NO_CONTENT, [
+				(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone()),
+				(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, POST, PATCH, DELETE")),
+				(header::ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("authorization, content-type")),
+				(header::VARY, HeaderValue::from_static("Origin")),
+			]).into_response();
+		}
+	}
+
+	match &access.token {
+		Some(token) if !bearer_token(request.headers()).is_some_and(|given| tokens_match(given, token)) => {
+			let mut response = ApiError(StatusCode::UNAUTHORIZED, "Missing or wrong token.".into()).into_response();
+			response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+			return response;
+		},
+		None if !request.headers().get(header::HOST).and_then(|host| host.to_str().ok()).is_some_and(is_loopback_host) => {
+			return ApiError(StatusCode::FORBIDDEN, "Requests must be addressed to localhost. Start the server with a token to use other names.".into()).into_response();
+		},
+		_ => {},
+	}
+
+	let mut response = next.run(request).await;
+	if let Some(origin) = origin {
+		response.headers_mut().insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+		response.headers_mut().insert(header::VARY, HeaderValue::from_static("Origin"));
+	}
+	response
+}
+
+/// Serves `router` on `address` until interrupted.
+pub async fn listen_until_interrupted(router: Router, address: SocketAddr) -> Result<(), MainError> {
+	let listener = tokio::net::TcpListener::bind(address).await?;
+	println!("Listening on http://{}. Press Ctrl+C to stop.", listener.local_addr()?);
+	axum::serve(listener, router)
+		.with_graceful_shutdown(async {
+			let _ = tokio::signal::ctrl_c().await;
+		})
+		.await?;
+	Ok(())
+}
+
+/// Serves the conversations of the current profile over HTTP until interrupted.
+pub async fn run_server(mgr: ChatManager, args: ServeArgs) -> Result<(), MainError> {
+	let access = Arc::new(args.access.into_access(&args.listen)?);
+	let state = Arc::new(ServerState { mgr: Arc::new(Mutex::new(mgr)), replying: Default::default() });
+	let router = Router::new()
+		.route("/api/conversations", get(list_conversations).post(create_conversation))
+		.route("/api/conversations/:id", get(get_conversation).patch(rename_conversation).delete(delete_conversation))
+		.route("/api/conversations/:id/messages", post(post_message))
+		.layer(middleware::from_fn_with_state(access, authorize))
+		.with_state(state);
+	listen_until_interrupted(router, args.listen).await
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::body::to_bytes;
+
+	use super::*;
+	use crate::test_support::*;
+
+	async fn record_request(State(requests): State<Arc<std::sync::Mutex<Vec<Value>>>>, Json(body): Json<Value>) -> StatusCode {
+		requests.lock().unwrap().push(body);
+		StatusCode::INTERNAL_SERVER_ERROR
+	}
+
+	#[tokio::test]
+	async fn leaves_file_references_of_posted_messages() {
+		let requests = Arc::new(std::sync::Mutex::new(vec![]));
+		let router = Router::new().route("/v1/chat/completions", post(record_request)).with_state(requests.clone());
+		let mgr = test_manager(&serve_stub(router).await);
+		let id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		let state = Arc::new(ServerState { mgr: Arc::new(Mutex::new(mgr)), replying: Default::default() });
+
+		let body = NewMessage { content: "What is in @/etc/passwd?".into(), budget_override: false };
+		let response = post_message(State(state), Path(id), Json(body)).await.map_err(|err| err.1).unwrap();
+		let events = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+		assert!(String::from_utf8_lossy(&events).contains("event: error"));
+
+		let requests = requests.lock().unwrap();
+		let messages = requests[0]["messages"].as_array().unwrap();
+		assert_eq!(messages.last().unwrap()["content"], "What is in @/etc/passwd?");
+	}
+}
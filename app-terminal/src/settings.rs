@@ -0,0 +1,160 @@
+use clap::ValueEnum;
+
+use openai::types::{CompletionParams, ConversationSettings};
+use database::*;
+
+use crate::context::ContextStrategy;
+use crate::error::*;
+use crate::persona::describe_params;
+use crate::types::*;
+
+pub static DEFAULT_MODEL: &str = "gpt-4";
+pub static DEFAULT_MAX_TOKEN: u64 = 3800;
+pub static DEFAULT_MAX_DIALOG: u64 = 32;
+
+fn overlay_params(base: CompletionParams, top: &CompletionParams) -> CompletionParams {
+	CompletionParams {
+		temperature: top.temperature.or(base.temperature),
+		top_p: top.top_p.or(base.top_p),
+		max_tokens: top.max_tokens.or(base.max_tokens),
+		presence_penalty: top.presence_penalty.or(base.presence_penalty),
+		frequency_penalty: top.frequency_penalty.or(base.frequency_penalty)
+	}
+}
+
+/// Returns `base` with every value that is set in `top` replaced.
+pub fn overlay(base: ConversationSettings, top: &ConversationSettings) -> ConversationSettings {
+	ConversationSettings {
+		model: top.model.clone().or(base.model),
+		params: overlay_params(base.params, &top.params),
+		context_strategy: top.context_strategy.clone().or(base.context_strategy),
+		max_token: top.max_token.or(base.max_token),
+		max_dialog: top.max_dialog.or(base.max_dialog),
+		system_prompt: top.system_prompt.clone().or(base.system_prompt),
+		persona: top.persona.clone().or(base.persona)
+	}
+}
+
+pub fn strategy_name(strategy: ContextStrategy) -> String {
+	strategy.to_possible_value().unwrap().get_name().to_string()
+}
+
+/// Makes `settings` the ones used for requests. Values that are not set fall back to the defaults, not to those
+/// of the previous conversation.
+pub fn apply_settings(mgr: &mut ChatManager, settings: &ConversationSettings) {
+	mgr.model = settings.model.clone().unwrap_or(DEFAULT_MODEL.into());
+	mgr.params = settings.params.clone();
+	mgr.context_strategy = settings.context_strategy
+		.as_deref()
+		.and_then(|name| ContextStrategy::from_str(name, true).ok())
+		.unwrap_or(ContextStrategy::Truncate);
+	mgr.max_token = settings.max_token.unwrap_or(DEFAULT_MAX_TOKEN);
+	mgr.max_dialog = settings.max_dialog.unwrap_or(DEFAULT_MAX_DIALOG);
+}
+
+fn describe_settings(mgr: &ChatManager, persona: Option<&str>) -> String {
+	let mut description = format!("model={}, context-strategy={}, max-token={}, max-dialog={}, parameters: {}",
+		mgr.model, strategy_name(mgr.context_strategy), mgr.max_token, mgr.max_dialog, describe_params(&mgr.params));
+	if let Some(persona) = persona {
+		description.push_str(&format!(", persona={}", persona));
+	}
+	description
+}
+
+/// The system prompt of a resumed conversation and notes about its settings for the user.
+pub struct RestoredSettings {
+	pub system_prompt: Option<String>,
+	pub notes: Vec<String>
+}
+
+/// Applies the stored settings of a conversation, with the settings given on the command line taking precedence,
+/// and returns its system prompt. Settings are saved for conversations that have none yet, such as new ones,
+/// and with `--update-settings`.
+pub fn restore_settings(mgr: &mut ChatManager, conversation_id: u32) -> Result<RestoredSettings, MainError> {
+	let mut notes = vec![];
+	let stored = Database::get_conversation_settings(&mgr.connection, conversation_id)?;
+	let settings = overlay(stored.clone().unwrap_or_default(), &mgr.overrides);
+	apply_settings(mgr, &settings);
+
+	if stored.is_none() || mgr.update_settings {
+		let captured = ConversationSettings {
+			model: Some(mgr.model.clone()),
+			params: mgr.params.clone(),
+			context_strategy: Some(strategy_name(mgr.context_strategy)),
+			max_token: Some(mgr.max_token),
+			max_dialog: Some(mgr.max_dialog),
+			system_prompt: settings.system_prompt.clone(),
+			persona: settings.persona.clone()
+		};
+		Database::set_conversation_settings(&mgr.connection, conversation_id, &captured)?;
+	}
+	else if settings != stored.clone().unwrap_or_default() {
+		notes.push("Settings given on the command line apply to this session only. Use --update-settings to keep them.".into());
+	}
+
+	if stored.is_some() {
+		notes.push(format!("Settings: {}", describe_settings(mgr, settings.persona.as_deref())));
+	}
+	Ok(RestoredSettings { system_prompt: settings.system_prompt, notes })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_support::*;
+
+	fn settings(model: Option<&str>, max_dialog: Option<u64>) -> ConversationSettings {
+		ConversationSettings { model: model.map(String::from), max_dialog, ..Default::default() }
+	}
+
+	#[test]
+	fn overlays_the_values_that_are_set() {
+		let mut base = settings(Some("gpt-3.5-turbo"), Some(8));
+		base.params.temperature = Some(0.2);
+		base.params.top_p = Some(0.9);
+		let mut top = settings(None, Some(4));
+		top.params.temperature = Some(1.0);
+		top.persona = Some("reviewer".into());
+
+		let overlaid = overlay(base, &top);
+		assert_eq!((overlaid.model.as_deref(), overlaid.max_dialog, overlaid.persona.as_deref()), (Some("gpt-3.5-turbo"), Some(4), Some("reviewer")));
+		assert_eq!((overlaid.params.temperature, overlaid.params.top_p), (Some(1.0), Some(0.9)));
+	}
+
+	#[test]
+	fn resets_settings_a_conversation_does_not_store() {
+		let mut mgr = test_manager("http://localhost/v1");
+		let first = Database::add_conversation(&mgr.connection, "first", &mgr.api_key).unwrap();
+		let second = Database::add_conversation(&mgr.connection, "second", &mgr.api_key).unwrap();
+		let third = Database::add_conversation(&mgr.connection, "third", &mgr.api_key).unwrap();
+		Database::set_conversation_settings(&mgr.connection, first, &settings(Some("gpt-3.5-turbo"), Some(8))).unwrap();
+		Database::set_conversation_settings(&mgr.connection, second, &settings(None, None)).unwrap();
+
+		restore_settings(&mut mgr, first).unwrap();
+		assert_eq!((mgr.model.as_str(), mgr.max_dialog), ("gpt-3.5-turbo", 8));
+		restore_settings(&mut mgr, second).unwrap();
+		assert_eq!((mgr.model.as_str(), mgr.max_dialog), (DEFAULT_MODEL, DEFAULT_MAX_DIALOG));
+
+		restore_settings(&mut mgr, first).unwrap();
+		restore_settings(&mut mgr, third).unwrap();
+		let stored = Database::get_conversation_settings(&mgr.connection, third).unwrap().unwrap();
+		assert_eq!((stored.model.as_deref(), stored.max_dialog), (Some(DEFAULT_MODEL), Some(DEFAULT_MAX_DIALOG)));
+	}
+
+	#[test]
+	fn keeps_command_line_settings_for_the_session_only() {
+		let mut mgr = test_manager("http://localhost/v1");
+		let id = Database::add_conversation(&mgr.connection, "title", &mgr.api_key).unwrap();
+		Database::set_conversation_settings(&mgr.connection, id, &settings(Some("gpt-3.5-turbo"), Some(8))).unwrap();
+		mgr.overrides = settings(Some("gpt-4o"), None);
+
+		let restored = restore_settings(&mut mgr, id).unwrap();
+		assert_eq!((mgr.model.as_str(), mgr.max_dialog), ("gpt-4o", 8));
+		assert!(restored.notes[0].contains("--update-settings"));
+		assert_eq!(Database::get_conversation_settings(&mgr.connection, id).unwrap().unwrap().model.as_deref(), Some("gpt-3.5-turbo"));
+
+		mgr.update_settings = true;
+		restore_settings(&mut mgr, id).unwrap();
+		assert_eq!(Database::get_conversation_settings(&mgr.connection, id).unwrap().unwrap().model.as_deref(), Some("gpt-4o"));
+	}
+}
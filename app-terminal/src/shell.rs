@@ -0,0 +1,219 @@
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use openai::types::*;
+
+use crate::error::*;
+use crate::include::fence_for;
+use crate::input::LineReader;
+use crate::types::*;
+
+/// Sent with the output of `!!command`.
+static SHELL_INSTRUCTION: &str = "Explain this output. If the command failed, explain why and how to fix it.";
+
+/// Lines longer than this are cut in the message.
+static MAX_LINE_CHARS: usize = 1000;
+
+/// How long output is still collected after the shell exits. Processes it left running in the background
+/// may keep its output open, so the end of the output is not waited for.
+static DRAIN_MILLIS: u64 = 200;
+
+/// The output of a command, waiting to be sent with the next message.
+pub struct PendingCommand {
+	pub run: CommandRun,
+	pub output: String
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+	let mut shell = Command::new("cmd");
+	shell.arg("/C").arg(command);
+	shell
+}
+
+/// The shell is started in a process group of its own, so that the processes it starts can be killed with it.
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+	let mut shell = Command::new("sh");
+	shell.arg("-c").arg(command).process_group(0);
+	shell
+}
+
+#[cfg(windows)]
+async fn kill(child: &mut Child) -> std::io::Result<()> {
+	child.kill().await
+}
+
+#[cfg(not(windows))]
+async fn kill(child: &mut Child) -> std::io::Result<()> {
+	if let Some(id) = child.id() {
+		// The group has the ID of the shell, which leads it
+		unsafe { libc::killpg(id as libc::pid_t, libc::SIGKILL) };
+	}
+	child.kill().await
+}
+
+fn forward_lines(stream: impl AsyncRead + Unpin + Send + 'static, sender: UnboundedSender<String>) {
+	tokio::spawn(async move {
+		let mut reader = BufReader::new(stream);
+		let mut line = vec![];
+		while reader.read_until(b'\n', &mut line).await.is_ok_and(|length| length > 0) {
+			if sender.send(String::from_utf8_lossy(&line).to_string()).is_err() {
+				break
+			}
+			line.clear();
+		}
+	});
+}
+
+/// Keeps the first quarter and the last three quarters of `max_lines` lines, where errors usually are.
+/// Returns the kept output and the number of lines left out.
+fn truncate_output(lines: &[String], max_lines: usize) -> (String, usize) {
+	let cut = |line: &String| {
+		let line = line.trim_end_matches(['\r', '\n']);
+		match line.char_indices().nth(MAX_LINE_CHARS) {
+			Some((index, _)) => format!("{} [...]", &line[..index]),
+			None => line.to_string(),
+		}
+	};
+	if lines.len() <= max_lines {
+		return (lines.iter().map(cut).collect::<Vec<String>>().join("\n"), 0);
+	}
+	let head = max_lines / 4;
+	let omitted = lines.len() - max_lines;
+	let mut kept: Vec<String> = lines[..head].iter().map(cut).collect();
+	kept.push(format!("[... {} lines omitted ...]", omitted));
+	kept.extend(lines[head + omitted..].iter().map(cut));
+	(kept.join("\n"), omitted)
+}
+
+pub fn describe_run(run: &CommandRun) -> String {
+	match run.exit_code {
+		_ if run.timed_out => format!("timed out after {} s", run.duration_ms / 1000),
+		Some(code) => format!("exit status {}", code),
+		None => "terminated by a signal".into(),
+	}
+}
+
+/// The output of a command as it is sent in a message.
+pub fn command_block(pending: &PendingCommand) -> String {
+	if pending.output.trim().is_empty() {
+		return format!("The command `{}` printed nothing ({}).", pending.run.command, describe_run(&pending.run));
+	}
+	let fence = fence_for(&pending.output);
+	format!("Output of `{}` ({}):\n{}text\n{}\n{}", pending.run.command, describe_run(&pending.run), fence, pending.output, fence)
+}
+
+/// Runs `command` in the shell, printing its output as it comes, and kills it with the processes it started after
+/// `timeout` seconds.
+async fn run_command(command: &str, timeout: u64, max_lines: usize) -> Result<PendingCommand, MainError> {
+	let started = Instant::now();
+	let mut child = shell(command)
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()?;
+	let (sender, mut receiver) = unbounded_channel();
+	forward_lines(child.stdout.take().unwrap(), sender.clone());
+	forward_lines(child.stderr.take().unwrap(), sender);
+
+	let mut lines = vec![];
+	let mut add_line = |line: String| {
+		print!("{}", line);
+		lines.push(line);
+	};
+	let deadline = tokio::time::sleep(Duration::from_secs(timeout));
+	tokio::pin!(deadline);
+	let status = loop {
+		tokio::select! {
+			Some(line) = receiver.recv() => add_line(line),
+			status = child.wait() => break Some(status?),
+			_ = &mut deadline => break None,
+		}
+	};
+	let (exit_code, timed_out) = match status {
+		Some(status) => (status.code(), false),
+		None => {
+			kill(&mut child).await?;
+			(None, true)
+		}
+	};
+	let drained = tokio::time::Instant::now() + Duration::from_millis(DRAIN_MILLIS);
+	while let Ok(Some(line)) = tokio::time::timeout_at(drained, receiver.recv()).await {
+		add_line(line);
+	}
+	if lines.last().is_some_and(|line| !line.ends_with('\n')) {
+		println!();
+	}
+
+	let (output, omitted_lines) = truncate_output(&lines, max_lines);
+	let run = CommandRun { command: command.into(), exit_code, timed_out, duration_ms: started.elapsed().as_millis() as u64, omitted_lines };
+	Ok(PendingCommand { run, output })
+}
+
+/// Runs a command typed as `!command`, after confirmation unless it is turned off, and keeps its output for the
+/// next message. With `send_now`, for `!!command`, returns a prompt asking about the output right away.
+pub async fn execute_shell_command(mgr: &mut ChatManager, reader: &mut LineReader, command: &str, send_now: bool) -> Result<Option<String>, MainError> {
+	if command.is_empty() {
+		println!("Usage: !command to run a command and send its output with your next message, or !!command to send it right away.");
+		return Ok(None);
+	}
+	if mgr.shell_confirm && !reader.confirm(&format!("Run `{}`? [y/N] ", command))? {
+		println!("The command was not run.");
+		return Ok(None);
+	}
+
+	let pending = run_command(command, mgr.shell_timeout, mgr.shell_max_lines).await?;
+	match pending.run.timed_out {
+		true => println!("The command was stopped after {} s.", mgr.shell_timeout),
+		false => println!("The command finished with {} in {:.1} s.", describe_run(&pending.run), pending.run.duration_ms as f64 / 1000.0),
+	}
+	if pending.run.omitted_lines > 0 {
+		println!("{} lines in the middle of the output will be left out of the message.", pending.run.omitted_lines);
+	}
+	let session = mgr.current_session.as_mut().unwrap();
+	session.pending_commands.push(pending);
+	if send_now {
+		return Ok(Some(SHELL_INSTRUCTION.into()));
+	}
+	println!("The output will be sent with your next message. Type /detach to discard it.");
+	Ok(None)
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn does_not_wait_for_background_processes() {
+		let pending = run_command("sleep 30 & echo started", 10, 100).await.unwrap();
+		assert!(!pending.run.timed_out);
+		assert_eq!(pending.run.exit_code, Some(0));
+		assert_eq!(pending.output, "started");
+		assert!(pending.run.duration_ms < 5000);
+	}
+
+	#[tokio::test]
+	async fn kills_started_processes_on_timeout() {
+		let marker = std::env::temp_dir().join(format!("ai-shell-test-{}", std::process::id()));
+		let command = format!("(sleep 2; touch {}) & sleep 30", marker.display());
+		let pending = run_command(&command, 1, 100).await.unwrap();
+		assert!(pending.run.timed_out);
+
+		tokio::time::sleep(Duration::from_secs(3)).await;
+		assert!(!marker.exists());
+	}
+
+	#[test]
+	fn keeps_the_start_and_end_of_long_output() {
+		let lines: Vec<String> = (1..=10).map(|index| format!("{}\n", index)).collect();
+		let (output, omitted) = truncate_output(&lines, 4);
+		assert_eq!(omitted, 6);
+		assert_eq!(output, "1\n[... 6 lines omitted ...]\n8\n9\n10");
+	}
+}
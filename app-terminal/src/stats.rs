@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, Utc};
+use clap::{Args, ValueEnum};
+use rusqlite::Connection;
+use serde::Serialize;
+
+use openai::types::*;
+use database::*;
+
+use crate::error::*;
+use crate::pricing::cost_of;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Period {
+	Day,
+	Week,
+	Month,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Grouping {
+	None,
+	Model,
+	Conversation,
+	Profile,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+	Table,
+	Csv,
+	Json,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+	/// Length of each reporting period
+	#[arg(long, value_enum, default_value = "day")]
+	period: Period,
+
+	/// Break each period down further
+	#[arg(long, value_enum, default_value = "none")]
+	by: Grouping,
+
+	/// Output format
+	#[arg(long, value_enum, default_value = "table")]
+	format: OutputFormat,
+}
+
+#[derive(Default, Serialize)]
+struct StatsRow {
+	period: String,
+	group: String,
+	requests: u64,
+	prompt_tokens: u64,
+	completion_tokens: u64,
+	total_tokens: u64,
+	cost_usd: Option<f64>,
+	avg_latency_ms: Option<f64>,
+	errors: u64,
+	error_rate: f64,
+	#[serde(skip)]
+	latency_sum: u64,
+	#[serde(skip)]
+	latency_count: u64,
+}
+
+/// Shortens an API key so that it can identify a profile without revealing the key.
+pub fn mask_key(key: &str) -> String {
+	let chars: Vec<char> = key.trim().chars().collect();
+	if chars.len() <= 8 {
+		return "*".repeat(chars.len());
+	}
+	format!("{}...{}", chars[..3].iter().collect::<String>(), chars[chars.len() - 4..].iter().collect::<String>())
+}
+
+/// The local day, week or month of `time`, as budgets count them.
+fn period_of(period: Period, time: &DateTime<Utc>) -> String {
+	let time = time.with_timezone(&Local);
+	match period {
+		Period::Day => time.format("%Y-%m-%d").to_string(),
+		Period::Week => time.format("%G-W%V").to_string(),
+		Period::Month => time.format("%Y-%m").to_string(),
+	}
+}
+
+fn group_of_usage(by: Grouping, record: &UsageRecord) -> Option<String> {
+	match by {
+		Grouping::None => Some("all".into()),
+		Grouping::Model => Some(record.model.clone().unwrap_or("unknown".into())),
+		Grouping::Conversation => record.conversation_id
+			.map(|id| format!("{}: {}", id, record.title.clone().unwrap_or_default())),
+		Grouping::Profile => Some(mask_key(&record.key)),
+	}
+}
+
+fn group_of_error(by: Grouping, record: &ErrorRecord) -> Option<String> {
+	match by {
+		Grouping::None => Some("all".into()),
+		Grouping::Model => Some(record.model.clone().unwrap_or("unknown".into())),
+		Grouping::Conversation => record.conversation_id
+			.map(|id| format!("{}: {}", id, record.title.clone().unwrap_or_default())),
+		Grouping::Profile => Some(mask_key(&record.key)),
+	}
+}
+
+fn collect_rows(conn: &Connection, args: &StatsArgs) -> Result<Vec<StatsRow>, MainError> {
+	let mut rows: BTreeMap<(String, String), StatsRow> = BTreeMap::new();
+
+	for record in Database::get_usage_records(conn)? {
+		let Some(group) = group_of_usage(args.by, &record) else {
+			continue
+		};
+		let key = (period_of(args.period, &record.updateat), group);
+		let row = rows.entry(key.clone()).or_insert_with(|| StatsRow { period: key.0, group: key.1, ..Default::default() });
+		row.requests += 1;
+		row.prompt_tokens += record.prompt_tokens;
+		row.completion_tokens += record.completion_tokens;
+		row.total_tokens += record.prompt_tokens + record.completion_tokens;
+		if let Some(cost) = cost_of(record.model.as_deref(), record.prompt_tokens, record.completion_tokens) {
+			row.cost_usd = Some(row.cost_usd.unwrap_or(0.0) + cost);
+		}
+		if let Some(latency) = record.latency_ms {
+			row.latency_sum += latency;
+			row.latency_count += 1;
+		}
+	}
+
+	for record in Database::get_error_records(conn)? {
+		let Some(group) = group_of_error(args.by, &record) else {
+			continue
+		};
+		let key = (period_of(args.period, &record.updateat), group);
+		let row = rows.entry(key.clone()).or_insert_with(|| StatsRow { period: key.0, group: key.1, ..Default::default() });
+		row.errors += 1;
+	}
+
+	let mut rows: Vec<StatsRow> = rows.into_values().collect();
+	for row in rows.iter_mut() {
+		if row.latency_count > 0 {
+			row.avg_latency_ms = Some(row.latency_sum as f64 / row.latency_count as f64);
+		}
+		row.error_rate = row.errors as f64 / (row.requests + row.errors) as f64;
+	}
+	Ok(rows)
+}
+
+fn cells_of(row: &StatsRow) -> Vec<String> {
+	let optional = |value: Option<String>| value.unwrap_or("-".into());
+	vec![
+		row.period.clone(),
+		row.group.clone(),
+		row.requests.to_string(),
+		row.prompt_tokens.to_string(),
+		row.completion_tokens.to_string(),
+		row.total_tokens.to_string(),
+		optional(row.cost_usd.map(|cost| format!("{:.4}", cost))),
+		optional(row.avg_latency_ms.map(|latency| format!("{:.0}", latency))),
+		row.errors.to_string(),
+		format!("{:.1}%", row.error_rate * 100.0),
+	]
+}
+
+static HEADERS: [&str; 10] = [
+	"Period", "Group", "Requests", "Prompt", "Completion", "Total", "Cost (USD)", "Latency (ms)", "Errors", "Error Rate"
+];
+
+fn print_table(rows: &[StatsRow]) {
+	let cells: Vec<Vec<String>> = rows.iter().map(cells_of).collect();
+	let mut widths: Vec<usize> = HEADERS.iter().map(|header| header.chars().count()).collect();
+	for line in cells.iter() {
+		for (i, cell) in line.iter().enumerate() {
+			widths[i] = widths[i].max(cell.chars().count());
+		}
+	}
+
+	let format_line = |line: Vec<String>| {
+		line.iter()
+			.enumerate()
+			.map(|(i, cell)| if i < 2 {
+				format!("{:<width$}", cell, width = widths[i])
+			}
+			else {
+				format!("{:>width$}", cell, width = widths[i])
+			})
+			.collect::<Vec<String>>()
+			.join("  ")
+	};
+
+	println!("{}", format_line(HEADERS.iter().map(|header| header.to_string()).collect()));
+	println!("{}", widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<String>>().join("  "));
+	for line in cells {
+		println!("{}", format_line(line));
+	}
+}
+
+fn escape_csv(field: &str) -> String {
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	}
+	else {
+		field.into()
+	}
+}
+
+fn print_csv(rows: &[StatsRow]) {
+	println!("period,group,requests,prompt_tokens,completion_tokens,total_tokens,cost_usd,avg_latency_ms,errors,error_rate");
+	for row in rows {
+		let optional = |value: Option<String>| value.unwrap_or_default();
+		let line = [
+			escape_csv(&row.period),
+			escape_csv(&row.group),
+			row.requests.to_string(),
+			row.prompt_tokens.to_string(),
+			row.completion_tokens.to_string(),
+			row.total_tokens.to_string(),
+			optional(row.cost_usd.map(|cost| format!("{:.6}", cost))),
+			optional(row.avg_latency_ms.map(|latency| format!("{:.1}", latency))),
+			row.errors.to_string(),
+			format!("{:.4}", row.error_rate),
+		];
+		println!("{}", line.join(","));
+	}
+}
+
+pub fn run_stats_command(conn: &Connection, args: StatsArgs) -> Result<(), MainError> {
+	let rows = collect_rows(conn, &args)?;
+	match args.format {
+		OutputFormat::Table => {
+			if rows.is_empty() {
+				println!("No usage recorded yet.");
+			}
+			else {
+				print_table(&rows);
+			}
+		},
+		OutputFormat::Csv => print_csv(&rows),
+		OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&rows).unwrap()),
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::TimeZone;
+
+	use super::*;
+	use crate::test_support::*;
+
+	#[test]
+	fn masks_keys_by_character() {
+		assert_eq!(mask_key(" sk-abcdefghijkl "), "sk-...ijkl");
+		assert_eq!(mask_key("clé-ünïcødé-schlüssel"), "clé...ssel");
+		assert_eq!(mask_key("short"), "*****");
+	}
+
+	#[test]
+	fn counts_periods_in_local_time() {
+		let late = Local.with_ymd_and_hms(2024, 3, 31, 23, 30, 0).unwrap().with_timezone(&Utc);
+		assert_eq!(period_of(Period::Day, &late), "2024-03-31");
+		assert_eq!(period_of(Period::Month, &late), "2024-03");
+	}
+
+	#[test]
+	fn adds_up_requests_and_errors() {
+		let mgr = test_manager("http://127.0.0.1:9/v1");
+		let id = Database::add_conversation(&mgr.connection, "title", "sk-abcdefghijkl").unwrap();
+		Database::add_client_message(&mgr.connection, id, "hello").unwrap();
+		Database::add_server_message(&mgr.connection, id, &reply("hi", 1000, 500), 200).unwrap();
+		Database::add_client_message(&mgr.connection, id, "again").unwrap();
+		Database::add_server_message(&mgr.connection, id, &reply("hi again", 2000, 500), 400).unwrap();
+		Database::add_error_log(&mgr.connection, "sk-abcdefghijkl", "gpt-4", Some(id), &[], "failed", None).unwrap();
+
+		let args = StatsArgs { period: Period::Month, by: Grouping::Profile, format: OutputFormat::Json };
+		let rows = collect_rows(&mgr.connection, &args).unwrap();
+		assert_eq!(rows.len(), 1);
+		let row = &rows[0];
+		assert_eq!((row.group.as_str(), row.requests, row.errors), ("sk-...ijkl", 2, 1));
+		assert_eq!((row.prompt_tokens, row.completion_tokens, row.total_tokens), (3000, 1000, 4000));
+		assert_eq!(row.avg_latency_ms, Some(300.0));
+		assert!((row.error_rate - 1.0 / 3.0).abs() < 1e-9);
+		assert!((row.cost_usd.unwrap() - (3.0 * 0.03 + 1.0 * 0.06)).abs() < 1e-9);
+	}
+}
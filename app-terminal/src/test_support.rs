@@ -0,0 +1,71 @@
+//! Helpers shared by the unit tests of the terminal client.
+
+use std::path::PathBuf;
+
+use axum::Router;
+use serde_json::{json, Value};
+
+use openai::types::{CompletionParams, CompletionResponse, ConversationSettings};
+use database::*;
+
+use crate::context::ContextStrategy;
+use crate::types::ChatManager;
+
+/// A manager with an in-memory database that sends requests to `api_base`.
+pub fn test_manager(api_base: &str) -> ChatManager {
+	let conn = open_connection(&PathBuf::from(":memory:"));
+	CurrentSchema::init_current_schema(&conn).unwrap();
+	ChatManager {
+		max_token: 3800,
+		max_dialog: 32,
+		api_key: "sk-test".into(),
+		connection: conn,
+		proxy: None,
+		api_base: api_base.into(),
+		model: "gpt-4".into(),
+		params: CompletionParams::default(),
+		context_strategy: ContextStrategy::Truncate,
+		embedding_model: "text-embedding-3-small".into(),
+		recall_k: 0,
+		persona_dir: std::env::temp_dir(),
+		overrides: ConversationSettings::default(),
+		update_settings: false,
+		budget_warn_percent: 80,
+		ignore_budget: false,
+		include_max_size: 256 * 1024,
+		include_max_tokens: None,
+		shell_confirm: false,
+		shell_timeout: 30,
+		shell_max_lines: 200,
+		raw: true,
+		current_session: None
+	}
+}
+
+/// A completion with `content` and the given usage, as the API returns it.
+pub fn completion_json(content: &str, prompt_tokens: u64, completion_tokens: u64) -> Value {
+	json!({
+		"id": "chatcmpl-test",
+		"object": "chat.completion",
+		"created": 0,
+		"model": "gpt-4",
+		"usage": { "prompt_tokens": prompt_tokens, "completion_tokens": completion_tokens, "total_tokens": prompt_tokens + completion_tokens },
+		"choices": [{ "index": 0, "finish_reason": "stop", "message": { "role": "assistant", "content": content } }]
+	})
+}
+
+/// The completion of `completion_json`, as it is stored.
+pub fn reply(content: &str, prompt_tokens: u64, completion_tokens: u64) -> CompletionResponse {
+	serde_json::from_value(completion_json(content, prompt_tokens, completion_tokens)).unwrap()
+}
+
+/// Serves `router` on a free local port until the test ends. Returns its address with `/v1` appended,
+/// to be used as the API base.
+pub async fn serve_stub(router: Router) -> String {
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(async move {
+		axum::serve(listener, router).await.unwrap();
+	});
+	format!("http://{}/v1", address)
+}
@@ -0,0 +1,827 @@
+use std::time::{Duration, Instant};
+
+use ansi_to_tui::IntoText;
+use crossterm::event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use unicode_width::UnicodeWidthStr;
+
+use openai::prelude::*;
+use database::*;
+
+use crate::chat::*;
+use crate::context::{role_of, speaker_of};
+use crate::error::*;
+use crate::markdown::MarkdownRenderer;
+use crate::pricing::cost_of;
+use crate::types::*;
+
+static SIDEBAR_WIDTH: u16 = 34;
+static MAX_INPUT_HEIGHT: u16 = 8;
+static KEY_HINTS: &str = "Tab: switch pane  Enter: send/open  Alt+Enter: new line  /: search  t: topic  Ctrl+N: new  PgUp/PgDn: scroll  Ctrl+C: quit";
+
+#[derive(PartialEq)]
+enum Focus {
+	Sidebar,
+	Input,
+}
+
+/// What the input box is used for.
+#[derive(PartialEq)]
+enum InputMode {
+	Message,
+	Search,
+	/// Naming the topic of a conversation.
+	Topic(u32),
+}
+
+enum StreamEvent {
+	Delta(String),
+	Done(Result<OpenAIResponse, String>, u64),
+}
+
+/// A request whose reply is being streamed.
+struct InFlight {
+	prompt: String,
+	context: Vec<Message>,
+	notes: Vec<String>,
+	budget_warnings: Vec<String>,
+	reply: String,
+	/// Reply rendered for a width, with the length of the reply it holds.
+	rendered: Option<(u16, usize, Text<'static>)>,
+	events: UnboundedReceiver<StreamEvent>
+}
+
+enum SidebarRow {
+	Topic(String),
+	Conversation(usize),
+}
+
+struct TuiState {
+	conversations: Vec<ConversationListing>,
+	selected: usize,
+	search: String,
+	focus: Focus,
+	mode: InputMode,
+	input: String,
+	/// Lines scrolled back from the end of the transcript.
+	scroll_back: u16,
+	in_flight: Option<InFlight>,
+	/// Transcript of the open conversation rendered for a width, with the number of messages it holds.
+	transcript: Option<(u16, usize, Text<'static>)>,
+	context_tokens: Option<u64>,
+	cost: f64,
+	notice: String,
+	quit: bool
+}
+
+/// Conversations of the current profile, most recently updated first.
+fn load_conversations(mgr: &ChatManager) -> Result<Vec<ConversationListing>, MainError> {
+	let mut conversations = Database::get_all_conversations(&mgr.connection, &mgr.api_key)?;
+	conversations.reverse();
+	Ok(conversations)
+}
+
+fn conversation_cost(mgr: &ChatManager, conversation_id: u32) -> Result<f64, MainError> {
+	Ok(Database::get_usage_records_since(&mgr.connection, None)?
+		.iter()
+		.filter(|record| record.conversation_id == Some(conversation_id))
+		.filter_map(|record| cost_of(record.model.as_deref(), record.prompt_tokens, record.completion_tokens))
+		.fold(0.0, |total, cost| total + cost))
+}
+
+/// Title of a new conversation, taken from its first message.
+pub fn title_of(prompt: &str) -> String {
+	let line = prompt.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default();
+	if line.chars().count() > 50 {
+		format!("{}...", line.chars().take(47).collect::<String>())
+	}
+	else {
+		line.into()
+	}
+}
+
+fn render_markdown(text: &str, width: u16) -> Text<'static> {
+	let rendered = MarkdownRenderer::render(true, width as usize, text.trim());
+	rendered.into_text().unwrap_or_else(|_| Text::raw(rendered))
+}
+
+fn header_line(speaker: &str, id: Option<u32>) -> Line<'static> {
+	let label = match id {
+		Some(id) => format!("{} (#{})", speaker, id),
+		None => speaker.to_string(),
+	};
+	let color = if speaker == "You" { Color::Green } else { Color::Cyan };
+	Line::from(label).style(Style::default().fg(color).add_modifier(Modifier::BOLD))
+}
+
+impl TuiState {
+	fn new(conversations: Vec<ConversationListing>) -> Self {
+		Self {
+			conversations,
+			selected: 0,
+			search: String::new(),
+			focus: Focus::Sidebar,
+			mode: InputMode::Message,
+			input: String::new(),
+			scroll_back: 0,
+			in_flight: None,
+			transcript: None,
+			context_tokens: None,
+			cost: 0.0,
+			notice: String::new(),
+			quit: false
+		}
+	}
+
+	/// Rows of the sidebar: conversations matching the search, grouped by topic, with ungrouped ones last.
+	fn sidebar_rows(&self) -> Vec<SidebarRow> {
+		let search = self.search.to_lowercase();
+		let mut matching: Vec<usize> = (0..self.conversations.len())
+			.filter(|index| {
+				let conv = &self.conversations[*index];
+				search.is_empty()
+					|| conv.title.to_lowercase().contains(&search)
+					|| conv.topic.as_ref().is_some_and(|topic| topic.to_lowercase().contains(&search))
+			})
+			.collect();
+		matching.sort_by_key(|index| (self.conversations[*index].topic.is_none(), self.conversations[*index].topic.clone()));
+
+		let mut rows = vec![];
+		let mut current_topic: Option<Option<String>> = None;
+		for index in matching {
+			let topic = self.conversations[index].topic.clone();
+			if current_topic.as_ref() != Some(&topic) {
+				rows.push(SidebarRow::Topic(topic.clone().unwrap_or("No topic".into())));
+				current_topic = Some(topic);
+			}
+			rows.push(SidebarRow::Conversation(index));
+		}
+		rows
+	}
+
+	fn visible_conversations(&self) -> Vec<usize> {
+		self.sidebar_rows()
+			.into_iter()
+			.filter_map(|row| match row {
+				SidebarRow::Conversation(index) => Some(index),
+				SidebarRow::Topic(_) => None,
+			})
+			.collect()
+	}
+
+	fn selected_conversation(&self) -> Option<&ConversationListing> {
+		self.visible_conversations().get(self.selected).map(|index| &self.conversations[*index])
+	}
+
+	fn move_selection(&mut self, offset: isize) {
+		let count = self.visible_conversations().len();
+		if count > 0 {
+			self.selected = self.selected.saturating_add_signed(offset).min(count - 1);
+		}
+	}
+}
+
+fn open_conversation(mgr: &mut ChatManager, state: &mut TuiState, conversation_id: u32) -> Result<(), MainError> {
+	let (session, notes) = open_session(mgr, conversation_id)?;
+	mgr.current_session = Some(session);
+	state.transcript = None;
+	state.scroll_back = 0;
+	state.context_tokens = None;
+	state.cost = conversation_cost(mgr, conversation_id)?;
+	state.notice = notes.join(" ");
+	if state.input.is_empty() {
+		state.input = Database::get_draft(&mgr.connection, conversation_id)?.unwrap_or_default();
+	}
+	Ok(())
+}
+
+/// Sends `prompt` in the open conversation, or in a new one if none is open, and starts streaming the reply.
+async fn send(mgr: &mut ChatManager, state: &mut TuiState, terminal: &mut DefaultTerminal, prompt: String) -> Result<(), MainError> {
+	if mgr.current_session.is_none() {
+		let conversation_id = Database::add_conversation(&mgr.connection, &title_of(&prompt), &mgr.api_key)?;
+		open_conversation(mgr, state, conversation_id)?;
+		state.conversations = load_conversations(mgr)?;
+	}
+	mgr.current_session.as_mut().unwrap().prompt = prompt.clone();
+
+	state.notice = "Preparing the request...".into();
+	terminal.draw(|frame| draw(frame, mgr, state))?;
+	let preparation = prepare_chat(mgr, true).await;
+	// Building the context may print warnings, for example when a summary fails, so repaint everything
+	terminal.clear()?;
+	let prepared = match preparation? {
+		Preparation::Ready(prepared) => prepared,
+		Preparation::Refused(refusal) => {
+			state.notice = format!("{} Press Ctrl+O to send it anyway.", refusal);
+			return Ok(());
+		}
+	};
+	state.context_tokens = Some(prepared.context.iter().map(|msg| crate::attachment::estimate_content_tokens(&msg.content)).sum());
+
+	let (sender, events) = unbounded_channel();
+	let context = prepared.context.clone();
+	let (api_key, proxy, api_base, model, params) = (mgr.api_key.clone(), mgr.proxy.clone(), mgr.api_base.clone(), mgr.model.clone(), mgr.params.clone());
+	tokio::spawn(async move {
+		let started = Instant::now();
+		let deltas = sender.clone();
+		let response = get_response_stream(&context, &api_key, &proxy, &api_base, &model, &params, |delta| {
+			let _ = deltas.send(StreamEvent::Delta(delta.into()));
+		}).await;
+		let _ = sender.send(StreamEvent::Done(response, started.elapsed().as_millis() as u64));
+	});
+
+	state.in_flight = Some(InFlight { prompt, context: prepared.context, notes: prepared.notes, budget_warnings: prepared.budget_warnings, reply: String::new(), rendered: None, events });
+	state.scroll_back = 0;
+	state.notice = "ChatGPT is replying...".into();
+	Ok(())
+}
+
+/// Takes the streamed pieces of the reply, and records the exchange once it is complete.
+fn receive(mgr: &mut ChatManager, state: &mut TuiState) -> Result<(), MainError> {
+	let Some(in_flight) = state.in_flight.as_mut() else {
+		return Ok(());
+	};
+	let mut done = None;
+	while let Ok(event) = in_flight.events.try_recv() {
+		match event {
+			StreamEvent::Delta(delta) => in_flight.reply.push_str(&delta),
+			StreamEvent::Done(response, latency_ms) => done = Some((response, latency_ms)),
+		}
+	}
+	let Some((response, latency_ms)) = done else {
+		return Ok(());
+	};
+
+	let in_flight = state.in_flight.take().unwrap();
+	state.notice = match record_chat(mgr, &in_flight.context, response, latency_ms)? {
+		ChatOutcome::Reply => in_flight.notes.iter().chain(in_flight.budget_warnings.iter()).cloned().collect::<Vec<String>>().join(" "),
+		ChatOutcome::Error(error) => {
+			state.input = in_flight.prompt;
+			format!("Error: {}", error)
+		}
+	};
+	let conversation_id = mgr.current_session.as_ref().unwrap().conversation_id;
+	state.cost = conversation_cost(mgr, conversation_id)?;
+	state.conversations = load_conversations(mgr)?;
+	state.transcript = None;
+	Ok(())
+}
+
+fn transcript_text(mgr: &ChatManager, state: &mut TuiState, width: u16) -> Text<'static> {
+	let Some(session) = mgr.current_session.as_ref() else {
+		return Text::from(vec![
+			Line::from("New conversation").bold(),
+			Line::from("Type a message below to start it, or choose a conversation on the left."),
+		]);
+	};
+
+	let cached = state.transcript.as_ref().is_some_and(|(cached_width, count, _)| *cached_width == width && *count == session.history.len());
+	if !cached {
+		let mut text = Text::default();
+		if let Some(system_prompt) = &session.system_prompt {
+			text.lines.push(header_line("System", None));
+			text.lines.extend(Text::raw(system_prompt.trim().to_string()).lines);
+			text.lines.push(Line::default());
+		}
+		for msg in session.history.iter() {
+			text.lines.push(header_line(speaker_of(msg), Some(msg.id)));
+			match role_of(msg) {
+				MessageRole::Assistant => text.lines.extend(render_markdown(&msg.content, width).lines),
+				_ => text.lines.extend(Text::raw(msg.content.trim().to_string()).lines),
+			}
+			for attachment in msg.attachments.iter() {
+				text.lines.push(Line::from(format!("[Attachment: {}]", attachment.name)).dim());
+			}
+			text.lines.push(Line::default());
+		}
+		state.transcript = Some((width, session.history.len(), text));
+	}
+
+	let mut text = state.transcript.as_ref().unwrap().2.clone();
+	if let Some(in_flight) = state.in_flight.as_mut() {
+		text.lines.push(header_line("You", None));
+		text.lines.extend(Text::raw(in_flight.prompt.clone()).lines);
+		text.lines.push(Line::default());
+		text.lines.push(header_line("ChatGPT", None));
+		let cached = in_flight.rendered.as_ref().is_some_and(|(cached_width, length, _)| *cached_width == width && *length == in_flight.reply.len());
+		if !cached {
+			in_flight.rendered = Some((width, in_flight.reply.len(), render_markdown(&format!("{}▌", in_flight.reply), width)));
+		}
+		text.lines.extend(in_flight.rendered.as_ref().unwrap().2.lines.iter().cloned());
+	}
+	text
+}
+
+fn draw_sidebar(frame: &mut Frame, area: Rect, mgr: &ChatManager, state: &TuiState) {
+	let open_id = mgr.current_session.as_ref().map(|session| session.conversation_id);
+	let rows = state.sidebar_rows();
+	let mut selected_row = None;
+	let mut conversation_number = 0;
+	let items: Vec<ListItem> = rows
+		.iter()
+		.enumerate()
+		.map(|(row, item)| match item {
+			SidebarRow::Topic(topic) => ListItem::new(Line::from(topic.clone()).bold().fg(Color::Magenta)),
+			SidebarRow::Conversation(index) => {
+				if conversation_number == state.selected {
+					selected_row = Some(row);
+				}
+				conversation_number += 1;
+				let conv = &state.conversations[*index];
+				let marker = if Some(conv.id) == open_id { "●" } else { " " };
+				ListItem::new(format!("{} {:>3} {}", marker, conv.id, conv.title))
+			}
+		})
+		.collect();
+
+	let title = if state.search.is_empty() { " Conversations ".to_string() } else { format!(" Conversations: {} ", state.search) };
+	let border = if state.focus == Focus::Sidebar { Color::Yellow } else { Color::DarkGray };
+	let list = List::new(items)
+		.block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(border)))
+		.highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+	let mut list_state = ListState::default().with_selected(selected_row);
+	frame.render_stateful_widget(list, area, &mut list_state);
+}
+
+fn draw(frame: &mut Frame, mgr: &ChatManager, state: &mut TuiState) {
+	let [main, status] = Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+	let [sidebar, right] = Layout::horizontal([Constraint::Length(SIDEBAR_WIDTH), Constraint::Min(20)]).areas(main);
+	let input_lines = state.input.lines().count().max(1) as u16 + if state.input.ends_with('\n') { 1 } else { 0 };
+	let [transcript_area, input_area] = Layout::vertical([Constraint::Min(3), Constraint::Length(input_lines.min(MAX_INPUT_HEIGHT) + 2)]).areas(right);
+
+	draw_sidebar(frame, sidebar, mgr, state);
+
+	let title = mgr.current_session.as_ref()
+		.and_then(|session| state.conversations.iter().find(|conv| conv.id == session.conversation_id))
+		.map_or(" New conversation ".to_string(), |conv| format!(" {} ", conv.title));
+	let width = transcript_area.width.saturating_sub(2).max(10);
+	let text = transcript_text(mgr, state, width);
+	let paragraph = Paragraph::new(text).wrap(Wrap { trim: false });
+	let height = transcript_area.height.saturating_sub(2);
+	let total = paragraph.line_count(width) as u16;
+	let max_scroll = total.saturating_sub(height);
+	state.scroll_back = state.scroll_back.min(max_scroll);
+	let paragraph = paragraph
+		.block(Block::default().borders(Borders::ALL).title(title).border_style(Style::default().fg(Color::DarkGray)))
+		.scroll((max_scroll - state.scroll_back, 0));
+	frame.render_widget(paragraph, transcript_area);
+
+	let input_title = match state.mode {
+		InputMode::Message if state.in_flight.is_some() => " Message (waiting for the reply) ".to_string(),
+		InputMode::Message => " Message ".to_string(),
+		InputMode::Search => " Search conversations ".to_string(),
+		InputMode::Topic(id) => format!(" Topic for conversation {} (empty to remove) ", id),
+	};
+	let border = if state.focus == Focus::Input { Color::Yellow } else { Color::DarkGray };
+	let visible_from = (input_lines.saturating_sub(MAX_INPUT_HEIGHT)) as usize;
+	let input_text: Vec<&str> = state.input.split('\n').skip(visible_from).collect();
+	let input = Paragraph::new(input_text.join("\n"))
+		.block(Block::default().borders(Borders::ALL).title(input_title).border_style(Style::default().fg(border)));
+	frame.render_widget(input, input_area);
+	if state.focus == Focus::Input {
+		let last_line = state.input.rsplit('\n').next().unwrap_or_default();
+		let row = (input_text.len().max(1) as u16 - 1).min(MAX_INPUT_HEIGHT - 1);
+		frame.set_cursor_position((input_area.x + 1 + last_line.width() as u16, input_area.y + 1 + row));
+	}
+
+	let context = match state.context_tokens {
+		Some(tokens) => format!("~{}/{} tokens", tokens, mgr.max_token),
+		None => format!("-/{} tokens", mgr.max_token),
+	};
+	let notice = if state.notice.is_empty() { KEY_HINTS } else { state.notice.as_str() };
+	let status_line = format!(" {} │ context {} │ cost ${:.4} │ {}", mgr.model, context, state.cost, notice);
+	frame.render_widget(Paragraph::new(status_line).style(Style::default().bg(Color::DarkGray).fg(Color::White)), status);
+}
+
+/// What a key press asks for that needs the chat manager.
+enum Action {
+	Open(u32),
+	Send(String),
+	Override,
+	SetTopic(u32, String),
+}
+
+fn handle_key(state: &mut TuiState, key: KeyEvent) -> Option<Action> {
+	let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+	match key.code {
+		KeyCode::Char('c' | 'q') if ctrl => state.quit = true,
+		KeyCode::Char('n') if ctrl => {
+			state.mode = InputMode::Message;
+			state.focus = Focus::Input;
+			return Some(Action::Open(0));
+		},
+		KeyCode::Char('o') if ctrl => return Some(Action::Override),
+		KeyCode::PageUp => state.scroll_back = state.scroll_back.saturating_add(10),
+		KeyCode::PageDown => state.scroll_back = state.scroll_back.saturating_sub(10),
+		KeyCode::Tab | KeyCode::BackTab if state.mode == InputMode::Message => {
+			state.focus = if state.focus == Focus::Sidebar { Focus::Input } else { Focus::Sidebar };
+		},
+		_ if state.focus == Focus::Sidebar => match key.code {
+			KeyCode::Up | KeyCode::Char('k') => state.move_selection(-1),
+			KeyCode::Down | KeyCode::Char('j') => state.move_selection(1),
+			KeyCode::Home => state.selected = 0,
+			KeyCode::End => state.move_selection(isize::MAX),
+			KeyCode::Char('/') => {
+				state.mode = InputMode::Search;
+				state.focus = Focus::Input;
+				state.input = state.search.clone();
+			},
+			KeyCode::Char('t') => {
+				if let Some((id, topic)) = state.selected_conversation().map(|conv| (conv.id, conv.topic.clone())) {
+					state.mode = InputMode::Topic(id);
+					state.input = topic.unwrap_or_default();
+					state.focus = Focus::Input;
+				}
+			},
+			KeyCode::Esc => {
+				state.search.clear();
+				state.selected = 0;
+			},
+			KeyCode::Enter => {
+				if let Some(id) = state.selected_conversation().map(|conv| conv.id) {
+					state.focus = Focus::Input;
+					return Some(Action::Open(id));
+				}
+			},
+			_ => {},
+		},
+		KeyCode::Enter if key.modifiers.contains(KeyModifiers::ALT) || key.modifiers.contains(KeyModifiers::SHIFT) => state.input.push('\n'),
+		KeyCode::Enter => match state.mode {
+			InputMode::Message => {
+				if state.input.trim().is_empty() || state.in_flight.is_some() {
+					return None;
+				}
+				return Some(Action::Send(std::mem::take(&mut state.input)));
+			},
+			InputMode::Search => {
+				state.mode = InputMode::Message;
+				state.input.clear();
+				state.focus = Focus::Sidebar;
+			},
+			InputMode::Topic(id) => {
+				state.mode = InputMode::Message;
+				state.focus = Focus::Sidebar;
+				return Some(Action::SetTopic(id, std::mem::take(&mut state.input)));
+			},
+		},
+		KeyCode::Esc => {
+			if state.mode == InputMode::Search {
+				state.search.clear();
+			}
+			if state.mode != InputMode::Message {
+				state.mode = InputMode::Message;
+				state.input.clear();
+				state.focus = Focus::Sidebar;
+			}
+		},
+		KeyCode::Backspace => {
+			state.input.pop();
+		},
+		KeyCode::Char(c) if !ctrl => state.input.push(c),
+		_ => {},
+	}
+	if state.mode == InputMode::Search {
+		state.search = state.input.clone();
+		state.selected = 0;
+	}
+	None
+}
+
+async fn run(mgr: &mut ChatManager, terminal: &mut DefaultTerminal) -> Result<(), MainError> {
+	let mut state = TuiState::new(load_conversations(mgr)?);
+
+	while !state.quit {
+		receive(mgr, &mut state)?;
+		terminal.draw(|frame| draw(frame, mgr, &mut state))?;
+		if !event::poll(Duration::from_millis(50))? {
+			continue
+		}
+
+		let action = match event::read()? {
+			Event::Key(key) if key.kind == KeyEventKind::Press => handle_key(&mut state, key),
+			Event::Paste(text) if state.focus == Focus::Input => {
+				state.input.push_str(&text.replace("\r\n", "\n").replace('\r', "\n"));
+				None
+			},
+			_ => None,
+		};
+		match action {
+			Some(_) if state.in_flight.is_some() => state.notice = "Wait for the reply to finish first.".into(),
+			Some(Action::Open(0)) => {
+				mgr.current_session = None;
+				state.transcript = None;
+				state.context_tokens = None;
+				state.cost = 0.0;
+				state.notice = "The first message starts a new conversation.".into();
+			},
+			Some(Action::Open(id)) => open_conversation(mgr, &mut state, id)?,
+			Some(Action::Send(prompt)) => {
+				if prompt.trim_start().starts_with(['/', '!']) {
+					state.notice = "Slash and shell commands are only available in the line mode.".into();
+					state.input = prompt;
+				}
+				else {
+					send(mgr, &mut state, terminal, prompt).await?;
+				}
+			},
+			Some(Action::Override) => {
+				let refused = mgr.current_session.as_mut().and_then(|session| {
+					session.budget_override = true;
+					session.refused_prompt.take()
+				});
+				match refused {
+					Some(prompt) => send(mgr, &mut state, terminal, prompt).await?,
+					None => state.notice = "Your next message will be sent regardless of budgets.".into(),
+				}
+			},
+			Some(Action::SetTopic(id, name)) => {
+				let name = name.trim();
+				Database::set_conversation_topic(&mgr.connection, id, Some(name).filter(|name| !name.is_empty()))?;
+				state.conversations = load_conversations(mgr)?;
+				state.notice = String::new();
+			},
+			None => {},
+		}
+	}
+	Ok(())
+}
+
+/// Runs the full-screen interface until the user quits.
+pub async fn run_tui(mgr: &mut ChatManager) -> Result<(), MainError> {
+	let mut terminal = ratatui::try_init()?;
+	execute!(std::io::stdout(), EnableBracketedPaste)?;
+	let result = run(mgr, &mut terminal).await;
+	execute!(std::io::stdout(), DisableBracketedPaste)?;
+	ratatui::try_restore()?;
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use ratatui::backend::TestBackend;
+	use ratatui::Terminal;
+	use tokio::sync::mpsc::UnboundedSender;
+
+	use super::*;
+	use crate::test_support::*;
+
+	fn press(state: &mut TuiState, code: KeyCode) -> Option<Action> {
+		handle_key(state, KeyEvent::new(code, KeyModifiers::NONE))
+	}
+
+	fn type_text(state: &mut TuiState, text: &str) {
+		for c in text.chars() {
+			press(state, KeyCode::Char(c));
+		}
+	}
+
+	/// A manager with conversations "alpha" and "beta" under topic "work" and an ungrouped "gamma", with the state to browse them.
+	fn browsing() -> (ChatManager, TuiState) {
+		let mgr = test_manager("http://127.0.0.1:9/v1");
+		for title in ["alpha", "gamma", "beta"] {
+			let id = Database::add_conversation(&mgr.connection, title, &mgr.api_key).unwrap();
+			if title != "gamma" {
+				Database::set_conversation_topic(&mgr.connection, id, Some("work")).unwrap();
+			}
+		}
+		let state = TuiState::new(load_conversations(&mgr).unwrap());
+		(mgr, state)
+	}
+
+	fn sidebar(state: &TuiState) -> Vec<String> {
+		state.sidebar_rows()
+			.into_iter()
+			.map(|row| match row {
+				SidebarRow::Topic(topic) => format!("[{}]", topic),
+				SidebarRow::Conversation(index) => state.conversations[index].title.clone(),
+			})
+			.collect()
+	}
+
+	fn id_of(state: &TuiState, title: &str) -> u32 {
+		state.conversations.iter().find(|conv| conv.title == title).unwrap().id
+	}
+
+	fn start_reply(state: &mut TuiState, prompt: &str) -> UnboundedSender<StreamEvent> {
+		let (sender, events) = unbounded_channel();
+		state.in_flight = Some(InFlight { prompt: prompt.into(), context: vec![], notes: vec![], budget_warnings: vec![], reply: String::new(), rendered: None, events });
+		sender
+	}
+
+	#[test]
+	fn groups_and_searches_the_sidebar() {
+		let (_, mut state) = browsing();
+		let rows = sidebar(&state);
+		assert_eq!(rows.len(), 5);
+		assert_eq!(rows[0], "[work]");
+		assert_eq!(rows[3..], ["[No topic]", "gamma"]);
+
+		press(&mut state, KeyCode::Char('/'));
+		assert!(state.mode == InputMode::Search && state.focus == Focus::Input);
+		type_text(&mut state, "GAM");
+		assert_eq!(sidebar(&state), ["[No topic]", "gamma"]);
+		type_text(&mut state, "x");
+		assert!(sidebar(&state).is_empty());
+		press(&mut state, KeyCode::Backspace);
+		press(&mut state, KeyCode::Enter);
+		assert!(state.mode == InputMode::Message && state.focus == Focus::Sidebar && state.input.is_empty());
+		assert_eq!(state.search, "GAM");
+
+		// Searching by topic, and Esc in the sidebar clears the search
+		press(&mut state, KeyCode::Char('/'));
+		assert_eq!(state.input, "GAM");
+		press(&mut state, KeyCode::Esc);
+		assert!(state.search.is_empty() && state.mode == InputMode::Message);
+		state.search = "WORK".into();
+		assert_eq!(sidebar(&state).len(), 3);
+		press(&mut state, KeyCode::Esc);
+		assert_eq!(sidebar(&state).len(), 5);
+	}
+
+	#[test]
+	fn moves_the_selection_within_the_visible_conversations() {
+		let (_, mut state) = browsing();
+		press(&mut state, KeyCode::Up);
+		assert_eq!(state.selected, 0);
+		press(&mut state, KeyCode::Down);
+		press(&mut state, KeyCode::Char('j'));
+		assert_eq!(state.selected_conversation().unwrap().title, "gamma");
+		press(&mut state, KeyCode::Down);
+		assert_eq!(state.selected, 2);
+		press(&mut state, KeyCode::Char('k'));
+		assert_eq!(state.selected, 1);
+		press(&mut state, KeyCode::End);
+		assert_eq!(state.selected, 2);
+		press(&mut state, KeyCode::Home);
+		assert_eq!(state.selected, 0);
+
+		// Enter opens the selected conversation and moves to the input
+		let id = state.selected_conversation().unwrap().id;
+		assert!(matches!(press(&mut state, KeyCode::Enter), Some(Action::Open(opened)) if opened == id));
+		assert!(state.focus == Focus::Input);
+
+		state.search = "gamma".into();
+		state.selected = 0;
+		press(&mut state, KeyCode::Tab);
+		press(&mut state, KeyCode::End);
+		assert_eq!(state.selected_conversation().unwrap().title, "gamma");
+	}
+
+	#[test]
+	fn edits_and_sends_messages_from_the_input() {
+		let (_, mut state) = browsing();
+		press(&mut state, KeyCode::Tab);
+		assert!(state.focus == Focus::Input);
+		type_text(&mut state, "j/k");
+		assert_eq!(state.input, "j/k", "keys of the sidebar are typed in the input");
+		handle_key(&mut state, KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT));
+		type_text(&mut state, "second");
+		assert_eq!(state.input, "j/k\nsecond");
+
+		assert!(matches!(press(&mut state, KeyCode::Enter), Some(Action::Send(prompt)) if prompt == "j/k\nsecond"));
+		assert!(state.input.is_empty());
+		type_text(&mut state, "  ");
+		assert!(press(&mut state, KeyCode::Enter).is_none());
+
+		// Nothing is sent while a reply is streaming
+		type_text(&mut state, "more");
+		let _sender = start_reply(&mut state, "prompt");
+		assert!(press(&mut state, KeyCode::Enter).is_none());
+		assert_eq!(state.input, "  more");
+
+		press(&mut state, KeyCode::BackTab);
+		assert!(state.focus == Focus::Sidebar);
+		handle_key(&mut state, KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
+		assert!(state.quit);
+	}
+
+	#[test]
+	fn names_the_topic_of_the_selected_conversation() {
+		let (_, mut state) = browsing();
+		let id = state.selected_conversation().unwrap().id;
+		press(&mut state, KeyCode::Char('t'));
+		assert!(state.mode == InputMode::Topic(id));
+		assert_eq!(state.input, "work");
+		press(&mut state, KeyCode::Backspace);
+		press(&mut state, KeyCode::Char('s'));
+		assert!(matches!(press(&mut state, KeyCode::Enter), Some(Action::SetTopic(topic_id, name)) if topic_id == id && name == "wors"));
+		assert!(state.mode == InputMode::Message && state.focus == Focus::Sidebar);
+
+		// Esc leaves the topic as it was
+		press(&mut state, KeyCode::Char('t'));
+		assert!(press(&mut state, KeyCode::Esc).is_none());
+		assert!(state.mode == InputMode::Message && state.input.is_empty());
+	}
+
+	#[test]
+	fn scrolls_back_within_the_transcript() {
+		let (mut mgr, mut state) = browsing();
+		let id = id_of(&state, "alpha");
+		for index in 0..20 {
+			Database::add_client_message(&mgr.connection, id, &format!("question {}", index)).unwrap();
+		}
+		open_conversation(&mut mgr, &mut state, id).unwrap();
+		let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+
+		press(&mut state, KeyCode::PageUp);
+		press(&mut state, KeyCode::PageUp);
+		assert_eq!(state.scroll_back, 20);
+		terminal.draw(|frame| draw(frame, &mgr, &mut state)).unwrap();
+		assert_eq!(state.scroll_back, 20);
+		for _ in 0..10 {
+			press(&mut state, KeyCode::PageUp);
+		}
+		// 20 messages of three lines, in a transcript of 24 - 3 - 1 - 2 lines
+		terminal.draw(|frame| draw(frame, &mgr, &mut state)).unwrap();
+		assert_eq!(state.scroll_back, 60 - 18);
+		press(&mut state, KeyCode::PageDown);
+		assert_eq!(state.scroll_back, 60 - 28);
+		for _ in 0..10 {
+			press(&mut state, KeyCode::PageDown);
+		}
+		assert_eq!(state.scroll_back, 0);
+	}
+
+	#[test]
+	fn switches_conversations_and_restores_their_drafts() {
+		let (mut mgr, mut state) = browsing();
+		let (alpha, beta) = (id_of(&state, "alpha"), id_of(&state, "beta"));
+		Database::add_client_message(&mgr.connection, alpha, "hello alpha").unwrap();
+		Database::save_draft(&mgr.connection, beta, "unsent").unwrap();
+
+		open_conversation(&mut mgr, &mut state, alpha).unwrap();
+		assert_eq!(mgr.current_session.as_ref().unwrap().conversation_id, alpha);
+		assert!(state.input.is_empty());
+		let text = transcript_text(&mgr, &mut state, 60);
+		assert!(text.lines.iter().any(|line| line.to_string() == "hello alpha"));
+
+		state.scroll_back = 5;
+		open_conversation(&mut mgr, &mut state, beta).unwrap();
+		assert_eq!(mgr.current_session.as_ref().unwrap().conversation_id, beta);
+		assert_eq!(state.input, "unsent");
+		assert_eq!(state.scroll_back, 0);
+		let text = transcript_text(&mgr, &mut state, 60);
+		assert!(!text.lines.iter().any(|line| line.to_string() == "hello alpha"));
+
+		// What is being typed is not replaced by a draft
+		state.input = "typed".into();
+		open_conversation(&mut mgr, &mut state, beta).unwrap();
+		assert_eq!(state.input, "typed");
+	}
+
+	#[tokio::test]
+	async fn appends_streamed_pieces_and_records_the_reply() {
+		let (mut mgr, mut state) = browsing();
+		let id = id_of(&state, "alpha");
+		open_conversation(&mut mgr, &mut state, id).unwrap();
+		mgr.current_session.as_mut().unwrap().prompt = "Say hi".into();
+		Database::save_draft(&mgr.connection, id, "Say hi").unwrap();
+		let sender = start_reply(&mut state, "Say hi");
+
+		sender.send(StreamEvent::Delta("Hi".into())).unwrap();
+		sender.send(StreamEvent::Delta(" **there**".into())).unwrap();
+		receive(&mut mgr, &mut state).unwrap();
+		assert_eq!(state.in_flight.as_ref().unwrap().reply, "Hi **there**");
+		let text = transcript_text(&mgr, &mut state, 60);
+		assert_eq!(text.lines.last().unwrap().to_string(), "Hi there▌");
+		sender.send(StreamEvent::Delta("!".into())).unwrap();
+		receive(&mut mgr, &mut state).unwrap();
+		let text = transcript_text(&mgr, &mut state, 60);
+		assert_eq!(text.lines.last().unwrap().to_string(), "Hi there!▌");
+		assert!(mgr.current_session.as_ref().unwrap().history.is_empty());
+
+		sender.send(StreamEvent::Done(Ok(OpenAIResponse::Success(reply("Hi **there**!", 5, 3))), 10)).unwrap();
+		receive(&mut mgr, &mut state).unwrap();
+		assert!(state.in_flight.is_none());
+		let history = &mgr.current_session.as_ref().unwrap().history;
+		assert_eq!(history.iter().map(|msg| msg.content.as_str()).collect::<Vec<_>>(), ["Say hi", "Hi **there**!"]);
+		assert_eq!(Database::get_draft(&mgr.connection, id).unwrap(), None);
+		assert!(state.cost > 0.0);
+		let text = transcript_text(&mgr, &mut state, 60);
+		assert!(text.lines.iter().any(|line| line.to_string() == "Hi there!"));
+		assert!(!text.lines.iter().any(|line| line.to_string().contains('▌')));
+	}
+
+	#[tokio::test]
+	async fn restores_the_prompt_when_the_reply_fails() {
+		let (mut mgr, mut state) = browsing();
+		let id = id_of(&state, "alpha");
+		open_conversation(&mut mgr, &mut state, id).unwrap();
+		mgr.current_session.as_mut().unwrap().prompt = "Say hi".into();
+		let sender = start_reply(&mut state, "Say hi");
+
+		sender.send(StreamEvent::Delta("H".into())).unwrap();
+		sender.send(StreamEvent::Done(Err("connection reset".into()), 10)).unwrap();
+		receive(&mut mgr, &mut state).unwrap();
+		assert!(state.in_flight.is_none());
+		assert_eq!(state.input, "Say hi");
+		assert_eq!(state.notice, "Error: connection reset");
+		assert!(mgr.current_session.as_ref().unwrap().history.is_empty());
+	}
+}
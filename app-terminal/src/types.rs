@@ -1,5 +1,10 @@
+use std::path::PathBuf;
+
 use rusqlite::Connection;
-use openai::types::SavedMessage;
+use openai::types::{Attachment, CompletionParams, ConversationSettings, IncludedFile, SavedMessage};
+
+use crate::context::ContextStrategy;
+use crate::shell::PendingCommand;
 
 pub struct ChatManager {
 	pub max_token: u64,
@@ -7,12 +12,36 @@ pub struct ChatManager {
 	pub api_key: String,
 	pub connection: Connection,
 	pub proxy: Option<String>,
+	pub api_base: String,
 	pub model: String,
+	pub params: CompletionParams,
+	pub context_strategy: ContextStrategy,
+	pub embedding_model: String,
+	pub recall_k: usize,
+	pub persona_dir: PathBuf,
+	pub overrides: ConversationSettings,
+	pub update_settings: bool,
+	pub budget_warn_percent: u32,
+	pub ignore_budget: bool,
+	pub include_max_size: u64,
+	pub include_max_tokens: Option<u64>,
+	pub shell_confirm: bool,
+	pub shell_timeout: u64,
+	pub shell_max_lines: usize,
+	pub raw: bool,
 	pub current_session: Option<ChatSession>
 }
 
 pub struct ChatSession {
 	pub conversation_id: u32,
 	pub history: Vec<SavedMessage>,
-	pub prompt: String
-}
\ No newline at end of file
+	pub system_prompt: Option<String>,
+	pub prompt: String,
+	pub pending_attachments: Vec<Attachment>,
+	pub budget_override: bool,
+	pub refused_prompt: Option<String>,
+	pub included_files: Vec<IncludedFile>,
+	pub pending_commands: Vec<PendingCommand>,
+	/// The message chosen with /code, whose code blocks /save and /copy refer to.
+	pub code_message: Option<u32>
+}
\ No newline at end of file
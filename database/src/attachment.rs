@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use rusqlite::{Connection, Result};
+use sha2::{Digest, Sha256};
+use openai::types::*;
+
+use crate::Database;
+
+/// Deletes stored attachment data that no message or failed prompt uses any more.
+pub(crate) const DELETE_UNUSED_ATTACHMENTS: &str = "
+	DELETE FROM attachment
+	WHERE id NOT IN (SELECT attachment_id FROM message_attachment)
+	AND id NOT IN (SELECT attachment_id FROM error_attachment);
+";
+
+impl Database {
+	/// Stores the data of an attachment once for identical content and returns its ID.
+	fn store_attachment_data(conn: &Connection, attachment: &Attachment) -> Result<u32> {
+		let hash = format!("{:x}", Sha256::digest(&attachment.data));
+		let sql = "
+			INSERT OR IGNORE INTO attachment (hash, mime, size, data) VALUES (?, ?, ?, encrypt(?));
+		";
+		conn.execute(sql, rusqlite::params![hash, attachment.mime, attachment.data.len(), attachment.data])?;
+		conn.query_row("SELECT id FROM attachment WHERE hash = ?;", [&hash], |row| row.get(0))
+	}
+
+	/// Stores an attachment of message `message_id`. Identical content is stored only once.
+	pub fn add_attachment(conn: &Connection, message_id: u32, position: u32, attachment: &Attachment) -> Result<u32> {
+		let attachment_id = Database::store_attachment_data(conn, attachment)?;
+		let sql = "
+			INSERT INTO message_attachment (message_id, attachment_id, position, name) VALUES (?, ?, ?, encrypt(?));
+		";
+		conn.execute(sql, rusqlite::params![message_id, attachment_id, position, attachment.name])?;
+		Ok(attachment_id)
+	}
+
+	/// Returns the attachments of every message in conversation `id`, keyed by message ID.
+	pub fn get_attachments_in_conversation(conn: &Connection, id: u32) -> Result<HashMap<u32, Vec<Attachment>>> {
+		let sql = "
+			SELECT b.message_id, decrypt(b.name), a.mime, decrypt(a.data)
+			FROM attachment a
+			INNER JOIN message_attachment b ON a.id = b.attachment_id
+			INNER JOIN message c ON c.id = b.message_id
+			WHERE c.conversation_id = ?
+			ORDER BY b.message_id ASC, b.position ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let mut attachments: HashMap<u32, Vec<Attachment>> = HashMap::new();
+		let rows = stmt.query_map([id], |row| {
+			Ok((row.get::<_, u32>(0)?, Attachment {
+				name: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+				mime: row.get(2)?,
+				data: row.get(3)?
+			}))
+		})?;
+		for row in rows {
+			let (message_id, attachment) = row?;
+			attachments.entry(message_id).or_default().push(attachment);
+		}
+
+		Ok(attachments)
+	}
+
+	/// Keeps the attachments of a prompt that failed with error log `error_id`.
+	pub fn add_error_attachments(conn: &Connection, error_id: u32, attachments: &[Attachment]) -> Result<()> {
+		let tx = conn.unchecked_transaction()?;
+		let sql = "
+			INSERT INTO error_attachment (error_id, attachment_id, position, name) VALUES (?, ?, ?, encrypt(?));
+		";
+		for (position, attachment) in attachments.iter().enumerate() {
+			let attachment_id = Database::store_attachment_data(&tx, attachment)?;
+			tx.execute(sql, rusqlite::params![error_id, attachment_id, position, attachment.name])?;
+		}
+		tx.commit()
+	}
+
+	/// Returns the attachments of the prompt that failed with error log `error_id`, in order.
+	pub fn get_error_attachments(conn: &Connection, error_id: u32) -> Result<Vec<Attachment>> {
+		let sql = "
+			SELECT decrypt(b.name), a.mime, decrypt(a.data)
+			FROM attachment a
+			INNER JOIN error_attachment b ON a.id = b.attachment_id
+			WHERE b.error_id = ?
+			ORDER BY b.position ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let attachments = stmt
+			.query_map([error_id], |row| {
+				Ok(Attachment {
+					name: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+					mime: row.get(1)?,
+					data: row.get(2)?
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(attachments)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rusqlite::types::Value;
+
+	use super::*;
+	use crate::test_support::*;
+
+	fn attachment(name: &str, data: &[u8]) -> Attachment {
+		Attachment { name: name.into(), mime: "text/plain".into(), data: data.to_vec() }
+	}
+
+	#[test]
+	fn stores_encrypted_attachments_once() {
+		let conn = test_connection();
+		Database::encrypt_database(&conn, "secret").unwrap();
+		let id = Database::add_conversation(&conn, "title", "key").unwrap();
+		let first = Database::add_client_message(&conn, id, "first").unwrap();
+		let second = Database::add_client_message(&conn, id, "second").unwrap();
+
+		Database::add_attachment(&conn, first, 0, &attachment("a.txt", b"same")).unwrap();
+		Database::add_attachment(&conn, first, 1, &attachment("b.txt", b"other")).unwrap();
+		Database::add_attachment(&conn, second, 0, &attachment("c.txt", b"same")).unwrap();
+
+		let count: u32 = conn.query_row("SELECT COUNT(*) FROM attachment;", [], |row| row.get(0)).unwrap();
+		assert_eq!(count, 2);
+		let data: Value = conn.query_row("SELECT data FROM attachment WHERE size = 4;", [], |row| row.get(0)).unwrap();
+		assert!(matches!(data, Value::Blob(blob) if blob != b"same"));
+
+		let attachments = Database::get_attachments_in_conversation(&conn, id).unwrap();
+		assert_eq!(attachments[&first], [attachment("a.txt", b"same"), attachment("b.txt", b"other")]);
+		assert_eq!(attachments[&second], [attachment("c.txt", b"same")]);
+	}
+
+	#[test]
+	fn keeps_attachments_of_failed_prompts_until_purged() {
+		let conn = test_connection();
+		let context = [Message { role: MessageRole::User, content: MessageContent::Text("hello".into()) }];
+		let error_id = Database::add_error_log(&conn, "key", "gpt-4", None, &context, "timed out", None).unwrap();
+		Database::add_error_attachments(&conn, error_id, &[attachment("a.txt", b"a"), attachment("b.txt", b"b")]).unwrap();
+
+		assert_eq!(Database::get_error_attachments(&conn, error_id).unwrap(), [attachment("a.txt", b"a"), attachment("b.txt", b"b")]);
+		Database::purge_error_logs(&conn, None, false).unwrap();
+		let count: u32 = conn.query_row("SELECT COUNT(*) FROM attachment;", [], |row| row.get(0)).unwrap();
+		assert_eq!(count, 0);
+	}
+}
@@ -0,0 +1,80 @@
+use std::{path::Path, time::Duration};
+
+use rusqlite::{backup::Backup, Connection, OpenFlags, Result};
+
+use crate::utils::{get_schema_version, table_exists};
+use crate::Database;
+
+/// Pages copied per backup step. Between steps the source database is unlocked, so other sessions can keep writing.
+const PAGES_PER_STEP: i32 = 256;
+const PAUSE_BETWEEN_STEPS: Duration = Duration::from_millis(10);
+
+impl Database {
+	pub fn get_schema_version(conn: &Connection) -> Result<u64> {
+		get_schema_version(conn)
+	}
+
+	/// Size of the database file in bytes, as seen by SQLite.
+	pub fn get_database_size(conn: &Connection) -> Result<u64> {
+		let page_count: u64 = conn.query_row("PRAGMA page_count;", [], |row| row.get(0))?;
+		let page_size: u64 = conn.query_row("PRAGMA page_size;", [], |row| row.get(0))?;
+		Ok(page_count * page_size)
+	}
+
+	/// Copies the database to `path` with SQLite's online backup API.
+	pub fn backup_database(conn: &Connection, path: &Path) -> Result<()> {
+		let mut dst = Connection::open(path)?;
+		let backup = Backup::new(conn, &mut dst)?;
+		backup.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)
+	}
+
+	pub fn is_conversation_database(path: &Path) -> Result<bool> {
+		let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+		Ok(table_exists(&conn, "conversation") && table_exists(&conn, "message"))
+	}
+
+	/// Replaces the contents of the database with the backup at `path`.
+	pub fn restore_database(conn: &mut Connection, path: &Path) -> Result<()> {
+		let src = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+		let backup = Backup::new(&src, conn)?;
+		backup.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)
+	}
+
+	pub fn vacuum(conn: &Connection) -> Result<usize> {
+		conn.execute("VACUUM;", [])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::crypto::register_functions;
+	use crate::test_support::*;
+
+	#[test]
+	fn restores_an_encrypted_backup() {
+		let path = std::env::temp_dir().join(format!("ai-backup-test-{}.db", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let mut conn = test_connection();
+		Database::encrypt_database(&conn, "secret").unwrap();
+		let id = Database::add_conversation(&conn, "kept", "key").unwrap();
+		Database::add_client_message(&conn, id, "hello").unwrap();
+		Database::backup_database(&conn, &path).unwrap();
+		Database::add_conversation(&conn, "after the backup", "key").unwrap();
+
+		assert!(Database::is_conversation_database(&path).unwrap());
+		Database::restore_database(&mut conn, &path).unwrap();
+		let conversations = Database::get_all_conversations(&conn, "key").unwrap();
+		assert_eq!(conversations.iter().map(|conversation| conversation.title.as_str()).collect::<Vec<_>>(), ["kept"]);
+		assert_eq!(Database::get_all_messages_in_conversation(&conn, id).unwrap()[0].content, "hello");
+
+		let backup = Connection::open(&path).unwrap();
+		register_functions(&backup, None, None).unwrap();
+		assert!(Database::is_encrypted(&backup).unwrap());
+		assert!(!Database::unlock(&backup, "wrong").unwrap());
+		assert!(Database::get_all_conversations(&backup, "key").is_err());
+		assert!(Database::unlock(&backup, "secret").unwrap());
+		assert_eq!(Database::get_all_conversations(&backup, "key").unwrap()[0].title, "kept");
+		std::fs::remove_file(&path).unwrap();
+	}
+}
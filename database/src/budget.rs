@@ -0,0 +1,70 @@
+use rusqlite::{Connection, Result, Row};
+use openai::types::*;
+
+use crate::Database;
+
+impl Database {
+	fn read_budget(row: &Row) -> Result<Budget> {
+		Ok(Budget {
+			id: row.get(0)?,
+			key: row.get(1)?,
+			conversation_id: row.get(2)?,
+			period: row.get(3)?,
+			unit: row.get(4)?,
+			amount: row.get(5)?
+		})
+	}
+
+	/// Sets the budget of profile `key` or of conversation `conversation_id`, replacing the one with the same period and unit.
+	pub fn set_budget(conn: &Connection, key: Option<&str>, conversation_id: Option<u32>, period: &str, unit: &str, amount: f64) -> Result<u32> {
+		let tx = conn.unchecked_transaction()?;
+		let sql = "
+			DELETE FROM budget
+			WHERE key IS ?1 AND conversation_id IS ?2 AND period = ?3 AND unit = ?4;
+		";
+		tx.execute(sql, rusqlite::params![key, conversation_id, period, unit])?;
+		let sql = "
+			INSERT INTO budget (key, conversation_id, period, unit, amount) VALUES (?1, ?2, ?3, ?4, ?5);
+		";
+		tx.execute(sql, rusqlite::params![key, conversation_id, period, unit, amount])?;
+		let id = tx.last_insert_rowid() as u32;
+		tx.commit()?;
+		Ok(id)
+	}
+
+	pub fn remove_budget(conn: &Connection, id: u32) -> Result<usize> {
+		conn.execute("DELETE FROM budget WHERE id = ?;", [id])
+	}
+
+	pub fn get_budgets(conn: &Connection) -> Result<Vec<Budget>> {
+		let sql = "
+			SELECT id, key, conversation_id, period, unit, amount
+			FROM budget
+			ORDER BY conversation_id IS NOT NULL, conversation_id, key, period, unit;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let budgets = stmt
+			.query_map([], Database::read_budget)?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(budgets)
+	}
+
+	/// Returns the budgets that apply to a request of profile `key` in conversation `conversation_id`.
+	pub fn get_applicable_budgets(conn: &Connection, key: &str, conversation_id: Option<u32>) -> Result<Vec<Budget>> {
+		let sql = "
+			SELECT id, key, conversation_id, period, unit, amount
+			FROM budget
+			WHERE key = ?1 OR (?2 IS NOT NULL AND conversation_id = ?2)
+			ORDER BY id;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let budgets = stmt
+			.query_map(rusqlite::params![key, conversation_id], Database::read_budget)?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(budgets)
+	}
+}
@@ -0,0 +1,323 @@
+use rusqlite::{Connection, OptionalExtension, Result};
+use openai::types::*;
+
+use crate::attachment::DELETE_UNUSED_ATTACHMENTS;
+use crate::types::ConversationFilter;
+use crate::utils::parse_timestamp;
+use crate::Database;
+
+/// Tables whose rows belong to a conversation through `conversation_id`, deleted when it is purged.
+const CONVERSATION_TABLES: &[&str] = &["summary", "conversation_settings", "error", "pin", "budget", "draft"];
+
+/// Tables whose rows belong to a message through `message_id`, deleted when its conversation is purged.
+const MESSAGE_TABLES: &[&str] = &["message_attachment", "embedding", "message_revision", "message_file", "message_command", "message_patch"];
+
+impl Database {
+	/// Lists conversations, least recently updated first, optionally restricted to profile `key`.
+	pub fn get_conversations(conn: &Connection, key: Option<&str>, filter: ConversationFilter) -> Result<Vec<ConversationListing>> {
+		let condition = match filter {
+			ConversationFilter::Active => "a.archived_at IS NULL AND a.deleted_at IS NULL",
+			ConversationFilter::Archived => "a.archived_at IS NOT NULL AND a.deleted_at IS NULL",
+			ConversationFilter::Trash => "a.deleted_at IS NOT NULL",
+			ConversationFilter::All => "1",
+		};
+		let sql = format!("
+			SELECT
+				a.id AS ID,
+				decrypt(a.title) AS Title,
+				IFNULL(SUM(b.prompt_tokens) + SUM(b.completion_tokens), 0) AS TotalUsage,
+				IFNULL(MAX(b.updateat), a.updateat) AS LastUpdate,
+				a.key,
+				a.archived_at,
+				a.deleted_at,
+				decrypt(c.name) AS Topic
+			FROM conversation a
+			LEFT JOIN message b ON a.id = b.conversation_id
+			LEFT JOIN topic c ON a.topic = c.id
+			WHERE (?1 IS NULL OR a.key = ?1) AND {condition}
+			GROUP BY a.id
+			ORDER BY LastUpdate ASC;
+		");
+		let mut stmt = conn.prepare(&sql)?;
+
+		let conv = stmt
+			.query_map([key], |row| {
+				Ok(ConversationListing {
+					id: row.get(0)?,
+					title: row.get(1)?,
+					usage: row.get(2)?,
+					lastupdate: parse_timestamp(&row.get::<_, String>(3)?),
+					key: row.get(4)?,
+					topic: row.get(7)?,
+					archived_at: row.get::<_, Option<String>>(5)?.map(|time| parse_timestamp(&time)),
+					deleted_at: row.get::<_, Option<String>>(6)?.map(|time| parse_timestamp(&time))
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(conv)
+	}
+
+	pub fn conversation_exists(conn: &Connection, id: u32) -> Result<bool> {
+		conn.query_row("SELECT COUNT(*) FROM conversation WHERE id = ?;", [id], |row| row.get::<_, u32>(0)).map(|count| count > 0)
+	}
+
+	/// Files conversation `id` under the topic named `name`, creating the topic if needed, or removes it from its topic.
+	/// Topics no conversation uses any more are deleted. Returns the number of conversations changed.
+	pub fn set_conversation_topic(conn: &Connection, id: u32, name: Option<&str>) -> Result<usize> {
+		let tx = conn.unchecked_transaction()?;
+		let topic_id = match name {
+			Some(name) => {
+				let existing = conn
+					.prepare("SELECT id FROM topic WHERE decrypt(name) = ?;")?
+					.query_map([name], |row| row.get::<_, u32>(0))?
+					.next()
+					.transpose()?;
+				match existing {
+					Some(topic_id) => topic_id,
+					None => {
+						conn.execute("INSERT INTO topic (name) VALUES (encrypt(?));", [name])?;
+						conn.last_insert_rowid() as u32
+					}
+				}
+			},
+			None => 0,
+		};
+		let changed = conn.execute("UPDATE conversation SET topic = ? WHERE id = ?;", [topic_id, id])?;
+		conn.execute("DELETE FROM topic WHERE id NOT IN (SELECT topic FROM conversation);", [])?;
+		tx.commit()?;
+		Ok(changed)
+	}
+
+	/// Renames conversation `id`. Returns the number of conversations changed.
+	pub fn set_conversation_title(conn: &Connection, id: u32, title: &str) -> Result<usize> {
+		conn.execute("UPDATE conversation SET title = encrypt(?1) WHERE id = ?2;", rusqlite::params![title, id])
+	}
+
+	/// Archives or unarchives conversation `id`. Returns the number of conversations changed.
+	pub fn set_conversation_archived(conn: &Connection, id: u32, archived: bool) -> Result<usize> {
+		let sql = "
+			UPDATE conversation
+			SET archived_at = CASE WHEN ?2 THEN CURRENT_TIMESTAMP ELSE NULL END
+			WHERE id = ?1 AND (archived_at IS NULL) = ?2;
+		";
+		conn.execute(sql, rusqlite::params![id, archived])
+	}
+
+	/// Moves conversation `id` to the trash, or restores it. Returns the number of conversations changed.
+	pub fn set_conversation_deleted(conn: &Connection, id: u32, deleted: bool) -> Result<usize> {
+		let sql = "
+			UPDATE conversation
+			SET deleted_at = CASE WHEN ?2 THEN CURRENT_TIMESTAMP ELSE NULL END
+			WHERE id = ?1 AND (deleted_at IS NULL) = ?2;
+		";
+		conn.execute(sql, rusqlite::params![id, deleted])
+	}
+
+	/// Permanently deletes conversations in the trash together with everything stored for them: messages,
+	/// attachments no other message uses, summaries, embeddings, settings and error logs.
+	/// Only conversation `id` is purged if given, and only those trashed more than `older_than_days` ago if given.
+	/// Returns the number of conversations deleted.
+	pub fn purge_conversations(conn: &Connection, id: Option<u32>, older_than_days: Option<u32>) -> Result<usize> {
+		let tx = conn.unchecked_transaction()?;
+		tx.execute_batch("
+			CREATE TEMP TABLE IF NOT EXISTS purge_conversation (id INTEGER PRIMARY KEY);
+			DELETE FROM purge_conversation;
+		")?;
+		let purged = tx.execute("
+			INSERT INTO purge_conversation (id)
+			SELECT id FROM conversation
+			WHERE deleted_at IS NOT NULL
+			AND (?1 IS NULL OR id = ?1)
+			AND (?2 IS NULL OR deleted_at < datetime('now', '-' || ?2 || ' days'));
+		", rusqlite::params![id, older_than_days])?;
+
+		for table in MESSAGE_TABLES {
+			tx.execute(&format!("
+				DELETE FROM {table} WHERE message_id IN (
+					SELECT id FROM message WHERE conversation_id IN (SELECT id FROM purge_conversation)
+				);
+			"), [])?;
+		}
+		for table in ["error_attachment", "error_file"] {
+			tx.execute(&format!("
+				DELETE FROM {table} WHERE error_id IN (
+					SELECT id FROM error WHERE conversation_id IN (SELECT id FROM purge_conversation)
+				);
+			"), [])?;
+		}
+		for table in CONVERSATION_TABLES {
+			tx.execute(&format!("
+				DELETE FROM {table} WHERE conversation_id IN (SELECT id FROM purge_conversation);
+			"), [])?;
+		}
+		tx.execute_batch(&format!("
+			DELETE FROM message WHERE conversation_id IN (SELECT id FROM purge_conversation);
+			DELETE FROM conversation WHERE id IN (SELECT id FROM purge_conversation);
+			{DELETE_UNUSED_ATTACHMENTS}
+			DELETE FROM topic WHERE id NOT IN (SELECT topic FROM conversation);
+			DELETE FROM purge_conversation;
+		"))?;
+		tx.commit()?;
+		Ok(purged)
+	}
+
+	/// Returns the active conversations of profile `key` set to `model` whose last message has `role` and, apart
+	/// from surrounding whitespace, `content`, most recently updated first and at most `limit` of them.
+	pub fn get_conversations_ending_with(conn: &Connection, key: &str, model: &str, role: &str, content: &str, limit: u32) -> Result<Vec<u32>> {
+		let sql = "
+			SELECT a.id
+			FROM conversation a
+			INNER JOIN conversation_settings c ON c.conversation_id = a.id
+			INNER JOIN message b ON b.id = (SELECT id FROM message WHERE conversation_id = a.id ORDER BY seq DESC, id DESC LIMIT 1)
+			WHERE a.key = ?1 AND a.archived_at IS NULL AND a.deleted_at IS NULL AND c.model = ?2 AND b.role = ?3
+			AND trim(CASE WHEN b.escaped = 1 THEN replace(decrypt(b.content), '\\\"', '\"') ELSE decrypt(b.content) END, char(32, 9, 10, 13)) = ?4
+			ORDER BY b.updateat DESC, b.id DESC
+			LIMIT ?5;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let conversations = stmt
+			.query_map(rusqlite::params![key, model, role, content.trim(), limit], |row| row.get(0))?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(conversations)
+	}
+
+	pub fn get_conversation_settings(conn: &Connection, id: u32) -> Result<Option<ConversationSettings>> {
+		let sql = "
+			SELECT model, params, context_strategy, max_token, max_dialog, decrypt(system_prompt), persona
+			FROM conversation_settings
+			WHERE conversation_id = ?;
+		";
+		conn.query_row(sql, [id], |row| {
+			let params: Option<String> = row.get(1)?;
+			Ok(ConversationSettings {
+				model: row.get(0)?,
+				params: params
+					.and_then(|params| serde_json::from_str(&params).ok())
+					.unwrap_or_default(),
+				context_strategy: row.get(2)?,
+				max_token: row.get(3)?,
+				max_dialog: row.get(4)?,
+				system_prompt: row.get(5)?,
+				persona: row.get(6)?
+			})
+		}).optional()
+	}
+
+	pub fn set_conversation_settings(conn: &Connection, id: u32, settings: &ConversationSettings) -> Result<usize> {
+		let sql = "
+			INSERT OR REPLACE INTO conversation_settings
+				(conversation_id, model, params, context_strategy, max_token, max_dialog, system_prompt, persona, updateat)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6, encrypt(?7), ?8, CURRENT_TIMESTAMP);
+		";
+		conn.execute(sql, rusqlite::params![
+			id,
+			settings.model,
+			serde_json::to_string(&settings.params).unwrap(),
+			settings.context_strategy,
+			settings.max_token,
+			settings.max_dialog,
+			settings.system_prompt,
+			settings.persona
+		])
+	}
+
+	pub fn set_system_prompt(conn: &Connection, id: u32, system_prompt: Option<&str>) -> Result<usize> {
+		let sql = "
+			INSERT INTO conversation_settings (conversation_id, system_prompt) VALUES (?2, encrypt(?1))
+			ON CONFLICT (conversation_id) DO UPDATE SET system_prompt = excluded.system_prompt, updateat = CURRENT_TIMESTAMP;
+		";
+		conn.execute(sql, rusqlite::params![system_prompt, id])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_support::*;
+
+	fn count(conn: &Connection, table: &str) -> u32 {
+		conn.query_row(&format!("SELECT COUNT(*) FROM {table};"), [], |row| row.get(0)).unwrap()
+	}
+
+	/// A conversation with a message that has something stored in every table a purge cleans up.
+	fn filled_conversation(conn: &Connection, title: &str, attachment: &[u8]) -> u32 {
+		let id = Database::add_conversation(conn, title, "key").unwrap();
+		let message_id = Database::add_client_message(conn, id, "hello").unwrap();
+		Database::add_server_message(conn, id, &reply("hi"), 10).unwrap();
+		let attachment = Attachment { name: "a.txt".into(), mime: "text/plain".into(), data: attachment.to_vec() };
+		Database::add_attachment(conn, message_id, 0, &attachment).unwrap();
+		Database::add_embedding(conn, message_id, "small", &[1.0]).unwrap();
+		Database::add_summary(conn, id, 1, 2, &reply("summary")).unwrap();
+		Database::add_pin(conn, id, message_id).unwrap();
+		Database::save_draft(conn, id, "draft").unwrap();
+		let context = [Message { role: MessageRole::User, content: MessageContent::Text("hello".into()) }];
+		let error_id = Database::add_error_log(conn, "key", "gpt-4", Some(id), &context, "timed out", None).unwrap();
+		Database::add_error_attachments(conn, error_id, &[attachment]).unwrap();
+		id
+	}
+
+	#[test]
+	fn finds_conversations_by_their_last_message() {
+		let conn = test_connection();
+		let settings = ConversationSettings { model: Some("gpt-4o".into()), ..Default::default() };
+		let mut ids = vec![];
+		for (title, last) in [("older", "hi"), ("newer", "hi"), ("asked", "hello"), ("other model", "hi")] {
+			let id = Database::add_conversation(&conn, title, "key").unwrap();
+			Database::set_conversation_settings(&conn, id, &settings).unwrap();
+			Database::add_client_message(&conn, id, "hello").unwrap();
+			if last == "hi" {
+				Database::add_server_message(&conn, id, &reply(" hi\n"), 10).unwrap();
+			}
+			ids.push(id);
+		}
+		Database::set_conversation_settings(&conn, ids[3], &ConversationSettings { model: Some("gpt-4".into()), ..Default::default() }).unwrap();
+
+		assert_eq!(Database::get_conversations_ending_with(&conn, "key", "gpt-4o", "assistant", "hi", 5).unwrap(), [ids[1], ids[0]]);
+		assert_eq!(Database::get_conversations_ending_with(&conn, "key", "gpt-4o", "assistant", "hi", 1).unwrap(), [ids[1]]);
+		assert_eq!(Database::get_conversations_ending_with(&conn, "key", "gpt-4o", "user", "hello", 5).unwrap(), [ids[2]]);
+		assert!(Database::get_conversations_ending_with(&conn, "key", "gpt-4o", "user", "hi", 5).unwrap().is_empty());
+		assert!(Database::get_conversations_ending_with(&conn, "other", "gpt-4o", "assistant", "hi", 5).unwrap().is_empty());
+
+		Database::set_conversation_deleted(&conn, ids[1], true).unwrap();
+		assert_eq!(Database::get_conversations_ending_with(&conn, "key", "gpt-4o", "assistant", "hi", 5).unwrap(), [ids[0]]);
+	}
+
+	#[test]
+	fn purges_everything_stored_for_a_trashed_conversation() {
+		let conn = test_connection();
+		let kept = filled_conversation(&conn, "kept", b"shared");
+		let trashed = filled_conversation(&conn, "trashed", b"shared");
+		Database::add_attachment(&conn, Database::add_client_message(&conn, trashed, "more").unwrap(), 0, &Attachment {
+			name: "b.txt".into(), mime: "text/plain".into(), data: b"only here".to_vec()
+		}).unwrap();
+
+		assert_eq!(Database::purge_conversations(&conn, None, None).unwrap(), 0);
+		Database::set_conversation_deleted(&conn, trashed, true).unwrap();
+		assert_eq!(Database::purge_conversations(&conn, None, Some(1)).unwrap(), 0);
+		assert_eq!(Database::purge_conversations(&conn, None, None).unwrap(), 1);
+
+		let tables = ["conversation", "message", "message_attachment", "attachment", "embedding", "summary", "pin", "draft", "error", "error_attachment"];
+		let counts: Vec<u32> = tables.iter().map(|table| count(&conn, table)).collect();
+		assert_eq!(counts, [1, 2, 1, 1, 1, 1, 1, 1, 1, 1]);
+		assert_eq!(Database::get_all_messages_in_conversation(&conn, kept).unwrap()[0].attachments[0].data, b"shared");
+	}
+
+	#[test]
+	fn sets_the_system_prompt_and_keeps_the_other_settings() {
+		let conn = test_connection();
+		let id = Database::add_conversation(&conn, "title", "key").unwrap();
+		let system_prompt = |conn: &Connection| Database::get_conversation_settings(conn, id).unwrap().and_then(|settings| settings.system_prompt);
+		assert_eq!(system_prompt(&conn), None);
+
+		let settings = ConversationSettings { model: Some("gpt-4o".into()), ..Default::default() };
+		Database::set_conversation_settings(&conn, id, &settings).unwrap();
+		Database::set_system_prompt(&conn, id, Some("Answer in French.")).unwrap();
+		assert_eq!(system_prompt(&conn).as_deref(), Some("Answer in French."));
+		Database::set_system_prompt(&conn, id, None).unwrap();
+		let stored = Database::get_conversation_settings(&conn, id).unwrap().unwrap();
+		assert_eq!((stored.model.as_deref(), stored.system_prompt), (Some("gpt-4o"), None));
+	}
+}
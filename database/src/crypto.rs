@@ -0,0 +1,262 @@
+use std::{error::Error, fmt::Display, sync::Arc};
+
+use aes_gcm::{aead::{Aead, KeyInit, OsRng, Payload}, AeadCore, Aes256Gcm, Key, Nonce};
+use argon2::{Argon2, Params};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rand::RngCore;
+use rusqlite::{functions::FunctionFlags, types::{Value, ValueRef}, Connection, Result};
+
+/// First byte of every encrypted value. Ciphertext is always stored as a BLOB, so a TEXT value is plaintext
+/// whatever it contains.
+const CIPHERTEXT_VERSION: u8 = 1;
+
+/// Second byte of an encrypted value, recording whether the plaintext was TEXT or a BLOB.
+const KIND_TEXT: u8 = 0;
+const KIND_BLOB: u8 = 1;
+
+/// Columns that hold conversation content and are encrypted when the database is locked with a passphrase.
+pub const ENCRYPTED_COLUMNS: &[(&str, &str)] = &[
+	("conversation", "title"),
+	("message", "content"),
+	("error", "context"),
+	("error", "prompt"),
+	("attachment", "data"),
+	("message_attachment", "name"),
+	("error_attachment", "name"),
+	("summary", "content"),
+	("embedding", "vector"),
+	("conversation_settings", "system_prompt"),
+	("message_revision", "content"),
+	("topic", "name"),
+	("draft", "content"),
+	("message_file", "path"),
+	("error_file", "path"),
+	("message_command", "command"),
+	("message_patch", "path"),
+];
+
+const HEADER_SIZE: usize = 2;
+const NONCE_SIZE: usize = 12;
+
+#[derive(Debug)]
+pub struct CryptoError {
+	message: String
+}
+
+impl CryptoError {
+	pub fn new(msg: &str) -> Self {
+		Self { message: msg.into() }
+	}
+}
+
+impl Display for CryptoError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Encryption error: {}", self.message)
+	}
+}
+
+impl Error for CryptoError {}
+
+impl From<CryptoError> for rusqlite::Error {
+	fn from(value: CryptoError) -> Self {
+		rusqlite::Error::UserFunctionError(Box::new(value))
+	}
+}
+
+/// Argon2id cost parameters used to derive the AES-256 key from a passphrase.
+#[derive(Clone, Copy)]
+pub struct KdfParams {
+	pub memory_cost: u32,
+	pub time_cost: u32,
+	pub parallelism: u32
+}
+
+impl Default for KdfParams {
+	fn default() -> Self {
+		Self {
+			memory_cost: Params::DEFAULT_M_COST,
+			time_cost: Params::DEFAULT_T_COST,
+			parallelism: Params::DEFAULT_P_COST
+		}
+	}
+}
+
+pub struct Cipher {
+	cipher: Aes256Gcm
+}
+
+impl Cipher {
+	pub fn derive(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<Self, CryptoError> {
+		let params = Params::new(params.memory_cost, params.time_cost, params.parallelism, Some(32))
+			.map_err(|err| CryptoError::new(&err.to_string()))?;
+		let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+		let mut key = [0u8; 32];
+		argon2
+			.hash_password_into(passphrase.as_bytes(), salt, &mut key)
+			.map_err(|err| CryptoError::new(&err.to_string()))?;
+		Ok(Self { cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)) })
+	}
+
+	pub fn generate_salt() -> Vec<u8> {
+		let mut salt = vec![0u8; 16];
+		OsRng.fill_bytes(&mut salt);
+		salt
+	}
+
+	/// Encrypts `plain` into the version byte and `kind`, both authenticated with the content, the nonce
+	/// and the ciphertext.
+	fn seal(&self, kind: u8, plain: &[u8]) -> Vec<u8> {
+		let header = [CIPHERTEXT_VERSION, kind];
+		let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+		let mut sealed = header.to_vec();
+		sealed.extend_from_slice(&nonce);
+		sealed.extend(self.cipher.encrypt(&nonce, Payload { msg: plain, aad: &header }).expect("AES-GCM encryption failed"));
+		sealed
+	}
+
+	fn open(&self, sealed: &[u8]) -> Result<Value, CryptoError> {
+		if sealed.len() < HEADER_SIZE + NONCE_SIZE || sealed[0] != CIPHERTEXT_VERSION {
+			return Err(CryptoError::new("value is not ciphertext of a known version"));
+		}
+		let (header, rest) = sealed.split_at(HEADER_SIZE);
+		let (nonce, data) = rest.split_at(NONCE_SIZE);
+		let plain = self.cipher
+			.decrypt(Nonce::from_slice(nonce), Payload { msg: data, aad: header })
+			.map_err(|_| CryptoError::new("wrong passphrase or corrupted data"))?;
+		match header[1] {
+			KIND_TEXT => String::from_utf8(plain).map(Value::Text).map_err(|err| CryptoError::new(&err.to_string())),
+			KIND_BLOB => Ok(Value::Blob(plain)),
+			_ => Err(CryptoError::new("unknown kind of encrypted value"))
+		}
+	}
+
+	/// Encrypts TEXT and BLOB values into a BLOB. Other values, such as NULL, are returned as they are.
+	pub fn encrypt_value(&self, value: ValueRef) -> Value {
+		match value {
+			ValueRef::Text(text) => Value::Blob(self.seal(KIND_TEXT, text)),
+			ValueRef::Blob(blob) => Value::Blob(self.seal(KIND_BLOB, blob)),
+			value => value.into()
+		}
+	}
+
+	/// Decrypts a value written by `encrypt_value`. Only BLOBs can be ciphertext; other values are returned as they are.
+	pub fn decrypt_value(&self, value: ValueRef) -> Result<Value, CryptoError> {
+		match value {
+			ValueRef::Blob(blob) => self.open(blob),
+			value => Ok(value.into())
+		}
+	}
+
+	/// Encrypts `plain` into Base64 text, for values kept outside the encrypted columns.
+	pub fn encrypt_text(&self, plain: &str) -> String {
+		BASE64.encode(self.seal(KIND_TEXT, plain.as_bytes()))
+	}
+
+	pub fn decrypt_text(&self, encoded: &str) -> Result<String, CryptoError> {
+		let sealed = BASE64.decode(encoded).map_err(|err| CryptoError::new(&err.to_string()))?;
+		match self.open(&sealed)? {
+			Value::Text(text) => Ok(text),
+			_ => Err(CryptoError::new("encrypted value is not text"))
+		}
+	}
+}
+
+/// Registers the `encrypt()` and `decrypt()` SQL functions used by every query touching an encrypted column.
+/// Without a cipher both functions pass values through unchanged.
+pub fn register_functions(conn: &Connection, encryptor: Option<Arc<Cipher>>, decryptor: Option<Arc<Cipher>>) -> Result<()> {
+	conn.create_scalar_function("encrypt", 1, FunctionFlags::SQLITE_UTF8, move |ctx| {
+		let value = ctx.get_raw(0);
+		Ok(match &encryptor {
+			Some(cipher) => cipher.encrypt_value(value),
+			None => value.into()
+		})
+	})?;
+	conn.create_scalar_function("decrypt", 1, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, move |ctx| {
+		let value = ctx.get_raw(0);
+		Ok(match &decryptor {
+			Some(cipher) => cipher.decrypt_value(value)?,
+			None => value.into()
+		})
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_cipher(passphrase: &str) -> Arc<Cipher> {
+		let params = KdfParams { memory_cost: 8, time_cost: 1, parallelism: 1 };
+		Arc::new(Cipher::derive(passphrase, b"0123456789abcdef", params).unwrap())
+	}
+
+	fn test_connection(cipher: Option<Arc<Cipher>>) -> Connection {
+		let conn = Connection::open_in_memory().unwrap();
+		register_functions(&conn, cipher.clone(), cipher).unwrap();
+		conn.execute("CREATE TABLE item (value);", []).unwrap();
+		conn
+	}
+
+	#[test]
+	fn text_round_trips_as_blob() {
+		let conn = test_connection(Some(test_cipher("secret")));
+		conn.execute("INSERT INTO item (value) VALUES (encrypt(?));", ["hello"]).unwrap();
+
+		let raw: Value = conn.query_row("SELECT value FROM item;", [], |row| row.get(0)).unwrap();
+		assert!(matches!(raw, Value::Blob(_)));
+		let plain: String = conn.query_row("SELECT decrypt(value) FROM item;", [], |row| row.get(0)).unwrap();
+		assert_eq!(plain, "hello");
+	}
+
+	#[test]
+	fn text_that_looks_encrypted_is_still_encrypted() {
+		let conn = test_connection(Some(test_cipher("secret")));
+		let text = "enc:v1:AAAA written by the user";
+		conn.execute("INSERT INTO item (value) VALUES (encrypt(?));", [text]).unwrap();
+
+		let raw: Value = conn.query_row("SELECT value FROM item;", [], |row| row.get(0)).unwrap();
+		assert!(matches!(raw, Value::Blob(_)));
+		let plain: String = conn.query_row("SELECT decrypt(value) FROM item;", [], |row| row.get(0)).unwrap();
+		assert_eq!(plain, text);
+	}
+
+	#[test]
+	fn blob_and_null_round_trip() {
+		let conn = test_connection(Some(test_cipher("secret")));
+		let blob = vec![CIPHERTEXT_VERSION, KIND_TEXT, 0, 255];
+		conn.execute("INSERT INTO item (value) VALUES (encrypt(?));", [&blob]).unwrap();
+		conn.execute("INSERT INTO item (value) VALUES (encrypt(NULL));", []).unwrap();
+
+		let values = conn
+			.prepare("SELECT decrypt(value) FROM item ORDER BY rowid;").unwrap()
+			.query_map([], |row| row.get::<_, Value>(0)).unwrap()
+			.collect::<Result<Vec<_>>>().unwrap();
+		assert_eq!(values, vec![Value::Blob(blob), Value::Null]);
+	}
+
+	#[test]
+	fn plaintext_is_read_unchanged() {
+		let conn = test_connection(None);
+		conn.execute("INSERT INTO item (value) VALUES (encrypt(?));", ["enc:v1:plain"]).unwrap();
+		register_functions(&conn, None, Some(test_cipher("secret"))).unwrap();
+
+		let plain: String = conn.query_row("SELECT decrypt(value) FROM item;", [], |row| row.get(0)).unwrap();
+		assert_eq!(plain, "enc:v1:plain");
+	}
+
+	#[test]
+	fn wrong_passphrase_fails() {
+		let conn = test_connection(Some(test_cipher("secret")));
+		conn.execute("INSERT INTO item (value) VALUES (encrypt(?));", ["hello"]).unwrap();
+		register_functions(&conn, None, Some(test_cipher("other"))).unwrap();
+
+		assert!(conn.query_row("SELECT decrypt(value) FROM item;", [], |row| row.get::<_, String>(0)).is_err());
+	}
+
+	#[test]
+	fn text_encoding_round_trips() {
+		let cipher = test_cipher("secret");
+		let encoded = cipher.encrypt_text("verifier");
+		assert_eq!(cipher.decrypt_text(&encoded).unwrap(), "verifier");
+		assert!(test_cipher("other").decrypt_text(&encoded).is_err());
+	}
+}
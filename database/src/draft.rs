@@ -0,0 +1,28 @@
+use rusqlite::{Connection, OptionalExtension, Result};
+
+use crate::Database;
+
+impl Database {
+	/// Keeps `content` as the unsent prompt of conversation `id`, replacing any previous draft.
+	pub fn save_draft(conn: &Connection, id: u32, content: &str) -> Result<usize> {
+		let sql = "
+			INSERT INTO draft (conversation_id, content) VALUES (?2, encrypt(?1))
+			ON CONFLICT (conversation_id) DO UPDATE SET content = excluded.content, updateat = CURRENT_TIMESTAMP;
+		";
+		conn.execute(sql, rusqlite::params![content, id])
+	}
+
+	pub fn get_draft(conn: &Connection, id: u32) -> Result<Option<String>> {
+		let sql = "
+			SELECT decrypt(content) FROM draft WHERE conversation_id = ?;
+		";
+		conn.query_row(sql, [id], |row| row.get(0)).optional()
+	}
+
+	pub fn delete_draft(conn: &Connection, id: u32) -> Result<usize> {
+		let sql = "
+			DELETE FROM draft WHERE conversation_id = ?;
+		";
+		conn.execute(sql, [id])
+	}
+}
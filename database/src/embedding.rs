@@ -0,0 +1,118 @@
+use rusqlite::{Connection, Result};
+use openai::types::*;
+
+use crate::Database;
+
+fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+	vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn blob_to_vector(blob: &[u8]) -> Vec<f32> {
+	blob.chunks_exact(4)
+		.map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+		.collect()
+}
+
+impl Database {
+	/// Returns up to `limit` messages of profile `key` that have no embedding from `model` yet, as (ID, content) pairs.
+	/// Conversations in the trash are skipped; archived ones are still recalled.
+	pub fn get_messages_without_embedding(conn: &Connection, key: &str, model: &str, limit: u32) -> Result<Vec<(u32, String)>> {
+		let sql = "
+			SELECT b.id, CASE WHEN b.escaped = 1 THEN replace(decrypt(b.content), '\\\"', '\"') ELSE decrypt(b.content) END
+			FROM message b
+			INNER JOIN conversation a ON a.id = b.conversation_id
+			LEFT JOIN embedding c ON c.message_id = b.id AND c.model = ?2
+			WHERE a.key = ?1 AND a.deleted_at IS NULL AND c.message_id IS NULL AND b.role != 'system'
+			ORDER BY b.id ASC
+			LIMIT ?3;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let messages = stmt
+			.query_map(rusqlite::params![key, model, limit], |row| {
+				Ok((row.get(0)?, row.get::<_, Option<String>>(1)?.unwrap_or_default()))
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(messages)
+	}
+
+	pub fn add_embedding(conn: &Connection, message_id: u32, model: &str, vector: &[f32]) -> Result<usize> {
+		let sql = "
+			INSERT OR REPLACE INTO embedding (message_id, model, dimensions, vector) VALUES (?, ?, ?, encrypt(?));
+		";
+		conn.execute(sql, rusqlite::params![message_id, model, vector.len(), vector_to_blob(vector)])
+	}
+
+	/// Returns every message of profile `key` embedded with `model` outside the trash, optionally skipping one conversation.
+	pub fn get_embedded_messages(conn: &Connection, key: &str, model: &str, exclude_conversation: Option<u32>) -> Result<Vec<EmbeddedMessage>> {
+		let sql = "
+			SELECT b.id, b.conversation_id, decrypt(a.title), b.role, CASE WHEN b.escaped = 1 THEN replace(decrypt(b.content), '\\\"', '\"') ELSE decrypt(b.content) END, decrypt(c.vector)
+			FROM embedding c
+			INNER JOIN message b ON b.id = c.message_id
+			INNER JOIN conversation a ON a.id = b.conversation_id
+			WHERE a.key = ?1 AND a.deleted_at IS NULL AND c.model = ?2 AND (?3 IS NULL OR b.conversation_id != ?3);
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let messages = stmt
+			.query_map(rusqlite::params![key, model, exclude_conversation], |row| {
+				Ok(EmbeddedMessage {
+					message_id: row.get(0)?,
+					conversation_id: row.get(1)?,
+					title: row.get(2)?,
+					role: row.get(3)?,
+					content: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+					vector: blob_to_vector(&row.get::<_, Vec<u8>>(5)?)
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(messages)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::test_support::*;
+	use crate::Database;
+
+	#[test]
+	fn keeps_one_vector_per_model() {
+		let conn = test_connection();
+		let id = Database::add_conversation(&conn, "title", "key").unwrap();
+		let message_id = Database::add_client_message(&conn, id, "hello").unwrap();
+
+		Database::add_embedding(&conn, message_id, "small", &[1.0, 0.0]).unwrap();
+		Database::add_embedding(&conn, message_id, "large", &[0.0, 1.0, 0.0]).unwrap();
+		Database::add_embedding(&conn, message_id, "small", &[0.5, 0.5]).unwrap();
+
+		let small = Database::get_embedded_messages(&conn, "key", "small", None).unwrap();
+		assert_eq!(small.len(), 1);
+		assert_eq!(small[0].vector, vec![0.5, 0.5]);
+		let large = Database::get_embedded_messages(&conn, "key", "large", None).unwrap();
+		assert_eq!(large[0].vector, vec![0.0, 1.0, 0.0]);
+		assert!(Database::get_messages_without_embedding(&conn, "key", "large", 10).unwrap().is_empty());
+	}
+
+	#[test]
+	fn recalls_archived_but_not_trashed_conversations() {
+		let conn = test_connection();
+		let archived = Database::add_conversation(&conn, "archived", "key").unwrap();
+		let trashed = Database::add_conversation(&conn, "trashed", "key").unwrap();
+		let archived_message = Database::add_client_message(&conn, archived, "kept").unwrap();
+		let trashed_message = Database::add_client_message(&conn, trashed, "secret").unwrap();
+		Database::add_client_message(&conn, trashed, "not embedded yet").unwrap();
+		Database::add_embedding(&conn, archived_message, "small", &[1.0]).unwrap();
+		Database::add_embedding(&conn, trashed_message, "small", &[1.0]).unwrap();
+		Database::set_conversation_archived(&conn, archived, true).unwrap();
+		Database::set_conversation_deleted(&conn, trashed, true).unwrap();
+
+		let recalled = Database::get_embedded_messages(&conn, "key", "small", None).unwrap();
+		assert_eq!(recalled.iter().map(|msg| msg.message_id).collect::<Vec<_>>(), [archived_message]);
+		assert!(Database::get_messages_without_embedding(&conn, "key", "small", 10).unwrap().is_empty());
+
+		Database::set_conversation_deleted(&conn, trashed, false).unwrap();
+		assert_eq!(Database::get_embedded_messages(&conn, "key", "small", None).unwrap().len(), 2);
+	}
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rusqlite::{Connection, OptionalExtension, Result};
+
+use crate::crypto::*;
+use crate::Database;
+
+static VERIFIER: &str = "ai-database-verifier";
+
+struct EncryptionConfig {
+	salt: Vec<u8>,
+	params: KdfParams,
+	verifier: String
+}
+
+impl Database {
+	fn get_encryption_config(conn: &Connection) -> Result<Option<EncryptionConfig>> {
+		let sql = "
+			SELECT salt, memory_cost, time_cost, parallelism, verifier FROM encryption WHERE id = 1;
+		";
+		conn.query_row(sql, [], |row| {
+			Ok(EncryptionConfig {
+				salt: BASE64.decode(row.get::<_, String>(0)?).map_err(|err| CryptoError::new(&err.to_string()))?,
+				params: KdfParams {
+					memory_cost: row.get(1)?,
+					time_cost: row.get(2)?,
+					parallelism: row.get(3)?
+				},
+				verifier: row.get(4)?
+			})
+		})
+		.optional()
+	}
+
+	fn set_encryption_config(conn: &Connection, salt: &[u8], params: KdfParams, cipher: &Cipher) -> Result<usize> {
+		let sql = "
+			INSERT OR REPLACE INTO encryption (id, salt, memory_cost, time_cost, parallelism, verifier) VALUES (
+				1, ?, ?, ?, ?, ?
+			);
+		";
+		conn.execute(sql, rusqlite::params![
+			BASE64.encode(salt),
+			params.memory_cost,
+			params.time_cost,
+			params.parallelism,
+			cipher.encrypt_text(VERIFIER)
+		])
+	}
+
+	fn derive_cipher(config: &EncryptionConfig, passphrase: &str) -> Result<Option<Cipher>> {
+		let cipher = Cipher::derive(passphrase, &config.salt, config.params)?;
+		match cipher.decrypt_text(&config.verifier) {
+			Ok(verifier) if verifier == VERIFIER => Ok(Some(cipher)),
+			_ => Ok(None)
+		}
+	}
+
+	pub fn is_encrypted(conn: &Connection) -> Result<bool> {
+		Ok(Database::get_encryption_config(conn)?.is_some())
+	}
+
+	/// Installs the key derived from `passphrase` on this connection. Returns `false` if the passphrase is wrong.
+	pub fn unlock(conn: &Connection, passphrase: &str) -> Result<bool> {
+		let Some(config) = Database::get_encryption_config(conn)? else {
+			return Ok(true);
+		};
+		let Some(cipher) = Database::derive_cipher(&config, passphrase)? else {
+			return Ok(false);
+		};
+		let cipher = Arc::new(cipher);
+		register_functions(conn, Some(cipher.clone()), Some(cipher))?;
+		Ok(true)
+	}
+
+	/// Encrypts every plaintext value in place and leaves the connection unlocked with the new key.
+	pub fn encrypt_database(conn: &Connection, passphrase: &str) -> Result<()> {
+		if Database::is_encrypted(conn)? {
+			return Err(CryptoError::new("the database is already encrypted").into());
+		}
+
+		let salt = Cipher::generate_salt();
+		let params = KdfParams::default();
+		let cipher = Arc::new(Cipher::derive(passphrase, &salt, params)?);
+
+		register_functions(conn, Some(cipher.clone()), Some(cipher.clone()))?;
+		let result = (|| {
+			let tx = conn.unchecked_transaction()?;
+			for (table, column) in ENCRYPTED_COLUMNS {
+				let sql = format!("UPDATE {table} SET {column} = encrypt({column}) WHERE {column} IS NOT NULL;");
+				tx.execute(&sql, [])?;
+			}
+			Database::set_encryption_config(&tx, &salt, params, &cipher)?;
+			tx.commit()
+		})();
+		if result.is_err() {
+			register_functions(conn, None, None)?;
+		}
+		result
+	}
+
+	/// Re-encrypts every value under a new passphrase. Returns `false` if `old_passphrase` is wrong.
+	pub fn rotate_passphrase(conn: &Connection, old_passphrase: &str, new_passphrase: &str) -> Result<bool> {
+		let Some(config) = Database::get_encryption_config(conn)? else {
+			return Err(CryptoError::new("the database is not encrypted").into());
+		};
+		let Some(old_cipher) = Database::derive_cipher(&config, old_passphrase)? else {
+			return Ok(false);
+		};
+
+		let salt = Cipher::generate_salt();
+		let params = KdfParams::default();
+		let new_cipher = Arc::new(Cipher::derive(new_passphrase, &salt, params)?);
+
+		let old_cipher = Arc::new(old_cipher);
+		register_functions(conn, Some(new_cipher.clone()), Some(old_cipher.clone()))?;
+		let result = (|| {
+			let tx = conn.unchecked_transaction()?;
+			for (table, column) in ENCRYPTED_COLUMNS {
+				let sql = format!("UPDATE {table} SET {column} = encrypt(decrypt({column})) WHERE {column} IS NOT NULL;");
+				tx.execute(&sql, [])?;
+			}
+			Database::set_encryption_config(&tx, &salt, params, &new_cipher)?;
+			tx.commit()
+		})();
+		match result {
+			Ok(_) => register_functions(conn, Some(new_cipher.clone()), Some(new_cipher))?,
+			Err(err) => {
+				register_functions(conn, Some(old_cipher.clone()), Some(old_cipher))?;
+				return Err(err);
+			}
+		}
+		Ok(true)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rusqlite::types::Value;
+	use crate::test_support::*;
+
+	#[test]
+	fn encrypts_existing_rows_and_rotates() {
+		let conn = test_connection();
+		let id = Database::add_conversation(&conn, "enc:v1:not really encrypted", "key").unwrap();
+		Database::add_client_message(&conn, id, "enc:v1:hello").unwrap();
+
+		Database::encrypt_database(&conn, "first").unwrap();
+		let title: Value = conn.query_row("SELECT title FROM conversation;", [], |row| row.get(0)).unwrap();
+		assert!(matches!(title, Value::Blob(_)));
+		assert!(Database::rotate_passphrase(&conn, "first", "second").unwrap());
+		assert!(!Database::unlock(&conn, "first").unwrap());
+		assert!(Database::unlock(&conn, "second").unwrap());
+
+		let conversations = Database::get_all_conversations(&conn, "key").unwrap();
+		assert_eq!(conversations[0].title, "enc:v1:not really encrypted");
+		let messages = Database::get_all_messages_in_conversation(&conn, id).unwrap();
+		assert_eq!(messages[0].content, "enc:v1:hello");
+	}
+}
@@ -0,0 +1,142 @@
+use rusqlite::{Connection, OptionalExtension, Result, Row};
+use openai::types::*;
+
+use crate::attachment::DELETE_UNUSED_ATTACHMENTS;
+use crate::utils::parse_timestamp;
+use crate::Database;
+
+impl Database {
+	fn read_error_log(row: &Row) -> Result<ErrorLog> {
+		let context: Option<String> = row.get(5)?;
+		Ok(ErrorLog {
+			id: row.get(0)?,
+			key: row.get(1)?,
+			model: row.get(2)?,
+			conversation_id: row.get(3)?,
+			prompt: row.get(4)?,
+			context: context
+				.and_then(|context| serde_json::from_str(&context).ok())
+				.unwrap_or_default(),
+			error: row.get(6)?,
+			message: row.get(7)?,
+			code: row.get(8)?,
+			r#type: row.get(9)?,
+			param: row.get(10)?,
+			retried_at: row.get::<_, Option<String>>(11)?.map(|time| parse_timestamp(&time)),
+			updateat: parse_timestamp(&row.get::<_, String>(12)?)
+		})
+	}
+
+	pub fn get_error_summary(conn: &Connection) -> Result<Vec<ErrorSummary>> {
+		let sql = "
+			SELECT code, type, COUNT(*), MAX(updateat)
+			FROM error
+			GROUP BY code, type
+			ORDER BY COUNT(*) DESC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let summary = stmt
+			.query_map([], |row| {
+				Ok(ErrorSummary {
+					code: row.get(0)?,
+					r#type: row.get(1)?,
+					count: row.get(2)?,
+					lastupdate: parse_timestamp(&row.get::<_, String>(3)?)
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(summary)
+	}
+
+	/// Returns the most recent error logs first, optionally restricted to one error code.
+	pub fn get_error_logs(conn: &Connection, code: Option<&str>, limit: u32) -> Result<Vec<ErrorLog>> {
+		let sql = "
+			SELECT
+				id, key, model, conversation_id, decrypt(prompt), decrypt(context),
+				error, message, code, type, param, retried_at, updateat
+			FROM error
+			WHERE ?1 IS NULL OR code = ?1
+			ORDER BY updateat DESC, id DESC
+			LIMIT ?2;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let logs = stmt
+			.query_map(rusqlite::params![code, limit], Database::read_error_log)?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(logs)
+	}
+
+	pub fn get_error_log(conn: &Connection, id: u32) -> Result<Option<ErrorLog>> {
+		let sql = "
+			SELECT
+				id, key, model, conversation_id, decrypt(prompt), decrypt(context),
+				error, message, code, type, param, retried_at, updateat
+			FROM error
+			WHERE id = ?;
+		";
+		conn.query_row(sql, [id], Database::read_error_log).optional()
+	}
+
+	pub fn mark_error_retried(conn: &Connection, id: u32) -> Result<usize> {
+		let sql = "
+			UPDATE error SET retried_at = CURRENT_TIMESTAMP WHERE id = ?;
+		";
+		conn.execute(sql, [id])
+	}
+
+	/// Deletes error logs older than `older_than_days` (all of them if `None`).
+	/// With `retried_only`, only logs that were successfully retried are removed.
+	pub fn purge_error_logs(conn: &Connection, older_than_days: Option<u32>, retried_only: bool) -> Result<usize> {
+		let sql = "
+			DELETE FROM error
+			WHERE (?1 IS NULL OR updateat < datetime('now', '-' || ?1 || ' days'))
+			AND (?2 = 0 OR retried_at IS NOT NULL);
+		";
+		let tx = conn.unchecked_transaction()?;
+		let purged = tx.execute(sql, rusqlite::params![older_than_days, retried_only])?;
+		tx.execute_batch(&format!("
+			DELETE FROM error_attachment WHERE error_id NOT IN (SELECT id FROM error);
+			DELETE FROM error_file WHERE error_id NOT IN (SELECT id FROM error);
+			{DELETE_UNUSED_ATTACHMENTS}
+		"))?;
+		tx.commit()?;
+		Ok(purged)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use openai::types::*;
+
+	use crate::test_support::*;
+	use crate::Database;
+
+	fn failure(code: &str) -> OpenAIError {
+		serde_json::from_value(serde_json::json!({ "error": { "message": code, "type": "invalid_request_error", "param": null, "code": code } })).unwrap()
+	}
+
+	#[test]
+	fn lists_failures_by_code_and_purges_retried_ones() {
+		let conn = test_connection();
+		let context = [Message { role: MessageRole::User, content: MessageContent::Text("hello".into()) }];
+		for code in ["rate_limit", "context_length", "rate_limit"] {
+			Database::add_error_log(&conn, "key", "gpt-4", None, &context, code, Some(&failure(code))).unwrap();
+		}
+
+		let limited = Database::get_error_logs(&conn, Some("rate_limit"), 10).unwrap();
+		assert_eq!(limited.iter().map(|log| log.id).collect::<Vec<u32>>(), [3, 1]);
+		assert_eq!(limited[0].prompt.as_deref(), Some("hello"));
+		assert_eq!(Database::get_error_logs(&conn, None, 1).unwrap()[0].id, 3);
+		let summary = Database::get_error_summary(&conn).unwrap();
+		assert_eq!((summary[0].code.as_deref(), summary[0].count), (Some("rate_limit"), 2));
+
+		Database::mark_error_retried(&conn, 1).unwrap();
+		assert_eq!(Database::purge_error_logs(&conn, None, true).unwrap(), 1);
+		assert_eq!(Database::purge_error_logs(&conn, Some(1), false).unwrap(), 0);
+		assert_eq!(Database::get_error_logs(&conn, None, 10).unwrap().len(), 2);
+	}
+}
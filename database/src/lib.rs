@@ -3,11 +3,33 @@ use std::path::PathBuf;
 
 mod utils;
 mod types;
-pub use types::Schema;
+pub use types::{ConversationFilter, Schema};
 
 mod versions;
 pub use versions::*;
 
+mod crypto;
+mod encryption;
+mod usage;
+mod error_log;
+mod attachment;
+mod summary;
+mod embedding;
+mod backup;
+mod conversation;
+mod pin;
+mod revision;
+mod budget;
+mod draft;
+mod message_file;
+mod message_command;
+mod message_patch;
+
+#[cfg(test)]
+mod test_support;
+
 pub fn open_connection(db: &PathBuf) -> Connection {
-    return Connection::open(db).unwrap();
+    let conn = Connection::open(db).unwrap();
+    crypto::register_functions(&conn, None, None).unwrap();
+    conn
 }
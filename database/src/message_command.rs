@@ -0,0 +1,46 @@
+use rusqlite::{Connection, Result};
+use openai::types::*;
+
+use crate::Database;
+
+impl Database {
+	/// Records the commands whose output was sent in message `message_id`.
+	pub fn add_message_commands(conn: &Connection, message_id: u32, runs: &[CommandRun]) -> Result<()> {
+		let tx = conn.unchecked_transaction()?;
+		let sql = "
+			INSERT INTO message_command (message_id, command, exit_code, timed_out, duration_ms, omitted_lines)
+			VALUES (?, encrypt(?), ?, ?, ?, ?);
+		";
+		for run in runs {
+			tx.execute(sql, rusqlite::params![message_id, run.command, run.exit_code, run.timed_out, run.duration_ms, run.omitted_lines])?;
+		}
+		tx.commit()
+	}
+
+	/// Returns the commands run for the messages of conversation `id` with the IDs of the messages,
+	/// in conversation order.
+	pub fn get_message_commands_in_conversation(conn: &Connection, id: u32) -> Result<Vec<(u32, CommandRun)>> {
+		let sql = "
+			SELECT a.message_id, decrypt(a.command), a.exit_code, a.timed_out, a.duration_ms, a.omitted_lines
+			FROM message_command a
+			INNER JOIN message b ON a.message_id = b.id
+			WHERE b.conversation_id = ?
+			ORDER BY b.seq ASC, a.id ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let runs = stmt
+			.query_map([id], |row| {
+				Ok((row.get(0)?, CommandRun {
+					command: row.get(1)?,
+					exit_code: row.get(2)?,
+					timed_out: row.get(3)?,
+					duration_ms: row.get(4)?,
+					omitted_lines: row.get(5)?
+				}))
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(runs)
+	}
+}
@@ -0,0 +1,75 @@
+use rusqlite::{Connection, Result};
+use openai::types::*;
+
+use crate::Database;
+
+impl Database {
+	/// Records the files included in message `message_id`.
+	pub fn add_message_files(conn: &Connection, message_id: u32, files: &[IncludedFile]) -> Result<()> {
+		let tx = conn.unchecked_transaction()?;
+		let sql = "
+			INSERT INTO message_file (message_id, path, sha256, size) VALUES (?, encrypt(?), ?, ?);
+		";
+		for file in files {
+			tx.execute(sql, rusqlite::params![message_id, file.path, file.sha256, file.size])?;
+		}
+		tx.commit()
+	}
+
+	/// Returns the files included in the messages of conversation `id` with the IDs of the messages,
+	/// in conversation order.
+	pub fn get_message_files_in_conversation(conn: &Connection, id: u32) -> Result<Vec<(u32, IncludedFile)>> {
+		let sql = "
+			SELECT a.message_id, decrypt(a.path), a.sha256, a.size
+			FROM message_file a
+			INNER JOIN message b ON a.message_id = b.id
+			WHERE b.conversation_id = ?
+			ORDER BY b.seq ASC, a.id ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let files = stmt
+			.query_map([id], |row| {
+				Ok((row.get(0)?, IncludedFile {
+					path: row.get(1)?,
+					sha256: row.get(2)?,
+					size: row.get(3)?
+				}))
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(files)
+	}
+
+	/// Records the files included in the prompt that failed with error log `error_id`.
+	pub fn add_error_files(conn: &Connection, error_id: u32, files: &[IncludedFile]) -> Result<()> {
+		let tx = conn.unchecked_transaction()?;
+		let sql = "
+			INSERT INTO error_file (error_id, path, sha256, size) VALUES (?, encrypt(?), ?, ?);
+		";
+		for file in files {
+			tx.execute(sql, rusqlite::params![error_id, file.path, file.sha256, file.size])?;
+		}
+		tx.commit()
+	}
+
+	/// Returns the files included in the prompt that failed with error log `error_id`.
+	pub fn get_error_files(conn: &Connection, error_id: u32) -> Result<Vec<IncludedFile>> {
+		let sql = "
+			SELECT decrypt(path), sha256, size FROM error_file WHERE error_id = ? ORDER BY id ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let files = stmt
+			.query_map([error_id], |row| {
+				Ok(IncludedFile {
+					path: row.get(0)?,
+					sha256: row.get(1)?,
+					size: row.get(2)?
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(files)
+	}
+}
@@ -0,0 +1,27 @@
+use rusqlite::{Connection, Result};
+use openai::types::*;
+
+use crate::Database;
+
+impl Database {
+	/// Records the changes applied to local files from message `message_id`.
+	pub fn add_message_patches(conn: &Connection, message_id: u32, patches: &[AppliedPatch]) -> Result<()> {
+		let tx = conn.unchecked_transaction()?;
+		let sql = "
+			INSERT INTO message_patch (message_id, path, action, hunks, fuzzy_hunks, sha256_before, sha256_after)
+			VALUES (?, encrypt(?), ?, ?, ?, ?, ?);
+		";
+		for patch in patches {
+			tx.execute(sql, rusqlite::params![
+				message_id,
+				patch.path,
+				patch.action,
+				patch.hunks,
+				patch.fuzzy_hunks,
+				patch.sha256_before,
+				patch.sha256_after
+			])?;
+		}
+		tx.commit()
+	}
+}
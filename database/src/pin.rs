@@ -0,0 +1,38 @@
+use rusqlite::{Connection, Result};
+
+use crate::Database;
+
+impl Database {
+	/// Pins message `message_id` of conversation `conversation_id`. Returns 0 if it was already pinned.
+	pub fn add_pin(conn: &Connection, conversation_id: u32, message_id: u32) -> Result<usize> {
+		let sql = "
+			INSERT OR IGNORE INTO pin (message_id, conversation_id) VALUES (?, ?);
+		";
+		conn.execute(sql, [message_id, conversation_id])
+	}
+
+	pub fn remove_pin(conn: &Connection, conversation_id: u32, message_id: u32) -> Result<usize> {
+		let sql = "
+			DELETE FROM pin WHERE message_id = ? AND conversation_id = ?;
+		";
+		conn.execute(sql, [message_id, conversation_id])
+	}
+
+	/// Returns the IDs of the pinned messages of conversation `id`, in conversation order.
+	pub fn get_pinned_messages(conn: &Connection, id: u32) -> Result<Vec<u32>> {
+		let sql = "
+			SELECT a.message_id
+			FROM pin a
+			INNER JOIN message b ON a.message_id = b.id
+			WHERE a.conversation_id = ?
+			ORDER BY b.seq ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let pins = stmt
+			.query_map([id], |row| row.get(0))?
+			.collect::<Result<Vec<u32>>>()?;
+
+		Ok(pins)
+	}
+}
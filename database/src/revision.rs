@@ -0,0 +1,89 @@
+use rusqlite::{Connection, Result};
+use openai::types::*;
+
+use crate::utils::parse_timestamp;
+use crate::Database;
+
+impl Database {
+	/// Replaces the content of message `message_id`, keeping the previous content as a new revision.
+	/// The embedding of the message and the summaries covering it are dropped so that they are made again.
+	/// Returns the number of the kept revision.
+	pub fn edit_message(conn: &Connection, message_id: u32, content: &str) -> Result<u32> {
+		let tx = conn.unchecked_transaction()?;
+		let sql = "
+			INSERT INTO message_revision (message_id, revision, content)
+			SELECT
+				id,
+				(SELECT IFNULL(MAX(revision), 0) + 1 FROM message_revision WHERE message_id = ?1),
+				CASE WHEN escaped = 1 THEN encrypt(replace(decrypt(content), '\\\"', '\"')) ELSE content END
+			FROM message
+			WHERE id = ?1;
+		";
+		tx.execute(sql, [message_id])?;
+		let revision: u32 = tx.query_row("SELECT MAX(revision) FROM message_revision WHERE message_id = ?;", [message_id], |row| row.get(0))?;
+
+		let sql = "
+			UPDATE message SET content = encrypt(?2), escaped = 0 WHERE id = ?1;
+		";
+		tx.execute(sql, rusqlite::params![message_id, content])?;
+		tx.execute("DELETE FROM embedding WHERE message_id = ?;", [message_id])?;
+		let sql = "
+			DELETE FROM summary WHERE id IN (
+				SELECT a.id FROM summary a
+				INNER JOIN message b ON b.conversation_id = a.conversation_id
+				WHERE b.id = ?1 AND b.seq BETWEEN a.from_seq AND a.to_seq
+			);
+		";
+		tx.execute(sql, [message_id])?;
+		tx.commit()?;
+		Ok(revision)
+	}
+
+	/// Returns the earlier revisions of message `message_id`, oldest first.
+	pub fn get_message_revisions(conn: &Connection, message_id: u32) -> Result<Vec<MessageRevision>> {
+		let sql = "
+			SELECT message_id, revision, decrypt(content), updateat
+			FROM message_revision
+			WHERE message_id = ?
+			ORDER BY revision ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let revisions = stmt
+			.query_map([message_id], |row| {
+				Ok(MessageRevision {
+					message_id: row.get(0)?,
+					revision: row.get(1)?,
+					content: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+					updateat: parse_timestamp(&row.get::<_, String>(3)?)
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(revisions)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::test_support::*;
+	use crate::Database;
+
+	#[test]
+	fn editing_a_summarized_message_drops_its_summary() {
+		let conn = test_connection();
+		let id = Database::add_conversation(&conn, "title", "key").unwrap();
+		let first = Database::add_client_message(&conn, id, "first").unwrap();
+		Database::add_server_message(&conn, id, &reply("answer"), 0).unwrap();
+		let last = Database::add_client_message(&conn, id, "second").unwrap();
+		let history = Database::get_all_messages_in_conversation(&conn, id).unwrap();
+		Database::add_summary(&conn, id, history[0].seq, history[1].seq, &reply("summary")).unwrap();
+
+		Database::edit_message(&conn, last, "second, edited").unwrap();
+		assert!(Database::get_latest_summary(&conn, id).unwrap().is_some());
+
+		Database::edit_message(&conn, first, "first, edited").unwrap();
+		assert!(Database::get_latest_summary(&conn, id).unwrap().is_none());
+		assert_eq!(Database::get_message_revisions(&conn, first).unwrap()[0].content, "first");
+	}
+}
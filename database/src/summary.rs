@@ -0,0 +1,53 @@
+use rusqlite::{Connection, OptionalExtension, Result};
+use openai::types::*;
+
+use crate::utils::parse_timestamp;
+use crate::Database;
+
+impl Database {
+	pub fn add_summary(
+		conn: &Connection,
+		conversation_id: u32,
+		from_seq: u64,
+		to_seq: u64,
+		msg: &CompletionResponse
+	) -> Result<u32> {
+		let sql = "
+			INSERT INTO summary (conversation_id, from_seq, to_seq, content, model, prompt_tokens, completion_tokens) VALUES (
+				?, ?, ?, encrypt(?), ?, ?, ?
+			);
+		";
+		conn.execute(sql, rusqlite::params![
+			conversation_id,
+			from_seq,
+			to_seq,
+			msg.msg().trim(),
+			msg.model,
+			msg.usage.prompt_tokens,
+			msg.usage.completion_tokens
+		])?;
+		conn.query_row("SELECT last_insert_rowid();", [], |row| row.get(0))
+	}
+
+	/// Returns the summary covering the most messages of conversation `id`.
+	pub fn get_latest_summary(conn: &Connection, id: u32) -> Result<Option<Summary>> {
+		let sql = "
+			SELECT id, conversation_id, from_seq, to_seq, decrypt(content), updateat
+			FROM summary
+			WHERE conversation_id = ?
+			ORDER BY to_seq DESC, id DESC
+			LIMIT 1;
+		";
+		conn.query_row(sql, [id], |row| {
+			Ok(Summary {
+				id: row.get(0)?,
+				conversation_id: row.get(1)?,
+				from_seq: row.get(2)?,
+				to_seq: row.get(3)?,
+				content: row.get(4)?,
+				updateat: parse_timestamp(&row.get::<_, String>(5)?)
+			})
+		})
+		.optional()
+	}
+}
@@ -0,0 +1,27 @@
+//! Helpers shared by the unit tests of the database.
+
+use rusqlite::Connection;
+use openai::types::CompletionResponse;
+
+use crate::crypto::register_functions;
+use crate::{CurrentSchema, Schema};
+
+/// An unencrypted in-memory database with the current schema.
+pub fn test_connection() -> Connection {
+	let conn = Connection::open_in_memory().unwrap();
+	register_functions(&conn, None, None).unwrap();
+	CurrentSchema::init_current_schema(&conn).unwrap();
+	conn
+}
+
+/// A reply with `content`, as the API returns it.
+pub fn reply(content: &str) -> CompletionResponse {
+	serde_json::from_value(serde_json::json!({
+		"id": "test",
+		"object": "chat.completion",
+		"created": 0,
+		"model": "test-model",
+		"usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+		"choices": [{ "index": 0, "finish_reason": "stop", "message": { "role": "assistant", "content": content } }]
+	})).unwrap()
+}
@@ -8,3 +8,12 @@ pub trait Schema {
 	fn version() -> u64;
 	fn init_current_schema(conn: &Connection) -> Result<usize>;
 }
+
+/// Which conversations a listing includes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConversationFilter {
+	Active,
+	Archived,
+	Trash,
+	All
+}
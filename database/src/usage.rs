@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, Result};
+use openai::types::*;
+
+use crate::utils::parse_timestamp;
+use crate::Database;
+
+impl Database {
+	pub fn add_embedding_usage(conn: &Connection, key: &str, model: &str, prompt_tokens: u64) -> Result<usize> {
+		let sql = "
+			INSERT INTO embedding_usage (key, model, prompt_tokens) VALUES (?, ?, ?);
+		";
+		conn.execute(sql, rusqlite::params![key, model, prompt_tokens])
+	}
+
+	/// Returns one record per completed request, including summarization and embedding requests, oldest first.
+	pub fn get_usage_records(conn: &Connection) -> Result<Vec<UsageRecord>> {
+		Database::get_usage_records_since(conn, None)
+	}
+
+	/// Like `get_usage_records`, restricted to requests completed at or after `since`.
+	pub fn get_usage_records_since(conn: &Connection, since: Option<DateTime<Utc>>) -> Result<Vec<UsageRecord>> {
+		let sql = "
+			SELECT
+				b.conversation_id,
+				decrypt(a.title),
+				a.key,
+				b.model,
+				b.prompt_tokens,
+				b.completion_tokens,
+				b.latency_ms,
+				b.updateat
+			FROM message b
+			INNER JOIN conversation a ON a.id = b.conversation_id
+			WHERE b.role = 'assistant' AND (?1 IS NULL OR b.updateat >= ?1)
+			UNION ALL
+			SELECT
+				c.conversation_id,
+				decrypt(a.title),
+				a.key,
+				c.model,
+				c.prompt_tokens,
+				c.completion_tokens,
+				NULL,
+				c.updateat
+			FROM summary c
+			INNER JOIN conversation a ON a.id = c.conversation_id
+			WHERE ?1 IS NULL OR c.updateat >= ?1
+			UNION ALL
+			SELECT NULL, NULL, d.key, d.model, d.prompt_tokens, 0, NULL, d.updateat
+			FROM embedding_usage d
+			WHERE ?1 IS NULL OR d.updateat >= ?1
+			ORDER BY 8 ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let since = since.map(|since| since.format("%Y-%m-%d %H:%M:%S").to_string());
+		let records = stmt
+			.query_map([since], |row| {
+				Ok(UsageRecord {
+					conversation_id: row.get(0)?,
+					title: row.get(1)?,
+					key: row.get(2)?,
+					model: row.get(3)?,
+					prompt_tokens: row.get(4)?,
+					completion_tokens: row.get(5)?,
+					latency_ms: row.get(6)?,
+					updateat: parse_timestamp(&row.get::<_, String>(7)?)
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(records)
+	}
+
+	pub fn get_error_records(conn: &Connection) -> Result<Vec<ErrorRecord>> {
+		let sql = "
+			SELECT a.key, a.model, a.conversation_id, decrypt(b.title), a.updateat
+			FROM error a
+			LEFT JOIN conversation b ON b.id = a.conversation_id
+			ORDER BY a.updateat ASC;
+		";
+		let mut stmt = conn.prepare(sql)?;
+
+		let records = stmt
+			.query_map([], |row| {
+				Ok(ErrorRecord {
+					key: row.get(0)?,
+					model: row.get(1)?,
+					conversation_id: row.get(2)?,
+					title: row.get(3)?,
+					updateat: parse_timestamp(&row.get::<_, String>(4)?)
+				})
+			})?
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(records)
+	}
+}
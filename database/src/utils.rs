@@ -1,6 +1,9 @@
-use rusqlite::{Connection, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use rusqlite::{Connection, OptionalExtension, Result};
 use serde_json::json;
 
+use crate::types::DatabaseConfig;
+
 pub fn table_exists(conn: &Connection, table_name: &str) -> bool {
     let result = conn.query_row::<i32, _, _>(
         "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
@@ -43,3 +46,42 @@ pub fn execute_query(conn: &Connection, query: &str, params: &[&dyn rusqlite::To
     }
     Ok(json!(data))
 }
+
+pub fn column_exists(conn: &Connection, table_name: &str, column_name: &str) -> Result<bool> {
+	let sql = "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2;";
+	conn.query_row(sql, [table_name, column_name], |row| row.get::<_, u32>(0)).map(|count| count > 0)
+}
+
+pub fn get_schema_version(conn: &Connection) -> Result<u64> {
+	if !table_exists(conn, "config") {
+		return Ok(1);
+	}
+	let sql = "SELECT version FROM config ORDER BY version DESC LIMIT 1;";
+	let config = conn
+		.query_row(sql, [], |row| Ok(DatabaseConfig { version: row.get(0)? }))
+		.optional()?;
+	match config {
+		Some(config) => Ok(config.version),
+		// Version 2 created the config table without storing its version, so its topic column tells it apart
+		None if column_exists(conn, "conversation", "topic")? => Ok(2),
+		None => Ok(1),
+	}
+}
+
+pub fn set_schema_version(conn: &Connection, version: u64) -> Result<usize> {
+	conn.execute("DELETE FROM config;", [])?;
+	conn.execute("INSERT INTO config (version) VALUES (?);", [version])
+}
+
+pub fn parse_timestamp(text: &str) -> DateTime<Utc> {
+	NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap().and_utc()
+}
+
+pub fn parse_timestamp_ms(millis: i64) -> DateTime<Utc> {
+	Utc.timestamp_millis_opt(millis).unwrap()
+}
+
+/// SQL expression yielding the next sequence number of a message in conversation `id`.
+pub fn next_message_seq(id: u32) -> String {
+	format!("(SELECT IFNULL(MAX(seq), 0) + 1 FROM message WHERE conversation_id = {})", id)
+}
@@ -2,5 +2,24 @@
 
 mod schema_v1;
 mod schema_v2;
+mod schema_v3;
+mod schema_v4;
+mod schema_v5;
+mod schema_v6;
+mod schema_v7;
+mod schema_v8;
+mod schema_v9;
+mod schema_v10;
+mod schema_v11;
+mod schema_v12;
+mod schema_v13;
+mod schema_v14;
+mod schema_v15;
+mod schema_v16;
+mod schema_v17;
+mod schema_v18;
+mod schema_v19;
+mod schema_v20;
 
 pub use schema_v1::SchemaV1 as Database;
+pub use schema_v20::SchemaV20 as CurrentSchema;
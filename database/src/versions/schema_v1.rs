@@ -1,8 +1,10 @@
-use chrono::{TimeZone, Utc};
+use chrono::Utc;
 use rusqlite::{Connection, Result};
 use openai::types::*;
 
-use crate::types::Schema;
+use crate::types::{ConversationFilter, Schema};
+use crate::Database;
+use crate::utils::{next_message_seq, parse_timestamp, parse_timestamp_ms};
 
 pub struct SchemaV1;
 
@@ -54,63 +56,39 @@ impl SchemaV1 {
 
 	pub fn add_conversation(conn: &Connection, title: &str, key: &str) -> Result<u32> {
 		let sql = "
-			INSERT INTO conversation (title, key) VALUES (?, ?);
+			INSERT INTO conversation (title, key) VALUES (encrypt(?), ?);
 		";
 		conn.execute(sql, [title, key])?;
-		return conn.query_row("SELECT last_insert_rowid();", [], |row| row.get(0));
+		conn.query_row("SELECT last_insert_rowid();", [], |row| row.get(0))
 	}
 	
+	/// Returns the conversations of profile `key` that are neither archived nor in the trash.
 	pub fn get_all_conversations(conn: &Connection, key: &str) -> Result<Vec<ConversationListing>> {
-		let sql = "
-			SELECT
-				a.id AS ID,
-				a.title AS Title,
-				IFNULL(SUM(b.prompt_tokens) + SUM(b.completion_tokens), 0) AS TotalUsage,
-				IFNULL(MAX(b.updateat), a.updateat) AS LastUpdate
-			FROM conversation a
-			LEFT JOIN message b ON a.id = b.conversation_id
-			WHERE a.key = ?
-			GROUP BY a.id
-			ORDER BY LastUpdate ASC;
-		";
-		let mut stmt = conn.prepare(sql).unwrap();
-	
-		let conv = stmt
-			.query_map([key], |row| {
-				Ok(ConversationListing {
-					id: row.get(0)?,
-					title: row.get(1)?,
-					usage: row.get(2)?,
-					lastupdate: Utc
-						.datetime_from_str(&row.get::<_, String>(3)?, "%Y-%m-%d %H:%M:%S")
-						.unwrap(),
-				})
-			})
-			.unwrap()
-			.map(Result::unwrap)
-			.collect();
-	
-		return Ok(conv);
+		Database::get_conversations(conn, Some(key), ConversationFilter::Active)
 	}
 	
 	pub fn get_all_messages_in_conversation(conn: &Connection, id: u32) -> Result<Vec<SavedMessage>> {
 		let sql = "
-			SELECT * FROM message WHERE conversation_id = ? ORDER BY updateat ASC;
+			SELECT id, conversation_id, role, CASE WHEN escaped = 1 THEN replace(decrypt(content), '\\\"', '\"') ELSE decrypt(content) END, prompt_tokens, completion_tokens, updateat, seq, createdat_ms
+			FROM message WHERE conversation_id = ? ORDER BY seq ASC;
 		";
 		let mut stmt = conn.prepare(sql).unwrap();
 	
+		let mut attachments = Database::get_attachments_in_conversation(conn, id)?;
 		let conv = stmt
 			.query_map([id], |row| {
+				let message_id = row.get(0)?;
 				Ok(SavedMessage {
-					id: row.get(0)?,
+					id: message_id,
 					conversation_id: row.get(1)?,
 					role: row.get(2)?,
 					content: row.get(3)?,
 					prompt_tokens: row.get(4)?,
 					completion_tokens: row.get(5)?,
-					updateat: Utc
-						.datetime_from_str(&row.get::<_, String>(6)?, "%Y-%m-%d %H:%M:%S")
-						.unwrap()
+					updateat: parse_timestamp(&row.get::<_, String>(6)?),
+					seq: row.get(7)?,
+					createdat: parse_timestamp_ms(row.get(8)?),
+					attachments: attachments.remove(&message_id).unwrap_or_default()
 				})
 			})
 			.unwrap()
@@ -120,44 +98,47 @@ impl SchemaV1 {
 		Ok(conv)
 	}
 	
-	pub fn add_client_message(conn: &Connection, id: u32, msg: &str) -> Result<usize> {
+	pub fn add_client_message(conn: &Connection, id: u32, msg: &str) -> Result<u32> {
 		let sql = format!("
-			INSERT INTO message (conversation_id, role, content, prompt_tokens, completion_tokens) VALUES (
-				{}, ?, ?, {}, {}
+			INSERT INTO message (conversation_id, role, content, prompt_tokens, completion_tokens, seq, createdat_ms) VALUES (
+				{}, ?, encrypt(?), {}, {}, {}, {}
 			);
-		", id, 0, 0);
+		", id, 0, 0, next_message_seq(id), Utc::now().timestamp_millis());
 		let mut stmt = conn.prepare(&sql)?;
-		stmt.execute(["user", msg])
+		stmt.execute(["user", msg])?;
+		conn.query_row("SELECT last_insert_rowid();", [], |row| row.get(0))
 	}
 	
-	pub fn add_server_message(conn: &Connection, id: u32, msg: &CompletionResponse) -> Result<usize> {
+	pub fn add_server_message(conn: &Connection, id: u32, msg: &CompletionResponse, latency_ms: u64) -> Result<usize> {
 		let role = match msg.choices[0].message.role {
 			MessageRole::Assistant => "assistant",
 			MessageRole::User => "user",
 			MessageRole::System => "system",
 		};
-		let content = msg.choices[0].message.content.clone().trim().replace("\"", "\\\"");
+		let content = msg.choices[0].message.content.text().trim().to_string();
 		let sql = format!("
-			INSERT INTO message (conversation_id, role, content, prompt_tokens, completion_tokens) VALUES (
-				{}, ?, ?, {}, {}
+			INSERT INTO message (conversation_id, role, content, prompt_tokens, completion_tokens, model, latency_ms, seq, createdat_ms) VALUES (
+				{}, ?, encrypt(?), {}, {}, ?, {}, {}, {}
 			);
-		", id, msg.usage.prompt_tokens, msg.usage.completion_tokens);
+		", id, msg.usage.prompt_tokens, msg.usage.completion_tokens, latency_ms, next_message_seq(id), Utc::now().timestamp_millis());
 		let mut stmt = conn.prepare(&sql)?;
-		stmt.execute([role, &content])
+		stmt.execute([role, &content, &msg.model])
 	}
 	
 	pub fn add_error_log(
 		conn: &Connection,
 		key: &str,
-		context: &Vec<Message>,
+		model: &str,
+		conversation_id: Option<u32>,
+		context: &[Message],
 		error: &str,
 		openai_error: Option<&OpenAIError>
-	) -> Result<usize> {
-		let sql = format!("
-			INSERT INTO error (key, context, error, message, type, code, param) VALUES (
-				?, ?, ?, ?, ?, ?, ?
+	) -> Result<u32> {
+		let sql = "
+			INSERT INTO error (key, model, conversation_id, prompt, context, error, message, type, code, param) VALUES (
+				?, ?, ?, encrypt(?), encrypt(?), ?, ?, ?, ?, ?
 			);
-		");
+		";
 		let mut message: Option<&str> = None;
 		let mut code: Option<&str> = None;
 		let mut r#type: Option<&str> = None;
@@ -167,11 +148,22 @@ impl SchemaV1 {
 			code = Some(&openai_error.error.code);
 			r#type = Some(&openai_error.error.r#type);
 			if let Some(has_params) = &openai_error.error.param {
-				param = Some(&has_params);
+				param = Some(has_params);
 			};
 		}
-		let mut stmt = conn.prepare(&sql)?;
-		stmt.execute([Some(key), Some(&serde_json::to_string(context).unwrap()), Some(error), message, code, r#type, param])
+		// With attachments the prompt is the first part; the others are kept with the error.
+		let prompt = context.last()
+			.filter(|msg| matches!(msg.role, MessageRole::User))
+			.map(|msg| match &msg.content {
+				MessageContent::Parts(parts) => match parts.first() {
+					Some(ContentPart::Text { text }) => text.clone(),
+					_ => String::new()
+				},
+				content => content.text()
+			});
+		let mut stmt = conn.prepare(sql)?;
+		stmt.execute(rusqlite::params![key, model, conversation_id, prompt, serde_json::to_string(context).unwrap(), error, message, r#type, code, param])?;
+		Ok(conn.last_insert_rowid() as u32)
 	}
 }
 
@@ -0,0 +1,46 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v9::SchemaV9 as PrevSchema;
+
+pub struct SchemaV10;
+
+impl SchemaV10 {
+	fn upgrade_from_v9(conn: &Connection) -> Result<usize> {
+		SchemaV10::create_schema_conversation_settings(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_conversation_settings(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS conversation_settings (
+				conversation_id INTEGER PRIMARY KEY,
+				model VARCHAR(128),
+				params TEXT,
+				context_strategy VARCHAR(32),
+				max_token INTEGER,
+				max_dialog INTEGER,
+				system_prompt TEXT,
+				persona VARCHAR(128),
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (conversation_id) REFERENCES conversation (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV10 {
+	fn version() -> u64 { 10 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV10::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV10::upgrade_from_v9(conn)?;
+			set_schema_version(conn, SchemaV10::version())?;
+		}
+		Ok(0)
+	}
+}
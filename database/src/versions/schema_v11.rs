@@ -0,0 +1,37 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v10::SchemaV10 as PrevSchema;
+
+pub struct SchemaV11;
+
+impl SchemaV11 {
+	fn upgrade_from_v10(conn: &Connection) -> Result<usize> {
+		SchemaV11::alter_schema_conversation(conn)?;
+
+		Ok(0)
+	}
+
+	fn alter_schema_conversation(conn: &Connection) -> Result<usize> {
+		let sql = "
+			ALTER TABLE conversation ADD COLUMN archived_at DATETIME;
+			ALTER TABLE conversation ADD COLUMN deleted_at DATETIME;
+		";
+		conn.execute_batch(sql)?;
+		Ok(0)
+	}
+}
+
+impl Schema for SchemaV11 {
+	fn version() -> u64 { 11 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV11::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV11::upgrade_from_v10(conn)?;
+			set_schema_version(conn, SchemaV11::version())?;
+		}
+		Ok(0)
+	}
+}
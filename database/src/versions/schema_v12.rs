@@ -0,0 +1,41 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v11::SchemaV11 as PrevSchema;
+
+pub struct SchemaV12;
+
+impl SchemaV12 {
+	fn upgrade_from_v11(conn: &Connection) -> Result<usize> {
+		SchemaV12::create_schema_pin(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_pin(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS pin (
+				message_id INTEGER PRIMARY KEY,
+				conversation_id INTEGER NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (message_id) REFERENCES message (id),
+				FOREIGN KEY (conversation_id) REFERENCES conversation (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV12 {
+	fn version() -> u64 { 12 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV12::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV12::upgrade_from_v11(conn)?;
+			set_schema_version(conn, SchemaV12::version())?;
+		}
+		Ok(0)
+	}
+}
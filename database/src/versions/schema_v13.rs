@@ -0,0 +1,44 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v12::SchemaV12 as PrevSchema;
+
+pub struct SchemaV13;
+
+impl SchemaV13 {
+	fn upgrade_from_v12(conn: &Connection) -> Result<usize> {
+		SchemaV13::create_schema_message_revision(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_message_revision(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS message_revision (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				message_id INTEGER NOT NULL,
+				revision INTEGER NOT NULL,
+				content TEXT,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (message_id) REFERENCES message (id)
+			);
+			CREATE UNIQUE INDEX IF NOT EXISTS message_revision_message ON message_revision (message_id, revision);
+		";
+		conn.execute_batch(sql)?;
+		Ok(0)
+	}
+}
+
+impl Schema for SchemaV13 {
+	fn version() -> u64 { 13 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV13::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV13::upgrade_from_v12(conn)?;
+			set_schema_version(conn, SchemaV13::version())?;
+		}
+		Ok(0)
+	}
+}
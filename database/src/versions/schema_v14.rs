@@ -0,0 +1,59 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v13::SchemaV13 as PrevSchema;
+
+pub struct SchemaV14;
+
+impl SchemaV14 {
+	fn upgrade_from_v13(conn: &Connection) -> Result<usize> {
+		SchemaV14::create_schema_budget(conn)?;
+		SchemaV14::create_schema_embedding_usage(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_budget(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS budget (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				key VARCHAR(512),
+				conversation_id INTEGER,
+				period VARCHAR(16) NOT NULL,
+				unit VARCHAR(16) NOT NULL,
+				amount REAL NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (conversation_id) REFERENCES conversation (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+
+	/// Tokens used by embedding requests, which have no reply message to record them with.
+	fn create_schema_embedding_usage(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS embedding_usage (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				key VARCHAR(512) NOT NULL,
+				model VARCHAR(64) NOT NULL,
+				prompt_tokens INTEGER NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV14 {
+	fn version() -> u64 { 14 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV14::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV14::upgrade_from_v13(conn)?;
+			set_schema_version(conn, SchemaV14::version())?;
+		}
+		Ok(0)
+	}
+}
@@ -0,0 +1,40 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v14::SchemaV14 as PrevSchema;
+
+pub struct SchemaV15;
+
+impl SchemaV15 {
+	fn upgrade_from_v14(conn: &Connection) -> Result<usize> {
+		SchemaV15::create_schema_topic(conn)?;
+
+		Ok(0)
+	}
+
+	/// Names for the `topic` column of conversations, which is 0 for conversations without a topic.
+	fn create_schema_topic(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS topic (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				name TEXT NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV15 {
+	fn version() -> u64 { 15 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV15::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV15::upgrade_from_v14(conn)?;
+			set_schema_version(conn, SchemaV15::version())?;
+		}
+		Ok(0)
+	}
+}
@@ -0,0 +1,41 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v15::SchemaV15 as PrevSchema;
+
+pub struct SchemaV16;
+
+impl SchemaV16 {
+	fn upgrade_from_v15(conn: &Connection) -> Result<usize> {
+		SchemaV16::create_schema_draft(conn)?;
+
+		Ok(0)
+	}
+
+	/// The unsent prompt of each conversation, kept until a reply to it is stored.
+	fn create_schema_draft(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS draft (
+				conversation_id INTEGER PRIMARY KEY,
+				content TEXT NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (conversation_id) REFERENCES conversation (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV16 {
+	fn version() -> u64 { 16 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV16::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV16::upgrade_from_v15(conn)?;
+			set_schema_version(conn, SchemaV16::version())?;
+		}
+		Ok(0)
+	}
+}
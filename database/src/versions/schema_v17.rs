@@ -0,0 +1,61 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v16::SchemaV16 as PrevSchema;
+
+pub struct SchemaV17;
+
+impl SchemaV17 {
+	fn upgrade_from_v16(conn: &Connection) -> Result<usize> {
+		SchemaV17::create_schema_message_file(conn)?;
+		SchemaV17::create_schema_error_file(conn)?;
+
+		Ok(0)
+	}
+
+	/// Files whose content was included in a user message with `@path`, with a hash of the content at the time.
+	fn create_schema_message_file(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS message_file (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				message_id INTEGER NOT NULL,
+				path TEXT NOT NULL,
+				sha256 TEXT NOT NULL,
+				size INTEGER NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (message_id) REFERENCES message (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+
+	/// Files included in a prompt that failed, kept so that a retry records them with the prompt.
+	fn create_schema_error_file(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS error_file (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				error_id INTEGER NOT NULL,
+				path TEXT NOT NULL,
+				sha256 TEXT NOT NULL,
+				size INTEGER NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (error_id) REFERENCES error (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV17 {
+	fn version() -> u64 { 17 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV17::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV17::upgrade_from_v16(conn)?;
+			set_schema_version(conn, SchemaV17::version())?;
+		}
+		Ok(0)
+	}
+}
@@ -0,0 +1,47 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v17::SchemaV17 as PrevSchema;
+
+pub struct SchemaV18;
+
+impl SchemaV18 {
+	fn upgrade_from_v17(conn: &Connection) -> Result<usize> {
+		SchemaV18::create_schema_message_command(conn)?;
+
+		Ok(0)
+	}
+
+	/// Commands run with `!command` whose output was sent in a user message. `exit_code` is NULL when the
+	/// command timed out or was terminated by a signal.
+	fn create_schema_message_command(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS message_command (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				message_id INTEGER NOT NULL,
+				command TEXT NOT NULL,
+				exit_code INTEGER,
+				timed_out INTEGER NOT NULL DEFAULT 0,
+				duration_ms INTEGER NOT NULL,
+				omitted_lines INTEGER NOT NULL DEFAULT 0,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (message_id) REFERENCES message (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV18 {
+	fn version() -> u64 { 18 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV18::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV18::upgrade_from_v17(conn)?;
+			set_schema_version(conn, SchemaV18::version())?;
+		}
+		Ok(0)
+	}
+}
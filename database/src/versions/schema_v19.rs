@@ -0,0 +1,72 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v18::SchemaV18 as PrevSchema;
+
+pub struct SchemaV19;
+
+impl SchemaV19 {
+	fn upgrade_from_v18(conn: &Connection) -> Result<usize> {
+		SchemaV19::add_column_escaped(conn)?;
+
+		Ok(0)
+	}
+
+	/// Replies used to be stored with every `"` escaped as `\"`. The content may be encrypted while migrating,
+	/// so those messages are flagged and unescaped when read.
+	fn add_column_escaped(conn: &Connection) -> Result<usize> {
+		let sql = "
+			ALTER TABLE message ADD COLUMN escaped INTEGER NOT NULL DEFAULT 0;
+		";
+		conn.execute(sql, [])?;
+		let sql = "
+			UPDATE message SET escaped = 1 WHERE role = 'assistant';
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV19 {
+	fn version() -> u64 { 19 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV19::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV19::upgrade_from_v18(conn)?;
+			set_schema_version(conn, SchemaV19::version())?;
+		}
+		Ok(0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rusqlite::Connection;
+
+	use super::*;
+	use crate::crypto::register_functions;
+	use crate::test_support::reply;
+	use crate::Database;
+
+	#[test]
+	fn unescapes_replies_stored_before_the_migration() {
+		let conn = Connection::open_in_memory().unwrap();
+		register_functions(&conn, None, None).unwrap();
+		PrevSchema::init_current_schema(&conn).unwrap();
+		conn.execute_batch(r#"
+			INSERT INTO conversation (title, key) VALUES ('old', 'key');
+			INSERT INTO message (conversation_id, role, content, prompt_tokens, completion_tokens, seq, createdat_ms) VALUES
+				(1, 'user', 'Say "hi"', 0, 0, 1, 0),
+				(1, 'assistant', 'print(\"hi\")', 0, 0, 2, 0);
+		"#).unwrap();
+
+		SchemaV19::init_current_schema(&conn).unwrap();
+		Database::add_server_message(&conn, 1, &reply(r#"print("a \"quoted\" word")"#), 10).unwrap();
+		let contents: Vec<String> = Database::get_all_messages_in_conversation(&conn, 1).unwrap()
+			.into_iter()
+			.map(|msg| msg.content)
+			.collect();
+		assert_eq!(contents, [r#"Say "hi""#, r#"print("hi")"#, r#"print("a \"quoted\" word")"#]);
+	}
+}
@@ -1,30 +1,12 @@
 use rusqlite::{Connection, Result};
 use crate::types::*;
-use crate::utils::table_exists;
+use crate::utils::{get_schema_version, set_schema_version};
 
 use super::schema_v1::SchemaV1 as PrevSchema;
 
 pub struct SchemaV2;
 
 impl SchemaV2 {
-	fn check_schema_version(conn: &Connection) -> Result<u64> {
-		let config_exists = table_exists(conn, "config");
-		if config_exists {
-			let sql = "SELECT * FROM config LIMIT 1;";
-			let mut stmt = conn.prepare(sql).unwrap();
-			let config: Vec<DatabaseConfig> = stmt.query_map([], |row| {
-				Ok(DatabaseConfig {
-					version: row.get(0)?
-				})
-			})
-			.unwrap()
-			.map(Result::unwrap)
-			.collect();
-			return Ok(config[0].version);
-		}
-		Ok(1)
-	}
-
 	fn upgrade_from_v1(conn: &Connection) -> Result<usize> {
 		SchemaV2::create_schema_config(conn)?;
 		SchemaV2::alter_schema_conversation(conn)?;
@@ -53,10 +35,38 @@ impl Schema for SchemaV2 {
 	fn version() -> u64 { 2 }
 
 	fn init_current_schema(conn: &Connection) -> Result<usize> {
-		if SchemaV2::check_schema_version(conn)? != SchemaV2::version() {
+		if get_schema_version(conn)? < SchemaV2::version() {
 			PrevSchema::init_current_schema(conn)?;
 			SchemaV2::upgrade_from_v1(conn)?;
+			set_schema_version(conn, SchemaV2::version())?;
 		}
 		Ok(0)
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use rusqlite::Connection;
+
+	use super::*;
+	use crate::crypto::register_functions;
+	use crate::{CurrentSchema, Database};
+
+	#[test]
+	fn migrates_a_database_without_a_stored_version() {
+		let conn = Connection::open_in_memory().unwrap();
+		register_functions(&conn, None, None).unwrap();
+		PrevSchema::init_current_schema(&conn).unwrap();
+		SchemaV2::upgrade_from_v1(&conn).unwrap();
+		conn.execute_batch("
+			INSERT INTO conversation (title, key) VALUES ('old', 'key');
+			INSERT INTO message (conversation_id, role, content, prompt_tokens, completion_tokens) VALUES (1, 'user', 'hello', 0, 0);
+		").unwrap();
+		assert_eq!(get_schema_version(&conn).unwrap(), 2);
+
+		CurrentSchema::init_current_schema(&conn).unwrap();
+		assert_eq!(get_schema_version(&conn).unwrap(), CurrentSchema::version());
+		assert_eq!(Database::get_all_conversations(&conn, "key").unwrap()[0].title, "old");
+		assert_eq!(Database::get_all_messages_in_conversation(&conn, 1).unwrap()[0].content, "hello");
+	}
+}
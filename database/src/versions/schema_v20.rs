@@ -0,0 +1,48 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v19::SchemaV19 as PrevSchema;
+
+pub struct SchemaV20;
+
+impl SchemaV20 {
+	fn upgrade_from_v19(conn: &Connection) -> Result<usize> {
+		SchemaV20::create_schema_message_patch(conn)?;
+
+		Ok(0)
+	}
+
+	/// Changes to local files applied from a reply with /apply. `action` is one of `modify`, `create` and
+	/// `delete`; the hash before is NULL for created files, the hash after NULL for deleted ones.
+	fn create_schema_message_patch(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS message_patch (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				message_id INTEGER NOT NULL,
+				path TEXT NOT NULL,
+				action TEXT NOT NULL,
+				hunks INTEGER NOT NULL DEFAULT 0,
+				fuzzy_hunks INTEGER NOT NULL DEFAULT 0,
+				sha256_before TEXT,
+				sha256_after TEXT,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (message_id) REFERENCES message (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV20 {
+	fn version() -> u64 { 20 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV20::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV20::upgrade_from_v19(conn)?;
+			set_schema_version(conn, SchemaV20::version())?;
+		}
+		Ok(0)
+	}
+}
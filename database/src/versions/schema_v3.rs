@@ -0,0 +1,43 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v2::SchemaV2 as PrevSchema;
+
+pub struct SchemaV3;
+
+impl SchemaV3 {
+	fn upgrade_from_v2(conn: &Connection) -> Result<usize> {
+		SchemaV3::create_schema_encryption(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_encryption(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS encryption (
+				id INTEGER PRIMARY KEY CHECK (id = 1),
+				salt TEXT NOT NULL,
+				memory_cost INTEGER NOT NULL,
+				time_cost INTEGER NOT NULL,
+				parallelism INTEGER NOT NULL,
+				verifier TEXT NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV3 {
+	fn version() -> u64 { 3 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV3::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV3::upgrade_from_v2(conn)?;
+			set_schema_version(conn, SchemaV3::version())?;
+		}
+		Ok(0)
+	}
+}
@@ -0,0 +1,45 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v3::SchemaV3 as PrevSchema;
+
+pub struct SchemaV4;
+
+impl SchemaV4 {
+	fn upgrade_from_v3(conn: &Connection) -> Result<usize> {
+		SchemaV4::alter_schema_message(conn)?;
+		SchemaV4::alter_schema_error_log(conn)?;
+
+		Ok(0)
+	}
+
+	fn alter_schema_message(conn: &Connection) -> Result<usize> {
+		let sql = "
+			ALTER TABLE message ADD COLUMN model VARCHAR(128);
+			ALTER TABLE message ADD COLUMN latency_ms INTEGER;
+		";
+		conn.execute_batch(sql)?;
+		Ok(0)
+	}
+
+	fn alter_schema_error_log(conn: &Connection) -> Result<usize> {
+		let sql = "
+			ALTER TABLE error ADD COLUMN model VARCHAR(128);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV4 {
+	fn version() -> u64 { 4 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV4::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV4::upgrade_from_v3(conn)?;
+			set_schema_version(conn, SchemaV4::version())?;
+		}
+		Ok(0)
+	}
+}
@@ -0,0 +1,38 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v4::SchemaV4 as PrevSchema;
+
+pub struct SchemaV5;
+
+impl SchemaV5 {
+	fn upgrade_from_v4(conn: &Connection) -> Result<usize> {
+		SchemaV5::alter_schema_error_log(conn)?;
+
+		Ok(0)
+	}
+
+	fn alter_schema_error_log(conn: &Connection) -> Result<usize> {
+		let sql = "
+			ALTER TABLE error ADD COLUMN conversation_id INTEGER REFERENCES conversation (id);
+			ALTER TABLE error ADD COLUMN prompt TEXT;
+			ALTER TABLE error ADD COLUMN retried_at DATETIME;
+		";
+		conn.execute_batch(sql)?;
+		Ok(0)
+	}
+}
+
+impl Schema for SchemaV5 {
+	fn version() -> u64 { 5 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV5::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV5::upgrade_from_v4(conn)?;
+			set_schema_version(conn, SchemaV5::version())?;
+		}
+		Ok(0)
+	}
+}
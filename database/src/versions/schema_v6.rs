@@ -0,0 +1,91 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v5::SchemaV5 as PrevSchema;
+
+pub struct SchemaV6;
+
+impl SchemaV6 {
+	fn upgrade_from_v5(conn: &Connection) -> Result<usize> {
+		SchemaV6::alter_schema_message(conn)?;
+		SchemaV6::backfill_message_order(conn)?;
+
+		Ok(0)
+	}
+
+	fn alter_schema_message(conn: &Connection) -> Result<usize> {
+		let sql = "
+			ALTER TABLE message ADD COLUMN seq INTEGER;
+			ALTER TABLE message ADD COLUMN createdat_ms INTEGER;
+		";
+		conn.execute_batch(sql)?;
+		Ok(0)
+	}
+
+	/// Numbers existing messages in the order they were inserted. Rows written in the same second
+	/// are ordered by their row ID, which is how the old second-resolution timestamps were meant to be read.
+	fn backfill_message_order(conn: &Connection) -> Result<usize> {
+		let sql = "
+			UPDATE message SET seq = (
+				SELECT COUNT(*) FROM message b
+				WHERE b.conversation_id = message.conversation_id
+				AND (b.updateat < message.updateat OR (b.updateat = message.updateat AND b.id <= message.id))
+			);
+			UPDATE message SET createdat_ms = CAST(strftime('%s', updateat) AS INTEGER) * 1000;
+			CREATE UNIQUE INDEX IF NOT EXISTS message_conversation_seq ON message (conversation_id, seq);
+		";
+		conn.execute_batch(sql)?;
+		Ok(0)
+	}
+}
+
+impl Schema for SchemaV6 {
+	fn version() -> u64 { 6 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV6::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV6::upgrade_from_v5(conn)?;
+			set_schema_version(conn, SchemaV6::version())?;
+		}
+		Ok(0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rusqlite::Connection;
+
+	use super::*;
+	use crate::crypto::register_functions;
+
+	#[test]
+	fn numbers_existing_messages_in_order() {
+		let conn = Connection::open_in_memory().unwrap();
+		register_functions(&conn, None, None).unwrap();
+		PrevSchema::init_current_schema(&conn).unwrap();
+		conn.execute_batch("
+			INSERT INTO conversation (title, key) VALUES ('first', 'key'), ('second', 'key');
+			INSERT INTO message (conversation_id, role, content, prompt_tokens, completion_tokens, updateat) VALUES
+				(1, 'user', 'a', 0, 0, '2024-01-01 10:00:00'),
+				(2, 'user', 'x', 0, 0, '2024-01-01 09:00:00'),
+				(1, 'assistant', 'c', 0, 0, '2024-01-01 10:00:05'),
+				(1, 'assistant', 'b', 0, 0, '2024-01-01 10:00:00');
+		").unwrap();
+
+		SchemaV6::init_current_schema(&conn).unwrap();
+		let mut stmt = conn.prepare("SELECT conversation_id, content, seq, createdat_ms FROM message ORDER BY conversation_id, seq;").unwrap();
+		let rows: Vec<(u32, String, u64, i64)> = stmt
+			.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+			.unwrap()
+			.collect::<Result<_>>()
+			.unwrap();
+		assert_eq!(rows, [
+			(1, "a".into(), 1, 1704103200000),
+			(1, "b".into(), 2, 1704103200000),
+			(1, "c".into(), 3, 1704103205000),
+			(2, "x".into(), 1, 1704099600000),
+		]);
+	}
+}
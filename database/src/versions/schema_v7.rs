@@ -0,0 +1,75 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v6::SchemaV6 as PrevSchema;
+
+pub struct SchemaV7;
+
+impl SchemaV7 {
+	fn upgrade_from_v6(conn: &Connection) -> Result<usize> {
+		SchemaV7::create_schema_attachment(conn)?;
+		SchemaV7::create_schema_message_attachment(conn)?;
+		SchemaV7::create_schema_error_attachment(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_attachment(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS attachment (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				hash VARCHAR(64) NOT NULL UNIQUE,
+				mime VARCHAR(128) NOT NULL,
+				size INTEGER NOT NULL,
+				data BLOB NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP
+			);
+		";
+		conn.execute(sql, [])
+	}
+
+	fn create_schema_message_attachment(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS message_attachment (
+				message_id INTEGER NOT NULL,
+				attachment_id INTEGER NOT NULL,
+				position INTEGER NOT NULL,
+				name TEXT,
+				PRIMARY KEY (message_id, position),
+				FOREIGN KEY (message_id) REFERENCES message (id),
+				FOREIGN KEY (attachment_id) REFERENCES attachment (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+
+	/// Attachments of a prompt that failed, kept so that a retry stores them with the prompt.
+	fn create_schema_error_attachment(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS error_attachment (
+				error_id INTEGER NOT NULL,
+				attachment_id INTEGER NOT NULL,
+				position INTEGER NOT NULL,
+				name TEXT,
+				PRIMARY KEY (error_id, position),
+				FOREIGN KEY (error_id) REFERENCES error (id),
+				FOREIGN KEY (attachment_id) REFERENCES attachment (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV7 {
+	fn version() -> u64 { 7 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV7::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV7::upgrade_from_v6(conn)?;
+			set_schema_version(conn, SchemaV7::version())?;
+		}
+		Ok(0)
+	}
+}
@@ -0,0 +1,46 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v7::SchemaV7 as PrevSchema;
+
+pub struct SchemaV8;
+
+impl SchemaV8 {
+	fn upgrade_from_v7(conn: &Connection) -> Result<usize> {
+		SchemaV8::create_schema_summary(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_summary(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS summary (
+				id INTEGER PRIMARY KEY AUTOINCREMENT,
+				conversation_id INTEGER NOT NULL,
+				from_seq INTEGER NOT NULL,
+				to_seq INTEGER NOT NULL,
+				content TEXT NOT NULL,
+				model VARCHAR(128),
+				prompt_tokens INTEGER NOT NULL,
+				completion_tokens INTEGER NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				FOREIGN KEY (conversation_id) REFERENCES conversation (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV8 {
+	fn version() -> u64 { 8 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV8::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV8::upgrade_from_v7(conn)?;
+			set_schema_version(conn, SchemaV8::version())?;
+		}
+		Ok(0)
+	}
+}
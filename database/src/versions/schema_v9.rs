@@ -0,0 +1,43 @@
+use rusqlite::{Connection, Result};
+use crate::types::*;
+use crate::utils::{get_schema_version, set_schema_version};
+
+use super::schema_v8::SchemaV8 as PrevSchema;
+
+pub struct SchemaV9;
+
+impl SchemaV9 {
+	fn upgrade_from_v8(conn: &Connection) -> Result<usize> {
+		SchemaV9::create_schema_embedding(conn)?;
+
+		Ok(0)
+	}
+
+	fn create_schema_embedding(conn: &Connection) -> Result<usize> {
+		let sql = "
+			CREATE TABLE IF NOT EXISTS embedding (
+				message_id INTEGER NOT NULL,
+				model VARCHAR(128) NOT NULL,
+				dimensions INTEGER NOT NULL,
+				vector BLOB NOT NULL,
+				updateat DATETIME DEFAULT CURRENT_TIMESTAMP,
+				PRIMARY KEY (message_id, model),
+				FOREIGN KEY (message_id) REFERENCES message (id)
+			);
+		";
+		conn.execute(sql, [])
+	}
+}
+
+impl Schema for SchemaV9 {
+	fn version() -> u64 { 9 }
+
+	fn init_current_schema(conn: &Connection) -> Result<usize> {
+		if get_schema_version(conn)? < SchemaV9::version() {
+			PrevSchema::init_current_schema(conn)?;
+			SchemaV9::upgrade_from_v8(conn)?;
+			set_schema_version(conn, SchemaV9::version())?;
+		}
+		Ok(0)
+	}
+}
@@ -1,38 +1,39 @@
 use reqwest::{self, Proxy};
+use serde::{de::DeserializeOwned, Serialize};
 
 use crate::error::*;
 use crate::types::*;
 
-pub async fn get_response(
-    context: &Vec<Message>,
-    api_key: &str,
-	use_proxy: &Option<String>,
-	model: &str
-) -> Result<OpenAIResponse, String> {
-    let client = if let Some(proxy) = use_proxy {
+pub static DEFAULT_API_BASE: &str = "https://api.openai.com/v1";
+
+fn build_client(use_proxy: &Option<String>) -> reqwest::Client {
+	if let Some(proxy) = use_proxy {
 		reqwest::Client::builder().proxy(Proxy::all(proxy).unwrap()).build().unwrap()
 	}
 	else {
 		reqwest::Client::new()
-	};
-    let url = "https://api.openai.com/v1/chat/completions";
+	}
+}
 
-    let request = CompletionRequest {
-        model: model.into(),
-        messages: context.clone().to_vec(),
-    };
+async fn post<Request: Serialize, Response: DeserializeOwned>(
+	url: &str,
+	request: &Request,
+	api_key: &str,
+	use_proxy: &Option<String>
+) -> Result<OpenAIResponse<Response>, String> {
+	let client = build_client(use_proxy);
 
     let response = client
         .post(url)
         .header("Content-Type", "application/json")
         .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request)
+        .json(request)
         .send()
         .await;
 
-    return match response {
+    match response {
         Ok(success) => match success.text().await {
-            Ok(body) => match serde_json::from_str::<CompletionResponse>(&body) {
+            Ok(body) => match serde_json::from_str::<Response>(&body) {
 				Ok(completion) => Ok(OpenAIResponse::Success(completion)),
 				Err(json_error) => match serde_json::from_str::<OpenAIError>(&body) {
 					Ok(openai_response) => Ok(OpenAIResponse::Failure(openai_response)),
@@ -46,5 +47,160 @@ pub async fn get_response(
             Err(request_error) => Err(RequestError::new(request_error).to_string())
         },
         Err(request_error) => Err(RequestError::new(request_error).to_string()),
+    }
+}
+
+pub async fn get_response(
+    context: &[Message],
+    api_key: &str,
+	use_proxy: &Option<String>,
+	api_base: &str,
+	model: &str,
+	params: &CompletionParams
+) -> Result<OpenAIResponse, String> {
+    let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
+
+    let request = CompletionRequest {
+        model: model.into(),
+        messages: context.to_vec(),
+        params: params.clone(),
+        stream: None,
+        stream_options: None,
     };
+
+	post(&url, &request, api_key, use_proxy).await
+}
+
+/// Like [`get_response`], but streams the reply and calls `on_delta` with each piece as it arrives.
+/// The pieces are assembled into a regular response. APIs that do not report usage for streams
+/// leave it at zero, and a reply that is not streamed is passed to `on_delta` whole.
+pub async fn get_response_stream(
+	context: &[Message],
+	api_key: &str,
+	use_proxy: &Option<String>,
+	api_base: &str,
+	model: &str,
+	params: &CompletionParams,
+	mut on_delta: impl FnMut(&str)
+) -> Result<OpenAIResponse, String> {
+	let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
+
+	let request = CompletionRequest {
+		model: model.into(),
+		messages: context.to_vec(),
+		params: params.clone(),
+		stream: Some(true),
+		stream_options: Some(StreamOptions { include_usage: true }),
+	};
+
+	let client = build_client(use_proxy);
+	let mut response = client
+		.post(&url)
+		.header("Content-Type", "application/json")
+		.header("Authorization", format!("Bearer {}", api_key))
+		.json(&request)
+		.send()
+		.await
+		.map_err(|err| RequestError::new(err).to_string())?;
+
+	let is_stream = response.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.is_some_and(|value| value.starts_with("text/event-stream"));
+	if !is_stream {
+		let body = response.text().await.map_err(|err| RequestError::new(err).to_string())?;
+		if let Ok(completion) = serde_json::from_str::<CompletionResponse>(&body) {
+			on_delta(&completion.msg());
+			return Ok(OpenAIResponse::Success(completion));
+		}
+		return match serde_json::from_str::<OpenAIError>(&body) {
+			Ok(openai_error) => Ok(OpenAIResponse::Failure(openai_error)),
+			Err(json_error) => Err(JSONParseError::new(json_error.to_string(), body).to_string()),
+		};
+	}
+
+	let mut reply = CompletionResponse {
+		id: String::new(),
+		object: "chat.completion".into(),
+		created: 0,
+		model: model.into(),
+		usage: TokenUsage { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 },
+		choices: vec![],
+	};
+	let mut text = String::new();
+	let mut finish_reason = None;
+	let mut buffer: Vec<u8> = vec![];
+	'events: while let Some(bytes) = response.chunk().await.map_err(|err| RequestError::new(err).to_string())? {
+		buffer.extend_from_slice(&bytes);
+		while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+			let line: Vec<u8> = buffer.drain(..=end).collect();
+			let line = String::from_utf8_lossy(&line);
+			let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+				continue
+			};
+			if data == "[DONE]" {
+				break 'events;
+			}
+			let chunk = serde_json::from_str::<CompletionChunk>(data)
+				.map_err(|err| JSONParseError::new(err.to_string(), data.into()).to_string())?;
+			reply.id = chunk.id;
+			reply.created = chunk.created;
+			reply.model = chunk.model;
+			if let Some(usage) = chunk.usage {
+				reply.usage = usage;
+			}
+			for choice in chunk.choices.into_iter().filter(|choice| choice.index == 0) {
+				if let Some(content) = choice.delta.content {
+					on_delta(&content);
+					text.push_str(&content);
+				}
+				finish_reason = choice.finish_reason.or(finish_reason);
+			}
+		}
+	}
+
+	reply.choices.push(ResponseChoice {
+		index: 0,
+		finish_reason,
+		message: Message { role: MessageRole::Assistant, content: MessageContent::Text(text) },
+	});
+	Ok(OpenAIResponse::Success(reply))
+}
+
+/// Posts `body` as it is to `path` under the API base, so that requests may use parameters this crate does not
+/// know. The response is returned unread, for streams to be passed on as they arrive.
+pub async fn forward_request(
+	path: &str,
+	body: Vec<u8>,
+	api_key: &str,
+	use_proxy: &Option<String>,
+	api_base: &str
+) -> Result<reqwest::Response, String> {
+	let url = format!("{}/{}", api_base.trim_end_matches('/'), path.trim_start_matches('/'));
+
+	build_client(use_proxy)
+		.post(&url)
+		.header("Content-Type", "application/json")
+		.header("Authorization", format!("Bearer {}", api_key))
+		.body(body)
+		.send()
+		.await
+		.map_err(|err| RequestError::new(err).to_string())
+}
+
+pub async fn get_embeddings(
+	input: &[String],
+	api_key: &str,
+	use_proxy: &Option<String>,
+	api_base: &str,
+	model: &str
+) -> Result<OpenAIResponse<EmbeddingResponse>, String> {
+	let url = format!("{}/embeddings", api_base.trim_end_matches('/'));
+
+	let request = EmbeddingRequest {
+		model: model.into(),
+		input: input.to_vec(),
+	};
+
+	post(&url, &request, api_key, use_proxy).await
 }
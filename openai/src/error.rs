@@ -1,4 +1,4 @@
-use std::string::ToString;
+use std::fmt::Display;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -18,9 +18,9 @@ impl RequestError {
 	}
 }
 
-impl ToString for RequestError {
-	fn to_string(&self) -> String {
-		return serde_json::to_string(&self).unwrap();
+impl Display for RequestError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", serde_json::to_string(&self).unwrap())
 	}
 }
 
@@ -36,8 +36,8 @@ impl JSONParseError {
 	}
 }
 
-impl ToString for JSONParseError {
-	fn to_string(&self) -> String {
-		return serde_json::to_string(&self).unwrap();
+impl Display for JSONParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", serde_json::to_string(&self).unwrap())
 	}
 }
\ No newline at end of file
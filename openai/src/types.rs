@@ -5,9 +5,14 @@ pub struct ConversationListing {
 	pub id: u32,
 	pub title: String,
 	pub usage: u64,
-	pub lastupdate: DateTime<Utc>
+	pub lastupdate: DateTime<Utc>,
+	pub key: String,
+	pub topic: Option<String>,
+	pub archived_at: Option<DateTime<Utc>>,
+	pub deleted_at: Option<DateTime<Utc>>
 }
 
+#[derive(Clone)]
 pub struct SavedMessage {
 	pub id: u32,
 	pub conversation_id: u32,
@@ -15,12 +20,22 @@ pub struct SavedMessage {
 	pub content: String,
 	pub prompt_tokens: u64,
 	pub completion_tokens: u64,
-	pub updateat: DateTime<Utc>
+	pub updateat: DateTime<Utc>,
+	pub seq: u64,
+	pub createdat: DateTime<Utc>,
+	pub attachments: Vec<Attachment>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+	pub name: String,
+	pub mime: String,
+	pub data: Vec<u8>
 }
 
 #[derive(Serialize, Deserialize)]
-pub enum OpenAIResponse {
-	Success(CompletionResponse),
+pub enum OpenAIResponse<T = CompletionResponse> {
+	Success(T),
 	Failure(OpenAIError)
 }
 
@@ -40,7 +55,33 @@ pub struct CompletionError {
 #[derive(Serialize)]
 pub struct CompletionRequest {
     pub model: String,
-	pub messages: Vec<Message>
+	pub messages: Vec<Message>,
+	#[serde(flatten)]
+	pub params: CompletionParams,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stream: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stream_options: Option<StreamOptions>
+}
+
+#[derive(Serialize)]
+pub struct StreamOptions {
+	pub include_usage: bool
+}
+
+/// Optional sampling parameters. Unset parameters are left out of the request so that the API defaults apply.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CompletionParams {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub temperature: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub top_p: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub max_tokens: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub presence_penalty: Option<f32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub frequency_penalty: Option<f32>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -55,10 +96,57 @@ pub struct CompletionResponse {
 
 impl CompletionResponse {
 	pub fn msg(&self) -> String {
-		return self.choices[0].message.content.clone();
+		self.choices[0].message.content.text()
 	}
 }
 
+/// One server-sent event of a streamed completion. The last chunk carries the usage and no choices.
+#[derive(Deserialize)]
+pub struct CompletionChunk {
+	pub id: String,
+	pub created: u64,
+	pub model: String,
+	pub choices: Vec<ChunkChoice>,
+	pub usage: Option<TokenUsage>
+}
+
+#[derive(Deserialize)]
+pub struct ChunkChoice {
+	pub index: u64,
+	pub finish_reason: Option<String>,
+	pub delta: MessageDelta
+}
+
+#[derive(Deserialize)]
+pub struct MessageDelta {
+	pub content: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct EmbeddingRequest {
+	pub model: String,
+	pub input: Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+	pub model: String,
+	pub data: Vec<Embedding>,
+	pub usage: EmbeddingUsage
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Embedding {
+	pub index: usize,
+	pub embedding: Vec<f32>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+	pub prompt_tokens: u64,
+	pub total_tokens: u64
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TokenUsage {
 	pub prompt_tokens: u64,
@@ -76,7 +164,56 @@ pub struct ResponseChoice {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Message {
 	pub role: MessageRole,
-	pub content: String
+	pub content: MessageContent
+}
+
+/// Either plain text or a list of parts for vision-capable models.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+	Text(String),
+	Parts(Vec<ContentPart>)
+}
+
+impl MessageContent {
+	/// Returns the text of the message, joining text parts and skipping images.
+	pub fn text(&self) -> String {
+		match self {
+			Self::Text(text) => text.clone(),
+			Self::Parts(parts) => parts
+				.iter()
+				.filter_map(|part| match part {
+					ContentPart::Text { text } => Some(text.as_str()),
+					ContentPart::ImageUrl { .. } => None
+				})
+				.collect::<Vec<&str>>()
+				.join("\n\n")
+		}
+	}
+}
+
+impl From<String> for MessageContent {
+	fn from(value: String) -> Self {
+		Self::Text(value)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum ContentPart {
+	#[serde(rename = "text")]
+	Text { text: String },
+
+	#[serde(rename = "image_url")]
+	ImageUrl { image_url: ImageUrl }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ImageUrl {
+	pub url: String,
+
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub detail: Option<String>
 }
 
 #[derive(Deserialize, Clone)]
@@ -102,3 +239,127 @@ impl Serialize for MessageRole {
 		}
 	}
 }
+
+/// Usage of one request. Embedding requests belong to no conversation.
+pub struct UsageRecord {
+	pub conversation_id: Option<u32>,
+	pub title: Option<String>,
+	pub key: String,
+	pub model: Option<String>,
+	pub prompt_tokens: u64,
+	pub completion_tokens: u64,
+	pub latency_ms: Option<u64>,
+	pub updateat: DateTime<Utc>
+}
+
+pub struct ErrorRecord {
+	pub key: String,
+	pub model: Option<String>,
+	pub conversation_id: Option<u32>,
+	pub title: Option<String>,
+	pub updateat: DateTime<Utc>
+}
+
+pub struct ErrorLog {
+	pub id: u32,
+	pub key: String,
+	pub model: Option<String>,
+	pub conversation_id: Option<u32>,
+	pub prompt: Option<String>,
+	pub context: Vec<Message>,
+	pub error: Option<String>,
+	pub message: Option<String>,
+	pub code: Option<String>,
+	pub r#type: Option<String>,
+	pub param: Option<String>,
+	pub retried_at: Option<DateTime<Utc>>,
+	pub updateat: DateTime<Utc>
+}
+
+pub struct ErrorSummary {
+	pub code: Option<String>,
+	pub r#type: Option<String>,
+	pub count: u64,
+	pub lastupdate: DateTime<Utc>
+}
+
+/// A summary of the messages `from_seq..=to_seq` of a conversation that no longer fit in the context.
+pub struct Summary {
+	pub id: u32,
+	pub conversation_id: u32,
+	pub from_seq: u64,
+	pub to_seq: u64,
+	pub content: String,
+	pub updateat: DateTime<Utc>
+}
+
+/// A stored message together with its embedding vector, used for semantic search.
+pub struct EmbeddedMessage {
+	pub message_id: u32,
+	pub conversation_id: u32,
+	pub title: String,
+	pub role: String,
+	pub content: String,
+	pub vector: Vec<f32>
+}
+
+/// Settings a conversation was created with. Unset values fall back to the command line defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConversationSettings {
+	pub model: Option<String>,
+	pub params: CompletionParams,
+	pub context_strategy: Option<String>,
+	pub max_token: Option<u64>,
+	pub max_dialog: Option<u64>,
+	pub system_prompt: Option<String>,
+	pub persona: Option<String>
+}
+
+/// An earlier content of a message, kept when the message is edited. Revisions are numbered from 1,
+/// `updateat` is when the revision was replaced.
+pub struct MessageRevision {
+	pub message_id: u32,
+	pub revision: u32,
+	pub content: String,
+	pub updateat: DateTime<Utc>
+}
+
+/// A file whose content was included in a user message with an `@path` reference. `path` is absolute.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IncludedFile {
+	pub path: String,
+	pub sha256: String,
+	pub size: u64
+}
+
+/// A command run with `!command` whose output was sent in a user message. `exit_code` is `None` when the
+/// command timed out or was terminated by a signal, `omitted_lines` is how many lines of output were left out.
+#[derive(Clone)]
+pub struct CommandRun {
+	pub command: String,
+	pub exit_code: Option<i32>,
+	pub timed_out: bool,
+	pub duration_ms: u64,
+	pub omitted_lines: usize
+}
+
+/// A change to a local file applied from a reply with /apply. `path` is absolute, `action` is `modify`,
+/// `create` or `delete`.
+pub struct AppliedPatch {
+	pub path: String,
+	pub action: String,
+	pub hunks: usize,
+	pub fuzzy_hunks: usize,
+	pub sha256_before: Option<String>,
+	pub sha256_after: Option<String>
+}
+
+/// A spending limit for a profile (`key`) or a single conversation, in `unit` ("tokens" or "usd") per `period` ("day" or "month").
+pub struct Budget {
+	pub id: u32,
+	pub key: Option<String>,
+	pub conversation_id: Option<u32>,
+	pub period: String,
+	pub unit: String,
+	pub amount: f64
+}